@@ -0,0 +1,674 @@
+use crate::opcodes::{self, AddrMode, Mnemonic};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A line the assembler couldn't make sense of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    /// 1 based, `None` for problems with the program as a whole
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {line}: {}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Machine code and the address it starts at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub origin: u16,
+    /// gaps left by `.org` are filled with zeros
+    pub bytes: Vec<u8>,
+}
+
+/// assembles 6502 source into a contiguous block of bytes
+///
+/// ```text
+/// PPUCTRL = $2000       ; constants
+///         .org $8000    ; where the following code goes, 0 by default
+/// reset:  lda #<table   ; labels, < and > take the low and high byte
+///         sta PPUCTRL
+///         jmp (vector)
+/// table:  .byte 1, $02, %11, "text\n"
+///         .word reset, table+2
+/// ```
+///
+/// numbers are decimal, `$` hex or `%` binary, and `*` is the address of the current line
+pub fn assemble(source: &str) -> Result<Program, Error> {
+    let mut symbols = HashMap::new();
+    let mut statements = vec![];
+    let mut pc: u32 = 0;
+
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let error = |message: String| Error {
+            line: Some(line),
+            message,
+        };
+        let tokens = tokenize(text).map_err(error)?;
+        let parsed = parse_line(&tokens, &symbols, pc as u16).map_err(error)?;
+
+        if let Some(label) = parsed.label {
+            define(&mut symbols, label, pc as i64).map_err(error)?;
+        }
+        match parsed.statement {
+            None => (),
+            Some(Statement::Constant(name, value)) => {
+                define(&mut symbols, name, value).map_err(error)?
+            }
+            Some(Statement::Org(addr)) => pc = addr as u32,
+            Some(statement) => {
+                let size = statement.size();
+                statements.push((line, pc as u16, statement));
+                pc += size;
+                if pc > 0x10000 {
+                    return Err(error("the program runs past $FFFF".to_string()));
+                }
+            }
+        }
+    }
+
+    let mut program = Program {
+        origin: statements.first().map_or(0, |(_, pc, _)| *pc),
+        bytes: vec![],
+    };
+    for (line, pc, statement) in statements {
+        let error = |message: String| Error {
+            line: Some(line),
+            message,
+        };
+        let offset = (pc as usize)
+            .checked_sub(program.origin as usize)
+            .filter(|offset| *offset >= program.bytes.len())
+            .ok_or_else(|| error(format!(".org ${pc:04X} overlaps code before it")))?;
+        program.bytes.resize(offset, 0);
+        statement
+            .emit(&symbols, pc, &mut program.bytes)
+            .map_err(error)?;
+    }
+    Ok(program)
+}
+
+/// assembles a mapper 0 iNES image, the source has to fill in the vectors at $FFFA itself
+///
+/// the program is laid out in 32 KiB of PRG ROM at 0x8000 - 0xffff, with an empty CHR bank
+pub fn assemble_ines(source: &str) -> Result<Vec<u8>, Error> {
+    let program = assemble(source)?;
+    if program.origin < 0x8000 {
+        return Err(Error {
+            line: None,
+            message: format!("PRG ROM starts at $8000, not ${:04X}", program.origin),
+        });
+    }
+
+    let mut image = vec![0x4e, 0x45, 0x53, 0x1a, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    let mut prg = vec![0; 0x8000];
+    let start = program.origin as usize - 0x8000;
+    prg[start..start + program.bytes.len()].copy_from_slice(&program.bytes);
+    image.extend(prg);
+    image.extend([0; 0x2000]);
+    Ok(image)
+}
+
+fn define(symbols: &mut HashMap<String, i64>, name: String, value: i64) -> Result<(), String> {
+    if symbols.insert(name.clone(), value).is_some() {
+        return Err(format!("{name} is defined twice"));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    Str(Vec<u8>),
+    Punct(char),
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            ';' => break,
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '"' => {
+                chars.next();
+                let mut bytes = vec![];
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => bytes.push(match chars.next() {
+                            Some('n') => b'\n',
+                            Some('0') => 0,
+                            Some(c @ ('\\' | '"')) => c as u8,
+                            _ => return Err("unknown escape in string".to_string()),
+                        }),
+                        Some(c) if c.is_ascii() => bytes.push(c as u8),
+                        Some(_) => return Err("strings can only hold ASCII".to_string()),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                tokens.push(Token::Str(bytes));
+            }
+            '$' | '%' | '0'..='9' => {
+                let radix = match c {
+                    '$' => 16,
+                    '%' => 2,
+                    _ => 10,
+                };
+                if radix != 10 {
+                    chars.next();
+                }
+                let mut digits = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric()) {
+                    digits.push(c);
+                    chars.next();
+                }
+                let number = i64::from_str_radix(&digits, radix)
+                    .map_err(|_| format!("bad number {c}{digits}"))?;
+                tokens.push(Token::Number(number));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' || c == '.' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars
+                    .peek()
+                    .filter(|c| c.is_ascii_alphanumeric() || **c == '_' || **c == '.')
+                {
+                    ident.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            '#' | '(' | ')' | ',' | ':' | '+' | '-' | '<' | '>' | '=' | '*' => {
+                tokens.push(Token::Punct(c));
+                chars.next();
+            }
+            c => return Err(format!("unexpected {c}")),
+        }
+    }
+    Ok(tokens)
+}
+
+/// `<expr` or `>expr`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Half {
+    Low,
+    High,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Number(i64),
+    Label(String),
+    /// `*`
+    Here,
+}
+
+/// a sum of terms, optionally cut down to one of its bytes
+#[derive(Debug, Clone, PartialEq)]
+struct Expr {
+    half: Option<Half>,
+    /// each term with its sign
+    terms: Vec<(i64, Term)>,
+}
+
+impl Expr {
+    /// `None` while a label it uses is still undefined
+    fn eval(&self, symbols: &HashMap<String, i64>, pc: u16) -> Option<i64> {
+        let mut value = 0;
+        for (sign, term) in &self.terms {
+            value += sign
+                * match term {
+                    Term::Number(number) => *number,
+                    Term::Label(label) => *symbols.get(label)?,
+                    Term::Here => pc as i64,
+                };
+        }
+        Some(match self.half {
+            None => value,
+            Some(Half::Low) => value & 0xff,
+            Some(Half::High) => (value >> 8) & 0xff,
+        })
+    }
+
+    fn resolve(&self, symbols: &HashMap<String, i64>, pc: u16) -> Result<i64, String> {
+        self.eval(symbols, pc).ok_or_else(|| {
+            let unknown = self.terms.iter().find_map(|(_, term)| match term {
+                Term::Label(label) if !symbols.contains_key(label) => Some(label.as_str()),
+                _ => None,
+            });
+            format!("unknown label {}", unknown.unwrap_or_default())
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    Expr(Expr),
+    Str(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Statement {
+    Instruction {
+        opcode: u8,
+        mode: AddrMode,
+        operand: Option<Expr>,
+    },
+    Bytes(Vec<Item>),
+    Words(Vec<Expr>),
+    Org(u16),
+    Constant(String, i64),
+}
+
+impl Statement {
+    fn size(&self) -> u32 {
+        match self {
+            Statement::Instruction { mode, .. } => mode.size() as u32,
+            Statement::Bytes(items) => items
+                .iter()
+                .map(|item| match item {
+                    Item::Expr(_) => 1,
+                    Item::Str(bytes) => bytes.len() as u32,
+                })
+                .sum(),
+            Statement::Words(words) => 2 * words.len() as u32,
+            Statement::Org(_) | Statement::Constant(..) => 0,
+        }
+    }
+
+    fn emit(
+        &self,
+        symbols: &HashMap<String, i64>,
+        pc: u16,
+        out: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        let byte = |value: i64| {
+            u8::try_from(value)
+                .or_else(|_| i8::try_from(value).map(|value| value as u8))
+                .map_err(|_| format!("{value} doesn't fit in a byte"))
+        };
+        let word =
+            |value: i64| u16::try_from(value).map_err(|_| format!("{value} doesn't fit in a word"));
+
+        match self {
+            Statement::Instruction {
+                opcode,
+                mode,
+                operand,
+            } => {
+                out.push(*opcode);
+                let Some(operand) = operand else {
+                    return Ok(());
+                };
+                let value = operand.resolve(symbols, pc)?;
+                if *mode == AddrMode::Relative {
+                    let offset = value - (pc as i64 + 2);
+                    let offset = i8::try_from(offset)
+                        .map_err(|_| format!("branch target is {offset} bytes away"))?;
+                    out.push(offset as u8);
+                } else if mode.size() == 2 {
+                    out.push(byte(value)?);
+                } else {
+                    out.extend(word(value)?.to_le_bytes());
+                }
+            }
+            Statement::Bytes(items) => {
+                for item in items {
+                    match item {
+                        Item::Expr(expr) => out.push(byte(expr.resolve(symbols, pc)?)?),
+                        Item::Str(bytes) => out.extend(bytes),
+                    }
+                }
+            }
+            Statement::Words(words) => {
+                for expr in words {
+                    out.extend(word(expr.resolve(symbols, pc)?)?.to_le_bytes());
+                }
+            }
+            Statement::Org(_) | Statement::Constant(..) => (),
+        }
+        Ok(())
+    }
+}
+
+struct Line {
+    label: Option<String>,
+    statement: Option<Statement>,
+}
+
+/// walks through a line's tokens
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        self.pos += 1;
+        self.tokens.get(self.pos - 1)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(&Token::Punct(c));
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(format!("expected {c}"))
+        }
+    }
+
+    fn done(&self) -> Result<(), String> {
+        match self.peek() {
+            None => Ok(()),
+            Some(token) => Err(format!("unexpected {token:?}")),
+        }
+    }
+
+    /// eats `,X` or `,Y` if it's there
+    fn index(&mut self) -> Result<Option<char>, String> {
+        if !self.eat(',') {
+            return Ok(None);
+        }
+        match self.next() {
+            Some(Token::Ident(reg)) if reg.eq_ignore_ascii_case("x") => Ok(Some('X')),
+            Some(Token::Ident(reg)) if reg.eq_ignore_ascii_case("y") => Ok(Some('Y')),
+            _ => Err("expected X or Y after ,".to_string()),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let half = if self.eat('<') {
+            Some(Half::Low)
+        } else if self.eat('>') {
+            Some(Half::High)
+        } else {
+            None
+        };
+
+        let mut terms = vec![];
+        let mut sign = if self.eat('-') { -1 } else { 1 };
+        loop {
+            let term = match self.next() {
+                Some(Token::Number(number)) => Term::Number(*number),
+                Some(Token::Ident(label)) => Term::Label(label.clone()),
+                Some(Token::Punct('*')) => Term::Here,
+                _ => return Err("expected a number or a label".to_string()),
+            };
+            terms.push((sign, term));
+
+            sign = if self.eat('+') {
+                1
+            } else if self.eat('-') {
+                -1
+            } else {
+                return Ok(Expr { half, terms });
+            };
+        }
+    }
+}
+
+fn parse_line(tokens: &[Token], symbols: &HashMap<String, i64>, pc: u16) -> Result<Line, String> {
+    let mut parser = Parser { tokens, pos: 0 };
+    let mut line = Line {
+        label: None,
+        statement: None,
+    };
+
+    if let [Token::Ident(name), Token::Punct(':'), ..] = tokens {
+        line.label = Some(name.clone());
+        parser.pos = 2;
+    }
+    let word = match parser.next() {
+        None => return Ok(line),
+        Some(Token::Ident(word)) => word.clone(),
+        Some(_) => return Err("expected an instruction or directive".to_string()),
+    };
+
+    let statement = if parser.eat('=') {
+        let value = parser
+            .expr()?
+            .eval(symbols, pc)
+            .ok_or("constants can only use labels defined above them")?;
+        Statement::Constant(word, value)
+    } else if let Some(directive) = word.strip_prefix('.') {
+        match directive.to_lowercase().as_str() {
+            "org" => {
+                let value = parser
+                    .expr()?
+                    .eval(symbols, pc)
+                    .ok_or(".org can only use labels defined above it")?;
+                Statement::Org(
+                    u16::try_from(value).map_err(|_| format!(".org {value} is out of range"))?,
+                )
+            }
+            "byte" | "db" => {
+                let mut items = vec![];
+                loop {
+                    items.push(match parser.peek() {
+                        Some(Token::Str(bytes)) => {
+                            let bytes = bytes.clone();
+                            parser.pos += 1;
+                            Item::Str(bytes)
+                        }
+                        _ => Item::Expr(parser.expr()?),
+                    });
+                    if !parser.eat(',') {
+                        break;
+                    }
+                }
+                Statement::Bytes(items)
+            }
+            "word" | "dw" => {
+                let mut words = vec![parser.expr()?];
+                while parser.eat(',') {
+                    words.push(parser.expr()?);
+                }
+                Statement::Words(words)
+            }
+            _ => return Err(format!("unknown directive {word}")),
+        }
+    } else {
+        instruction(&mut parser, &word, symbols, pc)?
+    };
+
+    parser.done()?;
+    line.statement = Some(statement);
+    Ok(line)
+}
+
+fn instruction(
+    parser: &mut Parser,
+    name: &str,
+    symbols: &HashMap<String, i64>,
+    pc: u16,
+) -> Result<Statement, String> {
+    let mnemonic = (0..=255u8)
+        .map(|opcode| opcodes::decode(opcode).mnemonic)
+        .find(|mnemonic| mnemonic.to_string().eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("unknown instruction {name}"))?;
+
+    let (candidates, operand): (&[AddrMode], _) = match parser.peek() {
+        None => (&[AddrMode::Implied, AddrMode::Accumulator], None),
+        Some(Token::Ident(reg))
+            if reg.eq_ignore_ascii_case("a") && parser.tokens.len() == parser.pos + 1 =>
+        {
+            parser.pos += 1;
+            (&[AddrMode::Accumulator], None)
+        }
+        Some(Token::Punct('#')) => {
+            parser.pos += 1;
+            (&[AddrMode::Immediate], Some(parser.expr()?))
+        }
+        Some(Token::Punct('(')) => {
+            parser.pos += 1;
+            let expr = parser.expr()?;
+            if parser.index()? == Some('X') {
+                parser.expect(')')?;
+                (&[AddrMode::IndirectX], Some(expr))
+            } else {
+                parser.expect(')')?;
+                match parser.index()? {
+                    None => (&[AddrMode::Indirect], Some(expr)),
+                    Some('Y') => (&[AddrMode::IndirectY], Some(expr)),
+                    Some(_) => return Err("only ($44),Y can be indexed".to_string()),
+                }
+            }
+        }
+        Some(_) => {
+            let expr = parser.expr()?;
+            // zero page is only picked when the value is already known to fit,
+            // forward references always take the absolute form so sizes don't change
+            let zero_page = expr
+                .eval(symbols, pc)
+                .is_some_and(|value| (0..=0xff).contains(&value));
+            let modes: &[AddrMode] = match (parser.index()?, zero_page) {
+                (None, true) => &[AddrMode::Relative, AddrMode::ZeroPage, AddrMode::Absolute],
+                (None, false) => &[AddrMode::Relative, AddrMode::Absolute],
+                (Some('X'), true) => &[AddrMode::ZeroPageX, AddrMode::AbsoluteX],
+                (Some('X'), false) => &[AddrMode::AbsoluteX],
+                (_, true) => &[AddrMode::ZeroPageY, AddrMode::AbsoluteY],
+                (_, false) => &[AddrMode::AbsoluteY],
+            };
+            (modes, Some(expr))
+        }
+    };
+
+    candidates
+        .iter()
+        .find_map(|&mode| {
+            find_opcode(mnemonic, mode).map(|opcode| Statement::Instruction {
+                opcode,
+                mode,
+                operand: operand.clone(),
+            })
+        })
+        .ok_or_else(|| format!("{mnemonic} can't be used with that addressing mode"))
+}
+
+/// the opcode for an instruction, preferring the official one when there are several
+fn find_opcode(mnemonic: Mnemonic, mode: AddrMode) -> Option<u8> {
+    let matches = |opcode: &u8| {
+        let op = opcodes::decode(*opcode);
+        op.mnemonic == mnemonic && op.mode == mode
+    };
+    let official = (0..=255u8).find(|opcode| matches(opcode) && opcodes::decode(*opcode).official);
+    official.or_else(|| (0..=255u8).find(matches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        assemble(source).unwrap().bytes
+    }
+
+    #[test]
+    fn addressing_modes() {
+        let source = "
+            nop
+            asl a
+            asl
+            lda #$44
+            lda $44
+            lda $44,x
+            ldx $44,y
+            lda $4400
+            lda $4400,X
+            lda $4400,Y
+            jmp ($5597)
+            lda ($44,x)
+            lda ($44),y
+            bne *
+        ";
+        assert!(
+            bytes(source)
+                == [
+                    0xea, 0x0a, 0x0a, 0xa9, 0x44, 0xa5, 0x44, 0xb5, 0x44, 0xb6, 0x44, 0xad, 0x00,
+                    0x44, 0xbd, 0x00, 0x44, 0xb9, 0x00, 0x44, 0x6c, 0x97, 0x55, 0xa1, 0x44, 0xb1,
+                    0x44, 0xd0, 0xfe,
+                ]
+        );
+    }
+
+    #[test]
+    fn labels_and_expressions() {
+        let source = "
+            ZP = $10
+                    .org $c000
+            start:  lda #<data      ; forward references take the absolute form
+                    ldx data+1
+                    sta ZP
+                    beq start
+                    jmp back
+            data:   .byte 1, -1, %101, \"hi\\n\"
+            back:   .word start, >data, *
+        ";
+        let program = assemble(source).unwrap();
+        assert!(program.origin == 0xc000);
+        assert!(
+            program.bytes
+                == [
+                    0xa9, 0x0c, 0xae, 0x0d, 0xc0, 0x85, 0x10, 0xf0, 0xf7, 0x4c, 0x12, 0xc0, 0x01,
+                    0xff, 0x05, b'h', b'i', b'\n', 0x00, 0xc0, 0xc0, 0x00, 0x12, 0xc0,
+                ]
+        );
+    }
+
+    #[test]
+    fn unofficial_opcodes() {
+        assert!(bytes("lax $44\nnop $44\nsbc #1") == [0xa7, 0x44, 0x04, 0x44, 0xe9, 0x01]);
+    }
+
+    #[test]
+    fn org_pads_with_zeros() {
+        assert!(bytes(".org 2\nnop\n.org 5\nnop") == [0xea, 0, 0, 0xea]);
+        assert!(assemble(".org 5\nnop\n.org 2\nnop").unwrap_err().line == Some(4));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source: &str| assemble(source).unwrap_err().to_string();
+        assert!(error("lda missing") == "line 1: unknown label missing");
+        assert!(error("foo") == "line 1: unknown instruction foo");
+        assert!(error("jmp $44,x") == "line 1: JMP can't be used with that addressing mode");
+        assert!(error("a: nop\na: nop") == "line 2: a is defined twice");
+        assert!(error(".org $200\nbne $400") == "line 2: branch target is 510 bytes away");
+        assert!(error(".byte 256") == "line 1: 256 doesn't fit in a byte");
+        assert!(error("lda #1 2") == "line 1: unexpected Number(2)");
+    }
+
+    #[test]
+    fn ines_images() {
+        let image = assemble_ines(".org $fffa\n.word $8000, $8001, $8002").unwrap();
+        assert!(image.len() == 16 + 0x8000 + 0x2000);
+        assert!(image[16 + 0x7ffc] == 0x01 && image[16 + 0x7ffd] == 0x80);
+
+        let rom = rune_ines::InesFile::from_bytes(&image);
+        assert!(rom.header.get_mapper() == 0);
+        assert!(rom.prg_rom.len() == 0x8000);
+
+        assert!(assemble_ines("nop").unwrap_err().line.is_none());
+    }
+}
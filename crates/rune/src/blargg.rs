@@ -0,0 +1,126 @@
+use crate::cpu::CPU;
+use crate::scheduler::{Region, Scheduler, Throttle};
+use std::fmt;
+use std::time::Duration;
+
+/// Bytes at 0x6001 - 0x6003 that tell the status at 0x6000 is valid
+pub const SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+
+/// Where a test ROM following blargg's protocol is at
+///
+/// https://www.nesdev.org/wiki/Emulator_tests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// the signature hasn't been written yet
+    Unknown,
+    Running,
+    /// the reset button has to be pressed, no sooner than 100 ms from now
+    NeedsReset,
+    /// the result code, 0 means every test passed
+    Done(u8),
+}
+
+impl Status {
+    pub fn read(cpu: &CPU) -> Status {
+        if (0..3).any(|i| cpu.bus.peek(0x6001 + i) != SIGNATURE[i as usize]) {
+            return Status::Unknown;
+        }
+        match cpu.bus.peek(0x6000) {
+            0x80 => Status::Running,
+            0x81 => Status::NeedsReset,
+            code => Status::Done(code),
+        }
+    }
+}
+
+/// What a finished test ROM reported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub code: u8,
+    /// the NUL terminated text at 0x6004
+    pub text: String,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        self.code == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// still running after this many frames
+    Timeout(u64),
+    /// the CPU ran into a KIL opcode
+    Halted,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Timeout(frames) => write!(f, "the test didn't finish in {frames} frames"),
+            Error::Halted => write!(f, "the CPU ran into a KIL opcode and locked up"),
+        }
+    }
+}
+
+/// runs a powered on test ROM as fast as possible until it reports a result
+pub fn run(cpu: &mut CPU, max_frames: u64) -> Result<Outcome, Error> {
+    let region = cpu
+        .bus
+        .cartridge()
+        .map_or(Region::default(), |cartridge| cartridge.region());
+    let mut scheduler = Scheduler::new(region, Throttle::Unthrottled);
+    let reset_delay = frames_in(region, Duration::from_millis(100));
+    let mut reset_at = None;
+
+    while scheduler.frames() < max_frames {
+        scheduler.run_frame(cpu);
+        if cpu.halted() {
+            return Err(Error::Halted);
+        }
+
+        match Status::read(cpu) {
+            Status::Unknown | Status::Running => reset_at = None,
+            Status::NeedsReset => {
+                let at = *reset_at.get_or_insert(scheduler.frames() + reset_delay);
+                if scheduler.frames() >= at {
+                    cpu.reset();
+                    reset_at = None;
+                }
+            }
+            Status::Done(code) => {
+                return Ok(Outcome {
+                    code,
+                    text: text(cpu),
+                })
+            }
+        }
+    }
+
+    Err(Error::Timeout(max_frames))
+}
+
+/// whole frames needed for `duration` to go by
+fn frames_in(region: Region, duration: Duration) -> u64 {
+    (duration.as_secs_f64() / region.frame_duration().as_secs_f64()).ceil() as u64
+}
+
+fn text(cpu: &CPU) -> String {
+    let bytes: Vec<u8> = (0x6004..=0x7fff)
+        .map(|addr| cpu.bus.peek(addr))
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reset_delay() {
+        assert!(frames_in(Region::Ntsc, Duration::from_millis(100)) == 7);
+        assert!(frames_in(Region::Pal, Duration::from_millis(100)) == 6);
+    }
+}
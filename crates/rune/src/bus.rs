@@ -0,0 +1,604 @@
+use crate::cartridge::Cartridge;
+use crate::mapper::Mapper;
+use crate::mmap;
+use crate::scheduler::Region;
+
+/// A component that sits behind the CPU bus (PPU, APU, cartridge...)
+pub trait BusDevice: Send {
+    /// `addr` is the CPU address after mirroring has been resolved
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, val: u8);
+
+    /// what `read` would return, without any of its side effects
+    fn peek(&self, _addr: u16) -> u8 {
+        0
+    }
+
+    /// bits the device doesn't drive when `addr` is read, they float to open bus
+    ///
+    /// asked right before the read, e.g. PPUSTATUS only drives its top 3 bits
+    fn open_bus_mask(&self, _addr: u16) -> u8 {
+        0
+    }
+
+    /// advances the device by one cycle of its own clock,
+    /// a dot for the PPU and a CPU cycle for everything else
+    fn tick(&mut self) {}
+
+    /// an address the device wants DMA to fetch for it, the APU's DMC uses this for samples
+    fn dma_request(&self) -> Option<u16> {
+        None
+    }
+
+    /// hands over the byte fetched for `dma_request`, the request should be dropped by now
+    fn dma_complete(&mut self, _val: u8) {}
+}
+
+/// What sits at 0x2000 - 0x2007, the PPU also sees the cartridge for its pattern tables
+/// and nametable mirroring
+///
+/// any `BusDevice` can stand in for it, it just never looks at the cartridge
+pub trait PpuDevice: Send {
+    fn read(&mut self, addr: u16, mapper: Option<&mut dyn Mapper>) -> u8;
+    fn write(&mut self, addr: u16, val: u8, mapper: Option<&mut dyn Mapper>);
+    fn peek(&self, addr: u16) -> u8;
+    fn open_bus_mask(&self, addr: u16) -> u8;
+    /// advances the PPU by one dot
+    fn tick(&mut self, mapper: Option<&mut dyn Mapper>);
+
+    /// whether the PPU is pulling the CPU's NMI line
+    fn nmi(&self) -> bool {
+        false
+    }
+
+    /// the picture drawn so far, one byte per pixel
+    fn framebuffer(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// where the PPU is in the frame as (scanline, dot), `None` if it doesn't keep track
+    fn position(&self) -> Option<(u16, u16)> {
+        None
+    }
+}
+
+impl<T: BusDevice> PpuDevice for T {
+    fn read(&mut self, addr: u16, _mapper: Option<&mut dyn Mapper>) -> u8 {
+        BusDevice::read(self, addr)
+    }
+
+    fn write(&mut self, addr: u16, val: u8, _mapper: Option<&mut dyn Mapper>) {
+        BusDevice::write(self, addr, val);
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        BusDevice::peek(self, addr)
+    }
+
+    fn open_bus_mask(&self, addr: u16) -> u8 {
+        BusDevice::open_bus_mask(self, addr)
+    }
+
+    fn tick(&mut self, _mapper: Option<&mut dyn Mapper>) {
+        BusDevice::tick(self);
+    }
+}
+
+/// A CPU access seen on the bus, mirrors already folded into their canonical address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub addr: u16,
+    pub val: u8,
+    pub write: bool,
+}
+
+/// A latch held in capacitance that forgets each bit once it goes without a refresh
+///
+/// the PPU's I/O latch is one, its bits fade to 0 after about 600 ms
+#[derive(Debug, Clone, Copy, Default)]
+struct DecayingLatch {
+    value: u8,
+    /// master clock each bit was last driven at
+    refreshed: [u64; 8],
+}
+
+impl DecayingLatch {
+    /// seconds a bit lasts without being refreshed
+    const DECAY: f64 = 0.6;
+
+    /// latches the `bits` of `val`, leaving the others to keep decaying
+    fn refresh(&mut self, val: u8, bits: u8, now: u64) {
+        for bit in 0..8 {
+            if bits & 1 << bit != 0 {
+                self.refreshed[bit] = now;
+            }
+        }
+        self.value = self.value & !bits | val & bits;
+    }
+
+    fn value(&self, now: u64, region: Region) -> u8 {
+        let decay = (region.master_clock_hz() * Self::DECAY) as u64;
+        (0..8)
+            .filter(|&bit| now - self.refreshed[bit] < decay)
+            .fold(0, |value, bit| value | self.value & 1 << bit)
+    }
+}
+
+/// Routes every CPU memory access to the component mapped at that address
+pub struct Bus {
+    /// 0x0 - 0x7ff and mirrors from 0x800 to 0x1fff
+    ram: [u8; 2048],
+    /// 0x2000 - 0x2007 and mirrors from 0x2008 to 0x3fff
+    ppu: Option<Box<dyn PpuDevice>>,
+    /// 0x4000 - 0x4017
+    apu_io: Option<Box<dyn BusDevice>>,
+    /// 0x4020 - 0xffff
+    cartridge: Option<Cartridge>,
+    /// CPU cycles the bus has been clocked for
+    cycles: u64,
+    region: Region,
+    /// master clocks elapsed since power on
+    master_clock: u64,
+    /// master clock the PPU has been caught up to
+    ppu_clock: u64,
+    /// accesses since they were last taken, only kept while a debugger asks for them
+    accesses: Option<Vec<Access>>,
+    /// page written to $4014, waiting for the CPU to halt for the copy
+    oam_dma: Option<u8>,
+    /// the last value on the CPU data bus, what reads with nothing behind them see
+    open_bus: u8,
+    /// the last value read from or written to the PPU's registers
+    ppu_latch: DecayingLatch,
+    /// the level of the PPU's NMI output when it was last looked at
+    nmi_line: bool,
+    /// the PPU's NMI output went up and the CPU hasn't been told yet
+    nmi_edge: bool,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Bus {
+            ram: [0; 2048],
+            ppu: None,
+            apu_io: None,
+            cartridge: None,
+            cycles: 0,
+            region: Region::default(),
+            master_clock: 0,
+            ppu_clock: 0,
+            accesses: None,
+            oam_dma: None,
+            open_bus: 0,
+            ppu_latch: DecayingLatch::default(),
+            nmi_line: false,
+            nmi_edge: false,
+        }
+    }
+}
+
+impl Bus {
+    pub fn attach_ppu(&mut self, ppu: Box<dyn PpuDevice>) {
+        self.ppu = Some(ppu);
+    }
+
+    pub fn attach_apu_io(&mut self, apu_io: Box<dyn BusDevice>) {
+        self.apu_io = Some(apu_io);
+    }
+
+    pub fn attach_cartridge(&mut self, cartridge: Cartridge) {
+        self.cartridge = Some(cartridge);
+    }
+
+    pub fn cartridge(&mut self) -> Option<&mut Cartridge> {
+        self.cartridge.as_mut()
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// clocks every attached device for the length of one CPU cycle
+    ///
+    /// the PPU runs on its own divider, so it gets 3 dots per cycle on NTSC and 3.2 on PAL
+    pub fn tick(&mut self) {
+        self.cycles += 1;
+        self.master_clock += self.region.cpu_divider();
+
+        let ppu_divider = self.region.ppu_divider();
+        while self.ppu_clock + ppu_divider <= self.master_clock {
+            self.ppu_clock += ppu_divider;
+            if let Some(ref mut ppu) = self.ppu {
+                ppu.tick(self.cartridge.as_mut().map(|cartridge| cartridge.mapper()));
+            }
+            self.poll_nmi();
+        }
+        if let Some(ref mut apu_io) = self.apu_io {
+            apu_io.tick();
+        }
+        if let Some(ref mut cartridge) = self.cartridge {
+            cartridge.mapper().cpu_tick();
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// starts or stops keeping a log of reads and writes
+    pub fn record_accesses(&mut self, record: bool) {
+        self.accesses = record.then(Vec::new);
+    }
+
+    /// the accesses recorded since the last call
+    pub fn take_accesses(&mut self) -> Vec<Access> {
+        self.accesses
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// what the PPU has drawn, `None` if there's no PPU or it doesn't draw
+    pub fn framebuffer(&self) -> Option<&[u8]> {
+        self.ppu.as_ref().and_then(|ppu| ppu.framebuffer())
+    }
+
+    /// whether the PPU raised an NMI since the last call
+    pub fn take_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_edge)
+    }
+
+    /// NMI is edge triggered, so only the PPU's output going up counts
+    fn poll_nmi(&mut self) {
+        let line = self.ppu.as_ref().is_some_and(|ppu| ppu.nmi());
+        if line && !self.nmi_line {
+            self.nmi_edge = true;
+        }
+        self.nmi_line = line;
+    }
+
+    /// the page an OAM DMA should copy, if one was started since the last call
+    pub fn take_oam_dma(&mut self) -> Option<u8> {
+        self.oam_dma.take()
+    }
+
+    /// the address the DMC is waiting for a sample byte from
+    pub fn dmc_dma_request(&self) -> Option<u16> {
+        self.apu_io.as_ref().and_then(|apu_io| apu_io.dma_request())
+    }
+
+    pub fn complete_dmc_dma(&mut self, val: u8) {
+        if let Some(ref mut apu_io) = self.apu_io {
+            apu_io.dma_complete(val);
+        }
+    }
+
+    pub fn master_clock(&self) -> u64 {
+        self.master_clock
+    }
+
+    /// folds mirrored addresses into their canonical address
+    fn decode(addr: u16) -> u16 {
+        match addr as usize {
+            mmap::ram::START..=mmap::ram::MIRRORS_END => addr & mmap::ram::END as u16,
+            mmap::ppu::START..=mmap::ppu::MIRRORS_END => mmap::ppu::START as u16 | (addr & 0b0111),
+            _ => addr,
+        }
+    }
+
+    pub fn read(&mut self, addr: u16) -> u8 {
+        let addr = Self::decode(addr);
+        let val = match addr as usize {
+            mmap::ram::START..=mmap::ram::END => self.ram[addr as usize],
+            mmap::ppu::START..=mmap::ppu::END => match self.ppu {
+                Some(ref mut ppu) => {
+                    let floating = ppu.open_bus_mask(addr);
+                    let mapper = self.cartridge.as_mut().map(|cartridge| cartridge.mapper());
+                    let val = ppu.read(addr, mapper);
+                    self.ppu_latch.refresh(val, !floating, self.master_clock);
+                    val & !floating
+                        | self.ppu_latch.value(self.master_clock, self.region) & floating
+                }
+                None => self.open_bus,
+            },
+            mmap::apu_io_registers::START..=mmap::apu_io_registers::END => match self.apu_io {
+                Some(ref mut apu_io) => {
+                    let floating = apu_io.open_bus_mask(addr);
+                    apu_io.read(addr) & !floating | self.open_bus & floating
+                }
+                None => self.open_bus,
+            },
+            mmap::cartrige::START..=mmap::cartrige::END => self
+                .cartridge
+                .as_mut()
+                .and_then(|cartridge| cartridge.mapper().cpu_read(addr))
+                .unwrap_or(self.open_bus),
+            // 0x4018 - 0x401f is only enabled in CPU test mode
+            _ => self.open_bus,
+        };
+        self.open_bus = val;
+        if let Some(ref mut accesses) = self.accesses {
+            accesses.push(Access {
+                addr,
+                val,
+                write: false,
+            });
+        }
+        val
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        let addr = Self::decode(addr);
+        self.open_bus = val;
+        if let Some(ref mut accesses) = self.accesses {
+            accesses.push(Access {
+                addr,
+                val,
+                write: true,
+            });
+        }
+        match addr as usize {
+            mmap::ram::START..=mmap::ram::END => self.ram[addr as usize] = val,
+            mmap::ppu::START..=mmap::ppu::END => {
+                self.ppu_latch.refresh(val, 0xff, self.master_clock);
+                if let Some(ref mut ppu) = self.ppu {
+                    ppu.write(
+                        addr,
+                        val,
+                        self.cartridge.as_mut().map(|cartridge| cartridge.mapper()),
+                    );
+                }
+                // enabling NMI in the middle of vblank raises one straight away
+                self.poll_nmi();
+            }
+            mmap::apu_io_registers::OAM_DMA => self.oam_dma = Some(val),
+            mmap::apu_io_registers::START..=mmap::apu_io_registers::END => {
+                if let Some(ref mut apu_io) = self.apu_io {
+                    apu_io.write(addr, val);
+                }
+            }
+            mmap::cartrige::START..=mmap::cartrige::END => {
+                if let Some(ref mut cartridge) = self.cartridge {
+                    cartridge.mapper().cpu_write(addr, val);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// reads memory without side effects, for tracers and debuggers
+    pub fn peek(&self, addr: u16) -> u8 {
+        let addr = Self::decode(addr);
+        match addr as usize {
+            mmap::ram::START..=mmap::ram::END => self.ram[addr as usize],
+            mmap::ppu::START..=mmap::ppu::END => match self.ppu {
+                Some(ref ppu) => {
+                    let floating = ppu.open_bus_mask(addr);
+                    let latch = self.ppu_latch.value(self.master_clock, self.region);
+                    ppu.peek(addr) & !floating | latch & floating
+                }
+                None => self.open_bus,
+            },
+            mmap::apu_io_registers::START..=mmap::apu_io_registers::END => match self.apu_io {
+                Some(ref apu_io) => {
+                    let floating = apu_io.open_bus_mask(addr);
+                    apu_io.peek(addr) & !floating | self.open_bus & floating
+                }
+                None => self.open_bus,
+            },
+            mmap::cartrige::START..=mmap::cartrige::END => self
+                .cartridge
+                .as_ref()
+                .and_then(|cartridge| cartridge.mapper_ref().cpu_peek(addr))
+                .unwrap_or(self.open_bus),
+            _ => self.open_bus,
+        }
+    }
+
+    /// where the PPU is in the frame as (scanline, dot)
+    ///
+    /// without a PPU that tracks it, it's counted from power on assuming every frame is
+    /// the same length, which drifts once an NTSC PPU starts skipping dots on odd frames
+    pub fn ppu_position(&self) -> (u16, u16) {
+        if let Some(position) = self.ppu.as_ref().and_then(|ppu| ppu.position()) {
+            return position;
+        }
+
+        let dots = self.ppu_clock / self.region.ppu_divider();
+        let scanlines = match self.region {
+            Region::Ntsc => 262,
+            Region::Pal => 312,
+        };
+        ((dots / 341 % scanlines) as u16, (dots % 341) as u16)
+    }
+
+    /// reads a little endian word
+    pub fn read_u16(&mut self, addr: u16) -> u16 {
+        let lo = self.read(addr) as u16;
+        let hi = self.read(addr.wrapping_add(1)) as u16;
+        hi << 8 | lo
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::{Mapper, Mirroring};
+    use std::sync::atomic::{AtomicU16, Ordering};
+    use std::sync::Arc;
+
+    /// records the last access it received
+    struct Probe {
+        last_addr: Arc<AtomicU16>,
+    }
+
+    impl BusDevice for Probe {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.last_addr.store(addr, Ordering::Relaxed);
+            0xaa
+        }
+
+        fn write(&mut self, addr: u16, _val: u8) {
+            self.last_addr.store(addr, Ordering::Relaxed);
+        }
+    }
+
+    impl Mapper for Probe {
+        fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+            Some(BusDevice::read(self, addr))
+        }
+
+        fn cpu_write(&mut self, addr: u16, val: u8) {
+            BusDevice::write(self, addr, val);
+        }
+
+        fn cpu_peek(&self, _addr: u16) -> Option<u8> {
+            Some(0xaa)
+        }
+
+        fn ppu_read(&mut self, _addr: u16) -> u8 {
+            0
+        }
+
+        fn ppu_write(&mut self, _addr: u16, _val: u8) {}
+
+        fn mirroring(&self) -> Mirroring {
+            Mirroring::Horizontal
+        }
+    }
+
+    /// drives the top 3 bits of $2002 and nothing on the other registers, like the PPU
+    struct Status;
+
+    impl BusDevice for Status {
+        fn read(&mut self, _addr: u16) -> u8 {
+            0x80
+        }
+
+        fn write(&mut self, _addr: u16, _val: u8) {}
+
+        fn peek(&self, _addr: u16) -> u8 {
+            0x80
+        }
+
+        fn open_bus_mask(&self, addr: u16) -> u8 {
+            match addr {
+                0x2002 => 0x1f,
+                _ => 0xff,
+            }
+        }
+    }
+
+    #[test]
+    fn open_bus() {
+        let mut bus = Bus::default();
+        bus.write(0x0000, 0x12);
+        assert!(bus.read(0x4018) == 0x12);
+        assert!(bus.read(0x2002) == 0x12);
+        assert!(bus.peek(0x8000) == 0x12);
+
+        bus.attach_ppu(Box::new(Status));
+        bus.write(0x2000, 0x3f);
+        bus.write(0x0000, 0x00);
+        // the PPU has a latch of its own, the rest of $2002 comes from the last write
+        assert!(bus.read(0x2002) == 0x9f);
+        assert!(bus.read(0x2005) == 0x9f);
+
+        // the reads refreshed the top bits later than the write did the others
+        for _ in 0..600_000 {
+            bus.tick();
+        }
+        bus.read(0x2002);
+        for _ in 0..600_000 {
+            bus.tick();
+        }
+        assert!(bus.peek(0x2000) == 0x80);
+        for _ in 0..600_000 {
+            bus.tick();
+        }
+        assert!(bus.peek(0x2000) == 0x00);
+    }
+
+    #[test]
+    fn ram_mirroring() {
+        let mut bus = Bus::default();
+        bus.write(0x0001, 0x12);
+        assert!(bus.read(0x0801) == 0x12);
+        assert!(bus.read(0x1001) == 0x12);
+        assert!(bus.read(0x1801) == 0x12);
+
+        bus.write(0x1fff, 0x34);
+        assert!(bus.read(0x07ff) == 0x34);
+    }
+
+    #[test]
+    fn ppu_register_mirroring() {
+        let last_addr = Arc::new(AtomicU16::new(0));
+        let mut bus = Bus::default();
+        bus.attach_ppu(Box::new(Probe {
+            last_addr: last_addr.clone(),
+        }));
+
+        assert!(bus.read(0x2002) == 0xaa);
+        assert!(last_addr.load(Ordering::Relaxed) == 0x2002);
+        bus.write(0x3ffe, 0);
+        assert!(last_addr.load(Ordering::Relaxed) == 0x2006);
+        bus.read(0x2008);
+        assert!(last_addr.load(Ordering::Relaxed) == 0x2000);
+    }
+
+    #[test]
+    fn routes_to_devices() {
+        let apu_addr = Arc::new(AtomicU16::new(0));
+        let cart_addr = Arc::new(AtomicU16::new(0));
+        let mut bus = Bus::default();
+
+        // nothing attached yet
+        assert!(bus.read(0x8000) == 0);
+
+        bus.attach_apu_io(Box::new(Probe {
+            last_addr: apu_addr.clone(),
+        }));
+        bus.attach_cartridge(Cartridge::new(Box::new(Probe {
+            last_addr: cart_addr.clone(),
+        })));
+
+        bus.write(0x4015, 0);
+        assert!(apu_addr.load(Ordering::Relaxed) == 0x4015);
+        assert!(bus.read(0xfffc) == 0xaa);
+        assert!(cart_addr.load(Ordering::Relaxed) == 0xfffc);
+        assert!(bus.read(0x4020) == 0xaa);
+        assert!(cart_addr.load(Ordering::Relaxed) == 0x4020);
+
+        // test mode registers are not routed anywhere, they read what was last on the bus
+        assert!(bus.read(0x4018) == 0xaa);
+        assert!(apu_addr.load(Ordering::Relaxed) == 0x4015);
+    }
+
+    #[test]
+    fn records_accesses() {
+        let mut bus = Bus::default();
+        bus.write(0x0810, 0x42);
+        assert!(bus.take_accesses().is_empty());
+
+        bus.record_accesses(true);
+        bus.write(0x0810, 0x42);
+        bus.read(0x0010);
+        bus.peek(0x0010);
+        assert!(
+            bus.take_accesses()
+                == [
+                    Access {
+                        addr: 0x0010,
+                        val: 0x42,
+                        write: true
+                    },
+                    Access {
+                        addr: 0x0010,
+                        val: 0x42,
+                        write: false
+                    },
+                ]
+        );
+        assert!(bus.take_accesses().is_empty());
+    }
+}
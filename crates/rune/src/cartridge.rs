@@ -0,0 +1,71 @@
+use crate::mapper::{self, Mapper};
+use crate::scheduler::Region;
+use rune_ines::InesFile;
+
+/// A game cartridge: the ROM's memory wired up through its board's mapper
+pub struct Cartridge {
+    mapper: Box<dyn Mapper>,
+    region: Region,
+}
+
+impl Cartridge {
+    /// wraps a board that wasn't loaded from a ROM file
+    pub fn new(mapper: Box<dyn Mapper>) -> Self {
+        Cartridge {
+            mapper,
+            region: Region::default(),
+        }
+    }
+
+    /// returns the mapper number if the board is not supported
+    pub fn from_ines(rom: InesFile) -> Result<Self, u8> {
+        let region = Region::from_header(&rom);
+        Ok(Cartridge {
+            mapper: mapper::from_ines(rom)?,
+            region,
+        })
+    }
+
+    /// the video standard the game was made for
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn mapper(&mut self) -> &mut dyn Mapper {
+        self.mapper.as_mut()
+    }
+
+    pub fn mapper_ref(&self) -> &dyn Mapper {
+        self.mapper.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rune_ines::InesHeader;
+
+    fn rom(flags10: u8) -> InesFile {
+        let mut header = [0u8; 16];
+        header[0..4].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a]);
+        header[4] = 1;
+        header[10] = flags10;
+        InesFile {
+            header: InesHeader::parse(&header).unwrap(),
+            trainer: None,
+            prg_rom: vec![0xaa; 16384],
+            chr_rom: vec![],
+            inst_rom: None,
+            prom: None,
+        }
+    }
+
+    #[test]
+    fn built_from_ines() {
+        let mut cartridge = Cartridge::from_ines(rom(0)).unwrap();
+        assert!(cartridge.region() == Region::Ntsc);
+        assert!(cartridge.mapper().cpu_read(0x8000) == Some(0xaa));
+
+        assert!(Cartridge::from_ines(rom(2)).unwrap().region() == Region::Pal);
+    }
+}
@@ -1,11 +1,15 @@
+use crate::bus::Bus;
+use crate::cartridge::Cartridge;
 use crate::mmap;
-use std::mem;
-use std::time::Instant;
+use crate::opcodes::{self, AddrMode, Mnemonic, Opcode};
+use rune_ines::InesFile;
+use std::io::Write;
 
-pub struct CPU<'prg_rom> {
-    /// 0x0 - 0x7ff and mirrors from 0x800 to 0x1fff
-    ram: [u8; 2048],
-    prg_rom: Option<&'prg_rom Vec<u8>>,
+mod trace;
+
+#[allow(clippy::upper_case_acronyms)]
+pub struct CPU {
+    pub bus: Bus,
     /// program counter
     pc: u16,
     /// stack pointer
@@ -24,15 +28,68 @@ pub struct CPU<'prg_rom> {
     decimal: bool,
     interrupt_disable: bool,
     zero: bool,
-    /// break command
-    b: bool,
+
+    /// number of cycles executed since power on
+    cycles: u64,
+
+    /// whether something is pulling the NMI line, NMIs trigger on the edge
+    nmi_line: bool,
+    /// an NMI edge was detected and has not been serviced yet
+    nmi_pending: bool,
+    /// whether something other than the cartridge is pulling the IRQ line
+    irq_line: bool,
+    /// a KIL opcode locked the CPU up, only a reset gets it running again
+    halted: bool,
+    /// cycles spent halted by DMA that the current step hasn't counted yet
+    stalled: u64,
+    timing: Timing,
+    /// receives a nestest.log style line before each instruction
+    tracer: Option<Box<dyn Write + Send>>,
+}
+
+/// How the CPU advances time relative to the rest of the system
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Timing {
+    /// instructions run atomically and only report how many cycles they took
+    #[default]
+    Instruction,
+    /// every bus access takes one cycle and clocks the bus before it happens,
+    /// dummy reads and writes are performed just like on hardware
+    Cycle,
 }
 
-impl Default for CPU<'_> {
+/// A snapshot of the registers a program can see
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub pc: u16,
+    pub sp: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    /// status flags packed the way the trace shows them
+    pub p: u8,
+}
+
+/// What an instruction operates on once its addressing mode is resolved
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Implied,
+    Accumulator,
+    Immediate(u8),
+    Address(u16),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Interrupt {
+    Nmi,
+    Irq,
+    Brk,
+}
+
+impl Default for CPU {
     fn default() -> Self {
         let mut cpu = CPU {
-            ram: [0; 2048],
-            prg_rom: None,
+            bus: Bus::default(),
             pc: 0,
             sp: 0xff,
             a: 0,
@@ -44,7 +101,14 @@ impl Default for CPU<'_> {
             interrupt_disable: false,
             zero: false,
             carry: 0,
-            b: false,
+            cycles: 0,
+            nmi_line: false,
+            nmi_pending: false,
+            irq_line: false,
+            halted: false,
+            stalled: 0,
+            timing: Timing::default(),
+            tracer: None,
         };
 
         cpu.set_status(0x34);
@@ -52,2003 +116,1547 @@ impl Default for CPU<'_> {
     }
 }
 
-impl CPU<'_> {
-    pub fn load_rom(&mut self, prg_rom: Vec<u8>) {
-        use mmap::cartrige;
-        // loads program
-        let mut j = 0;
-        for i in cartrige::START..cartrige::END {
-            if i >= prg_rom.len() {
-                break;
-            }
-
-            self.ram[i] = prg_rom[j];
-            j += 1;
-        }
-
-        // loads sprites
-    }
-
-    pub fn cycle(&mut self) {
-        const SECS_PER_CYCLE: f32 = 1.0 / 21441960.0;
-        let mut start = Instant::now();
-        let cycle_time: u8;
-
-        let rom = if let Some(ref rom) = self.prg_rom {
-            rom
-        } else {
-            return;
-        };
-
-        let op_u8: u8 = rom[self.pc as usize + 1];
-        let op_u16: u16 = (self.ram[(self.pc.wrapping_add(1)) as usize] as u16) << 8
-            | self.ram[self.pc as usize] as u16;
-
-        match rom[self.pc as usize] {
-            // ADC
-            0x69 => {
-                self.adc69(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-            0x65 => {
-                self.adc65(op_u8);
-                self.pc += 1;
-                cycle_time = 3;
-            }
-            0x75 => {
-                self.adc75(op_u8);
-                self.pc += 1;
-                cycle_time = 4;
-            }
-            0x6D => {
-                self.adc6d(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0x7D => {
-                self.adc7d(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-                // TODO: impl cross page time
-            }
-            0x79 => {
-                self.adc79(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0x61 => {
-                self.adc61(op_u8);
-                self.pc += 1;
-                cycle_time = 6;
-            }
-            0x71 => {
-                self.adc71(op_u8);
-                self.pc += 1;
-                cycle_time = 5;
-            }
-
-            // AND
-            0x29 => {
-                self.and29(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-            0x25 => {
-                self.and25(op_u8);
-                self.pc += 1;
-                cycle_time = 3;
-            }
-            0x35 => {
-                self.and35(op_u8);
-                self.pc += 1;
-                cycle_time = 4;
-            }
-            0x2D => {
-                self.and2d(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0x3D => {
-                // TODO: page cross
-                self.and3d(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0x39 => {
-                // TODO: page cross
-                self.and39(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0x21 => {
-                self.and21(op_u8);
-                self.pc += 1;
-                cycle_time = 6;
-            }
-            0x31 => {
-                // TODO: page cross
-                self.and31(op_u8);
-                self.pc += 1;
-                cycle_time = 5;
-            }
-
-            // ASL
-            0x0a => {
-                self.asl0a();
-                cycle_time = 2;
-            }
-            0x06 => {
-                self.asl06(op_u8);
-                self.pc += 1;
-                cycle_time = 5;
-            }
-            0x16 => {
-                self.asl16(op_u8);
-                self.pc += 1;
-                cycle_time = 6;
-            }
-            0x0e => {
-                self.asl0e(op_u16);
-                self.pc += 2;
-                cycle_time = 6;
-            }
-            0x1e => {
-                self.asl1e(op_u16);
-                self.pc += 2;
-                cycle_time = 7;
-            }
-
-            // BCC
-            0x90 => {
-                // TODO: branch and new page timings
-                self.bcc90(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-
-            // BCS
-            0xB0 => {
-                // TODO: branch and new page timings
-                self.bcsb0(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-
-            // BEQ
-            0xF0 => {
-                // TODO: branch and new page timings
-                self.beqf0(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-
-            // BIT
-            0x24 => {
-                self.bit24(op_u8);
-                self.pc += 1;
-                cycle_time = 3;
-            }
-            0x2C => {
-                self.bit2c(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-
-            // BMI
-            0x30 => {
-                // TODO: branch and new page timings
-                self.bmi30(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-
-            // BNE
-            0xD0 => {
-                // TODO: branch and new page timings
-                self.bned0(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-
-            // BPL
-            0x10 => {
-                // TODO: branch and new page timings
-                self.bpl10(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-
-            // BRK
-            0x00 => {
-                self.brk00();
-                cycle_time = 7;
-            }
-
-            // BVC
-            0x50 => {
-                // TODO: branch and new page timing
-                self.bvc50(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-
-            // BVS
-            0x70 => {
-                // TODO: branch and new page timing
-                self.bvs70(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-
-            // CLC
-            0x18 => {
-                self.clc18();
-                cycle_time = 2;
-            }
-
-            // CLD
-            0xD8 => {
-                self.cldd8();
-                cycle_time = 2;
-            }
-
-            // CLI
-            0x58 => {
-                self.cli58();
-                cycle_time = 2;
-            }
-
-            // CLV
-            0xB8 => {
-                self.clvb8();
-                cycle_time = 2;
-            }
-
-            // CMP
-            0xC9 => {
-                self.cmpc9(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-            0xC5 => {
-                self.cmpc5(op_u8);
-                self.pc += 1;
-                cycle_time = 3;
-            }
-            0xD5 => {
-                self.cmpd5(op_u8);
-                self.pc += 1;
-                cycle_time = 4;
-            }
-            0xCD => {
-                self.cmpcd(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0xDD => {
-                // TODO: page cross timing
-                self.cmpdd(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0xD9 => {
-                // TODO: page cross timing
-                self.cmpd9(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0xC1 => {
-                self.cmpc1(op_u8);
-                self.pc += 1;
-                cycle_time = 6;
-            }
-            0xD1 => {
-                self.cmpd1(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-
-            // CPX
-            0xE0 => {
-                self.cpxe0(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-            0xE4 => {
-                self.cpxe4(op_u8);
-                self.pc += 1;
-                cycle_time = 3;
-            }
-            0xEC => {
-                self.cpxec(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-
-            // CPY
-            0xC0 => {
-                self.cpyc0(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-            0xC4 => {
-                self.cpyc4(op_u8);
-                self.pc += 1;
-                cycle_time = 3;
-            }
-            0xCC => {
-                self.cpycc(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-
-            // DEC
-            0xC6 => {
-                self.decc6(op_u8);
-                self.pc += 1;
-                cycle_time = 5;
-            }
-            0xD6 => {
-                self.decd6(op_u8);
-                self.pc += 1;
-                cycle_time = 6;
-            }
-            0xCE => {
-                self.decce(op_u16);
-                self.pc += 2;
-                cycle_time = 6;
-            }
-            0xDE => {
-                self.decde(op_u16);
-                self.pc += 2;
-                cycle_time = 7;
-            }
-
-            // DEX
-            0xCA => {
-                self.dexca();
-                cycle_time = 2;
-            }
-
-            // DEY
-            0x88 => {
-                self.dey88();
-                cycle_time = 2;
-            }
-
-            // EOR
-            0x49 => {
-                self.eor49(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-            0x45 => {
-                self.eor45(op_u8);
-                self.pc += 1;
-                cycle_time = 3;
-            }
-            0x55 => {
-                self.eor55(op_u8);
-                self.pc += 1;
-                cycle_time = 4;
-            }
-            0x4D => {
-                self.eor4d(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0x5D => {
-                // TODO: page cross timing
-                self.eor5d(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0x59 => {
-                // TODO: page cross timing
-                self.eor59(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0x41 => {
-                self.eor41(op_u8);
-                self.pc += 1;
-                cycle_time = 6;
-            }
-            0x51 => {
-                // TODO: page cross timing
-                self.eor51(op_u8);
-                self.pc += 1;
-                cycle_time = 5;
-            }
-
-            // INC
-            0xE6 => {
-                self.ince6(op_u8);
-                self.pc += 1;
-                cycle_time = 5;
-            }
-            0xF6 => {
-                self.incf6(op_u8);
-                self.pc += 1;
-                cycle_time = 6;
-            }
-            0xEE => {
-                self.incee(op_u16);
-                self.pc += 2;
-                cycle_time = 6;
-            }
-            0xFE => {
-                self.incfe(op_u16);
-                self.pc += 2;
-                cycle_time = 7;
-            }
-
-            // INX
-            0xE8 => {
-                self.inxe8();
-                cycle_time = 2;
-            }
-
-            // INY
-            0xC8 => {
-                self.inyc8();
-                cycle_time = 2;
-            }
-
-            // JMP
-            0x4C => {
-                self.jmp4c(op_u16);
-                self.pc += 2;
-                cycle_time = 3;
-            }
-            0x6C => {
-                self.jmp6c(op_u16);
-                cycle_time = 5;
-            }
-
-            // JSR
-            0x20 => {
-                self.jsr20(op_u16);
-                self.pc += 2;
-                cycle_time = 6;
-            }
-
-            // LDA
-            0xA9 => {
-                self.ldaa9(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-            0xA5 => {
-                self.ldaa5(op_u8);
-                self.pc += 1;
-                cycle_time = 3;
-            }
-            0xB5 => {
-                self.ldab5(op_u8);
-                self.pc += 1;
-                cycle_time = 4;
-            }
-            0xAD => {
-                self.ldaad(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0xBD => {
-                // TODO: page cross timing
-                self.ldabd(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0xB9 => {
-                // TODO: page cross timing
-                self.ldab9(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0xA1 => {
-                self.ldaa1(op_u8);
-                self.pc += 1;
-                cycle_time = 6;
-            }
-            0xB1 => {
-                // TODO: page cross timing
-                self.ldab1(op_u8);
-                self.pc += 1;
-                cycle_time = 5;
-            }
-
-            // LDX
-            0xA2 => {
-                self.ldxa2(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-            0xA6 => {
-                self.ldxa6(op_u8);
-                self.pc += 1;
-                cycle_time = 3;
-            }
-            0xB6 => {
-                self.ldxb6(op_u8);
-                self.pc += 1;
-                cycle_time = 4;
-            }
-            0xAE => {
-                self.ldxae(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0xBE => {
-                // TODO: page cross timing
-                self.ldxbe(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-
-            // LDY
-            0xA0 => {
-                self.ldya0(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-            0xA4 => {
-                self.ldya4(op_u8);
-                self.pc += 1;
-                cycle_time = 3;
-            }
-            0xB4 => {
-                self.ldyb4(op_u8);
-                self.pc += 1;
-                cycle_time = 4;
-            }
-            0xAC => {
-                self.ldyac(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0xBC => {
-                // TODO: page cross timing
-                self.ldybc(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-
-            // LSR
-            0x4A => {
-                self.lsr4a();
-                cycle_time = 2;
-            }
-            0x46 => {
-                self.lsr46(op_u8);
-                self.pc += 1;
-                cycle_time = 5;
-            }
-            0x56 => {
-                self.lsr56(op_u8);
-                self.pc += 1;
-                cycle_time = 6;
-            }
-            0x4E => {
-                self.lsr4e(op_u16);
-                self.pc += 2;
-                cycle_time = 6;
-            }
-            0x5E => {
-                self.lsr5e(op_u16);
-                self.pc += 2;
-                cycle_time = 7;
-            }
-
-            // ORA
-            0x09 => {
-                self.ora09(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-            0x05 => {
-                self.ora05(op_u8);
-                self.pc += 1;
-                cycle_time = 3;
-            }
-            0x15 => {
-                self.ora15(op_u8);
-                self.pc += 1;
-                cycle_time = 4;
-            }
-            0x0D => {
-                self.ora0d(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0x1D => {
-                // TODO: page cross timing
-                self.ora1d(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0x19 => {
-                // TODO: page cross timing
-                self.ora19(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0x01 => {
-                self.ora01(op_u8);
-                self.pc += 1;
-                cycle_time = 6;
-            }
-            0x11 => {
-                // TODO: page cross timing
-                self.ora11(op_u8);
-                self.pc += 1;
-                cycle_time = 5;
-            }
-
-            // PHA
-            0x48 => {
-                self.pha48();
-                cycle_time = 3;
-            }
-
-            // PHP
-            0x08 => {
-                self.php08();
-                cycle_time = 3;
-            }
-
-            // PLA
-            0x68 => {
-                self.pla68();
-                cycle_time = 4;
-            }
-
-            // PLP
-            0x28 => {
-                self.plp28();
-                cycle_time = 4;
-            }
-
-            // ROL
-            0x2A => {
-                self.rol2a();
-                cycle_time = 2;
-            }
-            0x26 => {
-                self.rol26(op_u8);
-                self.pc += 1;
-                cycle_time = 5;
-            }
-            0x36 => {
-                self.rol36(op_u8);
-                self.pc += 1;
-                cycle_time = 6;
-            }
-            0x2E => {
-                self.rol2e(op_u16);
-                self.pc += 2;
-                cycle_time = 6;
-            }
-            0x3E => {
-                self.rol3e(op_u16);
-                self.pc += 2;
-                cycle_time = 7;
-            }
-
-            // ROR
-            0x6A => {
-                self.ror6a();
-                cycle_time = 2;
-            }
-            0x66 => {
-                self.ror66(op_u8);
-                self.pc += 1;
-                cycle_time = 5;
-            }
-            0x76 => {
-                self.ror76(op_u8);
-                self.pc += 1;
-                cycle_time = 6;
-            }
-            0x6E => {
-                self.ror6e(op_u16);
-                self.pc += 2;
-                cycle_time = 6;
-            }
-            0x7E => {
-                self.ror7e(op_u16);
-                self.pc += 2;
-                cycle_time = 7;
-            }
-
-            // RTI
-            0x40 => {
-                self.rti40();
-                cycle_time = 6;
-            }
-
-            // RTS
-            0x60 => {
-                self.rts60();
-                cycle_time = 6;
-            }
-
-            // SBC
-            0xE9 => {
-                self.sbce9(op_u8);
-                self.pc += 1;
-                cycle_time = 2;
-            }
-            0xE5 => {
-                self.sbce5(op_u8);
-                self.pc += 1;
-                cycle_time = 3;
-            }
-            0xF5 => {
-                self.sbcf5(op_u8);
-                self.pc += 1;
-                cycle_time = 4;
-            }
-            0xED => {
-                self.sbced(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0xFD => {
-                // TODO: page cross timing
-                self.sbcfd(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0xF9 => {
-                // TODO: page cross timing
-                self.sbcf9(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0xE1 => {
-                self.sbce1(op_u8);
-                self.pc += 1;
-                cycle_time = 6;
-            }
-            0xF1 => {
-                // TODO: page cross timing
-                self.sbcf1(op_u8);
-                self.pc += 1;
-                cycle_time = 5;
-            }
-
-            // SEC
-            0x38 => {
-                self.sec38();
-                cycle_time = 2;
-            }
-
-            // SED
-            0xF8 => unimplemented!(),
-
-            // SEI
-            0x78 => {
-                self.sei78();
-                cycle_time = 2;
-            }
-
-            // STA
-            0x85 => {
-                self.sta85(op_u8);
-                self.pc += 1;
-                cycle_time = 3;
-            }
-            0x95 => {
-                self.sta95(op_u8);
-                self.pc += 1;
-                cycle_time = 4;
-            }
-            0x8D => {
-                self.sta8d(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-            0x9D => {
-                self.sta9d(op_u16);
-                self.pc += 2;
-                cycle_time = 5;
-            }
-            0x99 => {
-                self.sta99(op_u16);
-                self.pc += 2;
-                cycle_time = 5;
-            }
-            0x81 => {
-                self.sta81(op_u8);
-                self.pc += 1;
-                cycle_time = 6;
-            }
-            0x91 => {
-                self.sta91(op_u8);
-                self.pc += 1;
-                cycle_time = 6;
-            }
-
-            // STX
-            0x86 => {
-                self.stx86(op_u8);
-                self.pc += 1;
-                cycle_time = 3;
-            }
-            0x96 => {
-                self.stx96(op_u8);
-                self.pc += 1;
-                cycle_time = 4;
-            }
-            0x8E => {
-                self.stx8e(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-
-            // STY
-            0x84 => {
-                self.sty84(op_u8);
-                self.pc += 1;
-                cycle_time = 3;
-            }
-            0x94 => {
-                self.sty94(op_u8);
-                self.pc += 1;
-                cycle_time = 4;
-            }
-            0x8C => {
-                self.sty8c(op_u16);
-                self.pc += 2;
-                cycle_time = 4;
-            }
-
-            // TAX
-            0xAA => {
-                self.taxaa();
-                cycle_time = 2;
-            }
-
-            // TAY
-            0xA8 => {
-                self.taya8();
-                cycle_time = 2;
-            }
-
-            // TSX
-            0xBA => {
-                self.tsxba();
-                cycle_time = 2;
-            }
-
-            // TXA
-            0x8A => {
-                self.txa8a();
-                cycle_time = 2;
-            }
-
-            // TXS
-            0x9A => {
-                self.txs9a();
-                cycle_time = 2;
-            }
-
-            // TYA
-            0x98 => {
-                self.tya98();
-                cycle_time = 2;
-            }
-
-            // NOP
-            0xEA | _ => {
-                self.pc += 1;
-                cycle_time = 2;
-            }
+impl CPU {
+    /// inserts the cartridge described by `rom` into the console
+    ///
+    /// returns the mapper number if the board is not supported
+    pub fn load_rom(&mut self, rom: InesFile) -> Result<(), u8> {
+        self.bus.attach_cartridge(Cartridge::from_ines(rom)?);
+        Ok(())
+    }
+
+    /// puts the CPU in its power up state and runs the reset sequence
+    ///
+    /// https://www.nesdev.org/wiki/CPU_power_up_state
+    pub fn power_on(&mut self) {
+        self.a = 0;
+        self.x = 0;
+        self.y = 0;
+        self.sp = 0;
+        self.set_status(0x34);
+        self.cycles = 0;
+        self.reset();
+    }
+
+    /// emulates the reset line being pulled, registers other than SP and P are left untouched
+    pub fn reset(&mut self) {
+        // reset runs the interrupt sequence with its stack writes turned into reads,
+        // so SP is decremented 3 times without anything being pushed
+        let start = self.bus.cycles();
+        self.dummy_read(self.pc);
+        self.dummy_read(self.pc);
+        for _ in 0..3 {
+            self.dummy_read((mmap::ram::stack::START + self.sp as usize) as u16);
+            self.sp = self.sp.wrapping_sub(1);
         }
+        self.interrupt_disable = true;
+        self.halted = false;
+        self.pc = self.read_u16(mmap::cpu::reset::START as u16);
+        self.cycles += 7;
 
-        // synchronizes to clockspeed
-        for _ in 0..cycle_time {
-            while SECS_PER_CYCLE > start.elapsed().as_secs_f32() {}
-            start = Instant::now();
+        // nothing else runs the bus during reset, so catch it up here
+        for _ in self.bus.cycles() - start..7 {
+            self.bus.tick();
         }
-        self.pc += 1;
     }
 
-    /// encodes the status flags into a single byte
-    fn get_status(&self) -> u8 {
-        let mut status_code: u8 = 0;
-        if self.negative {
-            status_code |= 0b1000_0000;
-        }
-
-        if self.overflow {
-            status_code |= 0b0100_0000;
-        }
-
-        if self.b {
-            status_code |= 0b0011_0000;
-        } else {
-            status_code |= 0b0010_0000;
-        }
-
-        if self.decimal {
-            status_code |= 0b0000_1000;
-        }
-
-        if self.interrupt_disable {
-            status_code |= 0b0000_0100;
-        }
-
-        if self.zero {
-            status_code |= 0b0000_0010;
-        }
-
-        if self.carry == 1 {
-            status_code |= 0b0000_0001;
-        }
-
-        status_code
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
     }
 
-    /// decodes status flag byte into their corresponding struct fields
-    fn set_status(&mut self, status_byte: u8) {
-        self.negative = status_byte & 0b1000_0000 == 0b1000_0000;
-        self.overflow = status_byte & 0b0100_0000 == 0b0100_0000;
-        self.b = status_byte & 0b0001_0000 == 0b0001_0000;
-        self.decimal = status_byte & 0b0000_1000 == 0b0000_1000;
-        self.interrupt_disable = status_byte & 0b0000_0100 == 0b0000_0100;
-        self.zero = status_byte & 0b0000_0010 == 0b0000_0010;
-        self.carry = status_byte & 0b0000_0001;
+    /// logs every instruction to `tracer` in the nestest.log format, `None` turns tracing off
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn Write + Send>>) {
+        self.tracer = tracer;
     }
 
-    fn get_indirect_addr(&mut self, operand: u8) -> u16 {
-        let addr1: u16 = (self.ram[(operand.wrapping_add(1)) as usize] as u16) << 8;
-        let addr2: u16 = self.ram[operand as usize] as u16;
-        addr1 | addr2
+    pub fn pc(&self) -> u16 {
+        self.pc
     }
 
-    fn push_to_stack(&mut self, val: u8) {
-        self.ram[mmap::ram::stack::START + self.sp as usize] = val;
-        self.sp -= 1;
+    pub fn registers(&self) -> Registers {
+        Registers {
+            pc: self.pc,
+            sp: self.sp,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            p: self.get_status(),
+        }
     }
 
-    fn pop_from_stack(&mut self) -> u8 {
-        self.sp += 1;
-        self.ram[mmap::ram::stack::START + self.sp as usize]
+    /// for debuggers, B and bit 5 of `p` are ignored like with PLP
+    pub fn set_registers(&mut self, regs: Registers) {
+        self.pc = regs.pc;
+        self.sp = regs.sp;
+        self.a = regs.a;
+        self.x = regs.x;
+        self.y = regs.y;
+        self.set_status(regs.p);
     }
 
-    // --- INSTRUCTIONS ---
-
-    /// ADC #$44
-    fn adc69(&mut self, operand: u8) {
-        let (inum, overflowed1) = unsafe {
-            mem::transmute::<u8, i8>(operand).overflowing_add(mem::transmute::<u8, i8>(self.carry))
-        };
-        let (_, overflowed2) = unsafe { inum.overflowing_add(mem::transmute::<u8, i8>(self.a)) };
-
-        let (num, carried1) = operand.overflowing_add(self.carry);
-        let (res, carried2) = num.overflowing_add(self.a);
-
-        self.a = res;
-
-        self.carry = if carried1 || carried2 { 1 } else { 0 };
-        self.overflow = overflowed1 || overflowed2;
-        self.zero = self.a == 0;
-        self.negative = (self.a >> 7) == 1;
+    /// jumps straight to `pc`, e.g. to start a test ROM in automation mode
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
     }
 
-    /// ADC $44
-    fn adc65(&mut self, operand: u8) {
-        let operand = self.ram[operand as usize];
-        self.adc69(operand);
+    /// whether a KIL opcode jammed the CPU
+    pub fn halted(&self) -> bool {
+        self.halted
     }
 
-    /// ADC $44, X
-    fn adc75(&mut self, operand: u8) {
-        let operand = self.ram[(operand.wrapping_add(self.x)) as usize];
-        self.adc69(operand);
+    /// drives the NMI input, an interrupt is requested when the line becomes asserted
+    pub fn set_nmi_line(&mut self, asserted: bool) {
+        if asserted && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = asserted;
     }
 
-    /// ADC $4400
-    fn adc6d(&mut self, operand: u16) {
-        let operand = self.ram[operand as usize];
-        self.adc69(operand);
+    /// drives the IRQ input, interrupts are requested for as long as the line is asserted
+    pub fn set_irq_line(&mut self, asserted: bool) {
+        self.irq_line = asserted;
     }
 
-    /// ADC $4400, X
-    fn adc7d(&mut self, operand: u16) {
-        let operand = self.ram[(operand.wrapping_add(self.x as u16)) as usize];
-        self.adc69(operand);
+    fn irq_asserted(&mut self) -> bool {
+        self.irq_line
+            || self
+                .bus
+                .cartridge()
+                .is_some_and(|cartridge| cartridge.mapper().irq())
     }
 
-    /// ADC $4400,Y
-    fn adc79(&mut self, operand: u16) {
-        let operand = self.ram[(operand.wrapping_add(self.y as u16)) as usize];
-        self.adc69(operand);
-    }
+    /// executes a single instruction and any interrupt raised during it
+    ///
+    /// returns the number of cycles taken
+    pub fn step(&mut self) -> u64 {
+        if self.halted {
+            // the clock keeps running but nothing gets executed, interrupts included
+            self.dummy_read(0xFFFF);
+            let cycles = 1 + std::mem::take(&mut self.stalled);
+            self.cycles += cycles;
+            return cycles;
+        }
 
-    /// ADC ($44,X)
-    fn adc61(&mut self, operand: u8) {
-        let addr = self.get_indirect_addr(operand.wrapping_add(self.x));
-        self.adc69(self.ram[addr as usize]);
-    }
+        if self.tracer.is_some() {
+            self.trace();
+        }
 
-    /// ADC ($44),Y
-    fn adc71(&mut self, operand: u8) {
-        let addr = self.get_indirect_addr(operand);
-        let operand = self.ram[(addr.wrapping_add(self.y as u16)) as usize];
-        self.adc69(operand);
-    }
+        if self.timing == Timing::Instruction {
+            // without reads of its own the CPU can only be halted between instructions
+            self.dma(self.pc);
+        }
 
-    /// AND #$44
-    fn and29(&mut self, operand: u8) {
-        self.a &= operand;
-        self.zero = self.a == 0;
-        self.negative = self.a >> 7 == 1;
-    }
+        let opcode = self.read(self.pc);
+        let op = opcodes::decode(opcode);
 
-    /// AND $44
-    fn and25(&mut self, operand: u8) {
-        let operand = self.ram[operand as usize];
-        self.and29(operand);
-    }
+        let interrupt_disable = self.interrupt_disable;
+        let (operand, page_crossed) = self.resolve(op);
+        let mut cycles = op.cycles as u64 + self.execute(op.mnemonic, operand);
+        if op.page_penalty && page_crossed {
+            cycles += 1;
+        }
 
-    /// AND $44,X
-    fn and35(&mut self, operand: u8) {
-        let operand = self.ram[(operand.wrapping_add(self.x)) as usize];
-        self.and29(operand);
-    }
+        if !self.halted {
+            cycles += self.poll_interrupts(opcode, interrupt_disable);
+        }
+        cycles += std::mem::take(&mut self.stalled);
+        self.cycles += cycles;
+        cycles
+    }
+
+    /// fetches the operand bytes of the instruction at PC and moves PC to the next one
+    ///
+    /// also reports whether indexing crossed a page boundary
+    fn resolve(&mut self, op: Opcode) -> (Operand, bool) {
+        let arg = self.pc.wrapping_add(1);
+        self.pc = self.pc.wrapping_add(op.len as u16);
+
+        let operand = match op.mode {
+            AddrMode::Implied | AddrMode::Accumulator => {
+                // the byte after the opcode is always fetched, even without operands
+                self.dummy_read(arg);
+                if op.mode == AddrMode::Implied {
+                    Operand::Implied
+                } else {
+                    Operand::Accumulator
+                }
+            }
+            AddrMode::Immediate => Operand::Immediate(self.read(arg)),
+            AddrMode::ZeroPage => Operand::Address(self.read(arg) as u16),
+            AddrMode::ZeroPageX | AddrMode::ZeroPageY => {
+                let base = self.read(arg);
+                // the index is added while the unindexed address is read
+                self.dummy_read(base as u16);
+                let index = if op.mode == AddrMode::ZeroPageX {
+                    self.x
+                } else {
+                    self.y
+                };
+                Operand::Address(base.wrapping_add(index) as u16)
+            }
+            AddrMode::Relative => {
+                let offset = self.read(arg) as i8;
+                Operand::Address(self.pc.wrapping_add(offset as u16))
+            }
+            AddrMode::Absolute => Operand::Address(self.read_u16(arg)),
+            AddrMode::AbsoluteX => {
+                let base = self.read_u16(arg);
+                return self.indexed(op, base, self.x);
+            }
+            AddrMode::AbsoluteY => {
+                let base = self.read_u16(arg);
+                return self.indexed(op, base, self.y);
+            }
+            AddrMode::Indirect => {
+                let pointer = self.read_u16(arg);
+                let lsb = self.read(pointer) as u16;
+                // the 6502 does not carry into the pointer's high byte,
+                // so JMP ($44FF) reads its MSB from $4400
+                let msb_addr = (pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF);
+                let msb = self.read(msb_addr) as u16;
+                Operand::Address(msb << 8 | lsb)
+            }
+            AddrMode::IndirectX => {
+                let pointer = self.read(arg);
+                self.dummy_read(pointer as u16);
+                Operand::Address(self.get_indirect_addr(pointer.wrapping_add(self.x)))
+            }
+            AddrMode::IndirectY => {
+                let pointer = self.read(arg);
+                let base = self.get_indirect_addr(pointer);
+                return self.indexed(op, base, self.y);
+            }
+        };
 
-    /// AND $4400
-    fn and2d(&mut self, operand: u16) {
-        let operand = self.ram[operand as usize];
-        self.and29(operand);
+        (operand, false)
     }
 
-    /// AND $4400,X
-    fn and3d(&mut self, operand: u16) {
-        let operand = self.ram[(operand.wrapping_add(self.x as u16)) as usize];
-        self.and29(operand);
-    }
+    /// the CPU first reads from the address with only its low byte indexed, then fixes the
+    /// high byte. reads that did not cross a page skip the fix, everything else always pays for it.
+    fn indexed(&mut self, op: Opcode, base: u16, index: u8) -> (Operand, bool) {
+        let addr = base.wrapping_add(index as u16);
+        let page_crossed = base & 0xFF00 != addr & 0xFF00;
+        if page_crossed || !op.page_penalty {
+            self.dummy_read((base & 0xFF00) | (addr & 0x00FF));
+        }
+        (Operand::Address(addr), page_crossed)
+    }
+
+    /// runs an instruction, returns the extra cycles taken by branches
+    fn execute(&mut self, mnemonic: Mnemonic, operand: Operand) -> u64 {
+        match mnemonic {
+            Mnemonic::Adc => self.adc(operand),
+            Mnemonic::And => self.and(operand),
+            Mnemonic::Asl => {
+                self.asl(operand);
+            }
+            Mnemonic::Bcc => return self.branch(self.carry == 0, operand),
+            Mnemonic::Bcs => return self.branch(self.carry == 1, operand),
+            Mnemonic::Beq => return self.branch(self.zero, operand),
+            Mnemonic::Bit => self.bit(operand),
+            Mnemonic::Bmi => return self.branch(self.negative, operand),
+            Mnemonic::Bne => return self.branch(!self.zero, operand),
+            Mnemonic::Bpl => return self.branch(!self.negative, operand),
+            Mnemonic::Brk => self.brk(),
+            Mnemonic::Bvc => return self.branch(!self.overflow, operand),
+            Mnemonic::Bvs => return self.branch(self.overflow, operand),
+            Mnemonic::Clc => self.carry = 0,
+            Mnemonic::Cld => self.decimal = false,
+            Mnemonic::Cli => self.interrupt_disable = false,
+            Mnemonic::Clv => self.overflow = false,
+            Mnemonic::Cmp => self.compare(self.a, operand),
+            Mnemonic::Cpx => self.compare(self.x, operand),
+            Mnemonic::Cpy => self.compare(self.y, operand),
+            Mnemonic::Dec => {
+                self.dec(operand);
+            }
+            Mnemonic::Dex => {
+                self.x = self.x.wrapping_sub(1);
+                self.set_zn(self.x);
+            }
+            Mnemonic::Dey => {
+                self.y = self.y.wrapping_sub(1);
+                self.set_zn(self.y);
+            }
+            Mnemonic::Eor => self.eor(operand),
+            Mnemonic::Inc => {
+                self.inc(operand);
+            }
+            Mnemonic::Inx => {
+                self.x = self.x.wrapping_add(1);
+                self.set_zn(self.x);
+            }
+            Mnemonic::Iny => {
+                self.y = self.y.wrapping_add(1);
+                self.set_zn(self.y);
+            }
+            Mnemonic::Jmp => self.jmp(operand),
+            Mnemonic::Jsr => self.jsr(operand),
+            Mnemonic::Lda => {
+                self.a = self.read_operand(operand);
+                self.set_zn(self.a);
+            }
+            Mnemonic::Ldx => {
+                self.x = self.read_operand(operand);
+                self.set_zn(self.x);
+            }
+            Mnemonic::Ldy => {
+                self.y = self.read_operand(operand);
+                self.set_zn(self.y);
+            }
+            Mnemonic::Lsr => {
+                self.lsr(operand);
+            }
+            Mnemonic::Nop => {
+                // unofficial NOPs with operands still read them
+                if let Operand::Address(addr) = operand {
+                    self.dummy_read(addr);
+                }
+            }
+            Mnemonic::Ora => self.ora(operand),
+            Mnemonic::Pha => self.push_to_stack(self.a),
+            Mnemonic::Php => self.php(),
+            Mnemonic::Pla => {
+                self.stack_dummy_read();
+                self.a = self.pop_from_stack();
+                self.set_zn(self.a);
+            }
+            Mnemonic::Plp => {
+                self.stack_dummy_read();
+                let status = self.pop_from_stack();
+                self.set_status(status);
+            }
+            Mnemonic::Rol => {
+                self.rol(operand);
+            }
+            Mnemonic::Ror => {
+                self.ror(operand);
+            }
+            Mnemonic::Rti => self.rti(),
+            Mnemonic::Rts => self.rts(),
+            Mnemonic::Sbc => self.sbc(operand),
+            Mnemonic::Sec => self.carry = 1,
+            // the 2A03 has no decimal mode, but the flag itself still works
+            Mnemonic::Sed => self.decimal = true,
+            Mnemonic::Sei => self.interrupt_disable = true,
+            Mnemonic::Sta => self.write_operand(operand, self.a),
+            Mnemonic::Stx => self.write_operand(operand, self.x),
+            Mnemonic::Sty => self.write_operand(operand, self.y),
+            Mnemonic::Tax => {
+                self.x = self.a;
+                self.set_zn(self.x);
+            }
+            Mnemonic::Tay => {
+                self.y = self.a;
+                self.set_zn(self.y);
+            }
+            Mnemonic::Tsx => {
+                self.x = self.sp;
+                self.set_zn(self.x);
+            }
+            Mnemonic::Txa => {
+                self.a = self.x;
+                self.set_zn(self.a);
+            }
+            Mnemonic::Txs => self.sp = self.x,
+            Mnemonic::Tya => {
+                self.a = self.y;
+                self.set_zn(self.a);
+            }
+
+            // unofficial
+            Mnemonic::Alr => {
+                self.and(operand);
+                self.lsr(Operand::Accumulator);
+            }
+            Mnemonic::Anc => {
+                self.and(operand);
+                self.carry = self.a >> 7;
+            }
+            Mnemonic::Arr => self.arr(operand),
+            Mnemonic::Axs => self.axs(operand),
+            Mnemonic::Dcp => {
+                let value = self.dec(operand);
+                self.compare(self.a, Operand::Immediate(value));
+            }
+            Mnemonic::Isc => {
+                let value = self.inc(operand);
+                self.sbc(Operand::Immediate(value));
+            }
+            Mnemonic::Kil => self.kil(),
+            Mnemonic::Las => {
+                let value = self.read_operand(operand) & self.sp;
+                self.a = value;
+                self.x = value;
+                self.sp = value;
+                self.set_zn(value);
+            }
+            Mnemonic::Lax => self.lax(operand),
+            Mnemonic::Rla => {
+                let value = self.rol(operand);
+                self.and(Operand::Immediate(value));
+            }
+            Mnemonic::Rra => {
+                let value = self.ror(operand);
+                self.adc(Operand::Immediate(value));
+            }
+            Mnemonic::Sax => self.write_operand(operand, self.a & self.x),
+            Mnemonic::Sha => self.store_high(operand, self.y, self.a & self.x),
+            Mnemonic::Shx => self.store_high(operand, self.y, self.x),
+            Mnemonic::Shy => self.store_high(operand, self.x, self.y),
+            Mnemonic::Slo => {
+                let value = self.asl(operand);
+                self.ora(Operand::Immediate(value));
+            }
+            Mnemonic::Sre => {
+                let value = self.lsr(operand);
+                self.eor(Operand::Immediate(value));
+            }
+            Mnemonic::Tas => {
+                self.sp = self.a & self.x;
+                self.store_high(operand, self.y, self.sp);
+            }
+            Mnemonic::Xaa => {
+                // unstable, the magic constant depends on the chip and its temperature
+                self.a = (self.a | 0xEE) & self.x & self.read_operand(operand);
+                self.set_zn(self.a);
+            }
+        }
 
-    /// AND $4400,Y
-    fn and39(&mut self, operand: u16) {
-        let operand = self.ram[(operand.wrapping_add(self.y as u16)) as usize];
-        self.and29(operand);
+        0
     }
 
-    /// AND ($44,X)
-    fn and21(&mut self, operand: u8) {
-        let addr = self.get_indirect_addr(operand.wrapping_add(self.x));
-        let operand = self.ram[addr as usize];
-        self.and29(operand);
+    fn read_operand(&mut self, operand: Operand) -> u8 {
+        match operand {
+            Operand::Accumulator => self.a,
+            Operand::Immediate(value) => value,
+            Operand::Address(addr) => self.read(addr),
+            Operand::Implied => unreachable!("implied instructions have no operand"),
+        }
     }
 
-    /// AND ($44),Y
-    fn and31(&mut self, operand: u8) {
-        let addr = self.get_indirect_addr(operand);
-        let operand = self.ram[(addr.wrapping_add(self.y as u16)) as usize];
-        self.and29(operand);
+    fn write_operand(&mut self, operand: Operand, val: u8) {
+        match operand {
+            Operand::Accumulator => self.a = val,
+            Operand::Address(addr) => self.write(addr, val),
+            Operand::Implied | Operand::Immediate(_) => {
+                unreachable!("cannot write to {operand:?}")
+            }
+        }
     }
 
-    /// ASL A
-    fn asl0a(&mut self) {
-        self.carry = (self.a & 0b1000_0000) >> 7;
-        self.a <<= 1;
-        self.a &= 0b1111_1110;
-
-        self.zero = self.a == 0;
-        self.negative = (self.a & 0b1000_0000) == 0b1000_0000;
+    /// reads memory, taking a cycle in cycle stepped mode
+    fn read(&mut self, addr: u16) -> u8 {
+        if self.timing == Timing::Cycle {
+            self.dma(addr);
+            self.bus.tick();
+        }
+        self.bus.read(addr)
     }
 
-    /// ASL $4400
-    fn asl0e(&mut self, operand: u16) {
-        let operand = operand as usize;
-        self.carry = (self.ram[operand] & 0b1000_0000) >> 7;
-        self.ram[operand] <<= 1;
-        self.ram[operand] &= 0b1111_1110;
+    /// writes memory, taking a cycle in cycle stepped mode
+    fn write(&mut self, addr: u16, val: u8) {
+        if self.timing == Timing::Cycle {
+            self.bus.tick();
+        }
+        self.bus.write(addr, val);
+    }
+
+    /// halts for any pending DMA, `addr` is what the CPU was about to read
+    ///
+    /// DMA can only halt the CPU on a read and the read keeps being repeated while it is
+    /// halted, which matters for registers like $2007 that change on every read. The
+    /// controller ports see back to back reads as a single one, so only the first counts.
+    /// OAM DMA reads on even cycles and writes on odd ones, a DMC fetch takes the place of
+    /// an OAM read and needs a halt and a dummy cycle first.
+    /// https://www.nesdev.org/wiki/DMA
+    fn dma(&mut self, addr: u16) {
+        let oam_page = self.bus.take_oam_dma();
+        let mut dmc = self.bus.dmc_dma_request();
+        if oam_page.is_none() && dmc.is_none() {
+            return;
+        }
 
-        self.zero = self.ram[operand] == 0;
-        self.negative = (self.ram[operand] & 0b1000_0000) == 0b1000_0000;
-    }
+        let repeat_reads = !matches!(
+            addr as usize,
+            mmap::apu_io_registers::JOY1 | mmap::apu_io_registers::JOY2
+        );
+        let mut oam_src = oam_page.map(|page| (page as u16) << 8);
+        let mut oam_latch = None;
+        // cycles the DMC still has to wait before it can fetch
+        let mut dmc_wait: u8 = if dmc.is_some() { 1 } else { 0 };
+        let mut cycle = self.bus.cycles() + 1;
+
+        // the halt cycle, the CPU's read goes through and is thrown away
+        self.dma_tick();
+        self.bus.read(addr);
+
+        loop {
+            if dmc.is_none() {
+                dmc = self.bus.dmc_dma_request();
+                if dmc.is_some() {
+                    dmc_wait = 2;
+                }
+            }
+            if dmc.is_none() && oam_src.is_none() && oam_latch.is_none() {
+                break;
+            }
 
-    /// ASL $44
-    fn asl06(&mut self, operand: u8) {
-        self.asl0e(operand as u16);
+            self.dma_tick();
+            let get = cycle.is_multiple_of(2);
+            match (dmc, oam_src, oam_latch) {
+                (Some(dmc_addr), _, _) if get && dmc_wait == 0 => {
+                    let val = self.bus.read(dmc_addr);
+                    self.bus.complete_dmc_dma(val);
+                    dmc = None;
+                }
+                (_, Some(src), _) if get => {
+                    oam_latch = Some(self.bus.read(src));
+                    oam_src = (src & 0xff != 0xff).then_some(src + 1);
+                }
+                (_, _, Some(val)) if !get => {
+                    self.bus.write(mmap::ppu::OAM_DATA as u16, val);
+                    oam_latch = None;
+                }
+                // waiting on the DMC or for the cycles to line up
+                _ if repeat_reads => {
+                    self.bus.read(addr);
+                }
+                _ => (),
+            }
+            dmc_wait = dmc_wait.saturating_sub(1);
+            cycle += 1;
+        }
     }
 
-    /// ASL $44,X
-    fn asl16(&mut self, operand: u8) {
-        self.asl06(operand.wrapping_add(self.x));
+    /// one cycle the CPU spends halted, the DMA decides what goes on the bus
+    fn dma_tick(&mut self) {
+        self.stalled += 1;
+        if self.timing == Timing::Cycle {
+            self.bus.tick();
+        }
     }
 
-    /// ASL $4400, X
-    fn asl1e(&mut self, operand: u16) {
-        self.asl0e(operand.wrapping_add(self.x as u16));
+    /// reads a little endian word
+    fn read_u16(&mut self, addr: u16) -> u16 {
+        let lo = self.read(addr) as u16;
+        let hi = self.read(addr.wrapping_add(1)) as u16;
+        hi << 8 | lo
     }
 
-    /// BCC $44
-    fn bcc90(&mut self, operand: u8) {
-        if self.carry == 0 {
-            self.pc = self.pc.wrapping_add(operand as u16);
+    /// a read whose value is thrown away, only performed in cycle stepped mode
+    /// where its side effects (like acknowledging $2002) happen at the right time
+    fn dummy_read(&mut self, addr: u16) {
+        if self.timing == Timing::Cycle {
+            self.read(addr);
         }
     }
 
-    /// BCS $44
-    fn bcsb0(&mut self, operand: u8) {
-        if self.carry == 1 {
-            self.pc = self.pc.wrapping_add(operand as u16);
+    /// a write that is immediately overwritten, only performed in cycle stepped mode
+    fn dummy_write(&mut self, addr: u16, val: u8) {
+        if self.timing == Timing::Cycle {
+            self.write(addr, val);
         }
     }
 
-    /// BEQ $44
-    fn beqf0(&mut self, operand: u8) {
-        if self.zero {
-            self.pc = self.pc.wrapping_add(operand as u16);
+    /// read-modify-write instructions write the unmodified value back before the result
+    fn write_back(&mut self, operand: Operand, val: u8) {
+        if let Operand::Address(addr) = operand {
+            self.dummy_write(addr, val);
         }
     }
 
-    /// helper function for BIT instructions
-    fn bit_helper(&mut self, operand: u16) {
-        let value = self.ram[operand as usize] & self.a;
-
-        self.zero = value == 0;
-        self.negative = value & 0b1000_0000 == 0b1000_0000;
-        self.overflow = value & 0b0100_0000 == 0b0100_0000;
-    }
-
-    /// BIT $44
-    fn bit24(&mut self, operand: u8) {
-        self.bit_helper(operand as u16);
-    }
-
-    /// BIT $4400
-    fn bit2c(&mut self, operand: u16) {
-        self.bit_helper(operand);
+    /// updates the zero and negative flags from a result
+    fn set_zn(&mut self, val: u8) {
+        self.zero = val == 0;
+        self.negative = (val & 0b1000_0000) == 0b1000_0000;
     }
 
-    /// BMI $44
-    fn bmi30(&mut self, operand: u8) {
-        if self.negative {
-            self.pc = self.pc.wrapping_add(operand as u16);
+    /// services a pending interrupt once an instruction is done, returns the cycles it took
+    ///
+    /// `interrupt_disable` is the I flag from before the instruction ran. CLI, SEI and PLP
+    /// change the flag after the CPU has already polled, delaying their effect by one instruction.
+    fn poll_interrupts(&mut self, opcode: u8, interrupt_disable: bool) -> u64 {
+        if self.bus.take_nmi() {
+            self.nmi_pending = true;
         }
-    }
+        let interrupt_disable = match opcode {
+            // CLI, SEI, PLP
+            0x58 | 0x78 | 0x28 => interrupt_disable,
+            _ => self.interrupt_disable,
+        };
 
-    /// BNE $44
-    fn bned0(&mut self, operand: u8) {
-        if !self.zero {
-            self.pc = self.pc.wrapping_add(operand as u16);
+        if self.nmi_pending {
+            self.interrupt(Interrupt::Nmi);
+            7
+        } else if !interrupt_disable && self.irq_asserted() {
+            self.interrupt(Interrupt::Irq);
+            7
+        } else {
+            0
         }
     }
 
-    /// BPL $44
-    fn bpl10(&mut self, operand: u8) {
-        if !self.negative {
-            self.pc = self.pc.wrapping_add(operand as u16);
+    /// pushes PC and P then jumps through the interrupt's vector
+    ///
+    /// an NMI that shows up before the vector is fetched hijacks BRK and IRQ,
+    /// they then jump to the NMI handler but still push their own B flag
+    fn interrupt(&mut self, kind: Interrupt) {
+        if kind != Interrupt::Brk {
+            // the opcode fetch and the following read are discarded
+            self.dummy_read(self.pc);
+            self.dummy_read(self.pc);
         }
-    }
 
-    /// BRK
-    fn brk00(&mut self) {
         let pc_lsb = (self.pc & 0x00FF) as u8;
         let pc_msb = ((self.pc & 0xFF00) >> 8) as u8;
         self.push_to_stack(pc_msb);
         self.push_to_stack(pc_lsb);
-        self.push_to_stack(self.get_status());
 
-        let irq: u16 = self.ram[mmap::cpu::irq_brk::START] as u16
-            | (self.ram[mmap::cpu::irq_brk::END] as u16) << 8;
+        let status = if kind == Interrupt::Brk {
+            self.get_status() | 0b0001_0000
+        } else {
+            self.get_status() & !0b0001_0000
+        };
+        self.push_to_stack(status);
+        self.interrupt_disable = true;
 
-        self.pc = irq;
-        self.b = true;
+        let vector = if self.nmi_pending {
+            self.nmi_pending = false;
+            mmap::cpu::nmi::START
+        } else {
+            mmap::cpu::irq_brk::START
+        };
+        self.pc = self.read_u16(vector as u16);
     }
 
-    /// BVC $44
-    fn bvc50(&mut self, operand: u8) {
-        if !self.overflow {
-            self.pc = self.pc.wrapping_add(operand as u16);
+    /// encodes the status flags into a single byte
+    fn get_status(&self) -> u8 {
+        let mut status_code: u8 = 0;
+        if self.negative {
+            status_code |= 0b1000_0000;
         }
-    }
 
-    /// BVS $44
-    fn bvs70(&mut self, operand: u8) {
         if self.overflow {
-            self.pc = self.pc.wrapping_add(operand as u16);
-        }
-    }
-
-    /// CLC
-    fn clc18(&mut self) {
-        self.carry = 0;
-    }
-
-    /// CLD
-    fn cldd8(&mut self) {
-        self.decimal = false;
-    }
-
-    /// CLI
-    fn cli58(&mut self) {
-        self.interrupt_disable = false;
-    }
-
-    /// CLV
-    fn clvb8(&mut self) {
-        self.overflow = false;
-    }
-
-    fn cmp_helper(&mut self, operand: u16) {
-        let acc = self.a as u16;
-        let res = acc.wrapping_sub(operand);
-        self.carry = if acc >= operand { 1 } else { 0 };
-
-        self.zero = acc == operand;
-        self.negative = (res & 0b1000_0000) == 0b1000_0000;
-        self.carry = if acc >= operand { 1 } else { 0 };
-    }
-
-    /// CMP #$44
-    fn cmpc9(&mut self, operand: u8) {
-        self.cmp_helper(operand as u16);
-    }
-
-    /// CMP $44
-    fn cmpc5(&mut self, operand: u8) {
-        self.cmpc9(self.ram[operand as usize]);
-    }
-
-    /// CMP $44,X
-    fn cmpd5(&mut self, operand: u8) {
-        let operand = self.ram[(operand.wrapping_add(self.x)) as usize];
-        self.cmpc9(operand);
-    }
-
-    /// CMP $4400
-    fn cmpcd(&mut self, operand: u16) {
-        self.cmpc9(self.ram[operand as usize]);
-    }
-
-    /// CMP $4400,X
-    fn cmpdd(&mut self, operand: u16) {
-        let operand = self.ram[(operand.wrapping_add(self.x as u16)) as usize];
-        self.cmpc9(operand);
-    }
-
-    /// CMP $4400,Y
-    fn cmpd9(&mut self, operand: u16) {
-        let operand = self.ram[(operand.wrapping_add(self.y as u16)) as usize];
-        self.cmpc9(operand);
-    }
-
-    /// CMP ($44,X)
-    fn cmpc1(&mut self, operand: u8) {
-        let operand = self.get_indirect_addr(operand.wrapping_add(self.x));
-        self.cmp_helper(operand);
-    }
-
-    /// CMP ($44),Y
-    fn cmpd1(&mut self, operand: u8) {
-        let operand = self.get_indirect_addr(operand);
-        self.cmp_helper(operand.wrapping_add(self.y as u16));
-    }
-
-    /// CPX #$44
-    fn cpxe0(&mut self, operand: u8) {
-        self.carry = if self.x >= operand { 1 } else { 0 };
-        self.zero = self.x == operand;
-        let res = self.x.wrapping_sub(operand);
-        self.negative = (res & 0b1000_0000) == 0b1000_0000;
-    }
-
-    /// CPX $44
-    fn cpxe4(&mut self, operand: u8) {
-        self.cpxe0(self.ram[operand as usize]);
-    }
-
-    /// CPX $4400
-    fn cpxec(&mut self, operand: u16) {
-        self.cpxe0(self.ram[operand as usize]);
-    }
-
-    /// CPY #$44
-    fn cpyc0(&mut self, operand: u8) {
-        self.carry = if self.y >= operand { 1 } else { 0 };
-        self.zero = self.y == operand;
-        let res = self.y.wrapping_sub(operand);
-        self.negative = (res & 0b1000_0000) == 0b1000_0000;
-    }
-
-    /// CPY $44
-    fn cpyc4(&mut self, operand: u8) {
-        self.cpyc0(self.ram[operand as usize]);
-    }
-
-    /// CPY $4400
-    fn cpycc(&mut self, operand: u16) {
-        self.cpyc0(self.ram[operand as usize]);
-    }
-
-    /// DEC $44
-    fn decc6(&mut self, operand: u8) {
-        self.ram[operand as usize] -= 1;
-
-        let res = self.ram[operand as usize];
-        if res == 0 {
-            self.zero = true;
-        } else {
-            self.zero = false;
+            status_code |= 0b0100_0000;
         }
 
-        self.negative = (res & 0b1000_0000) == 0b1000_0000;
-    }
-
-    /// DEC $44,X
-    fn decd6(&mut self, operand: u8) {
-        self.decc6(operand.wrapping_add(self.x));
-    }
+        // bit 5 is always set, B (bit 4) is not stored anywhere in the CPU
+        status_code |= 0b0010_0000;
 
-    /// DEC $4400
-    fn decce(&mut self, operand: u16) {
-        self.ram[operand as usize] -= 1;
+        if self.decimal {
+            status_code |= 0b0000_1000;
+        }
 
-        let res = self.ram[operand as usize];
-        if res == 0 {
-            self.zero = true;
-        } else {
-            self.zero = false;
+        if self.interrupt_disable {
+            status_code |= 0b0000_0100;
         }
 
-        self.negative = (res & 0b1000_0000) == 0b1000_0000;
-    }
+        if self.zero {
+            status_code |= 0b0000_0010;
+        }
 
-    /// DEC $4400,X
-    fn decde(&mut self, operand: u16) {
-        self.decce(operand.wrapping_add(self.x as u16));
-    }
+        if self.carry == 1 {
+            status_code |= 0b0000_0001;
+        }
 
-    /// DEX
-    fn dexca(&mut self) {
-        self.x -= 1;
-        self.zero = self.x == 0;
-        self.negative = (self.x & 0b1000_0000) == 0b1000_0000;
+        status_code
     }
 
-    /// DEY
-    fn dey88(&mut self) {
-        self.y -= 1;
-        self.zero = self.y == 0;
-        self.negative = (self.y & 0b1000_0000) == 0b1000_0000;
+    /// decodes status flag byte into their corresponding struct fields
+    fn set_status(&mut self, status_byte: u8) {
+        self.negative = status_byte & 0b1000_0000 == 0b1000_0000;
+        self.overflow = status_byte & 0b0100_0000 == 0b0100_0000;
+        self.decimal = status_byte & 0b0000_1000 == 0b0000_1000;
+        self.interrupt_disable = status_byte & 0b0000_0100 == 0b0000_0100;
+        self.zero = status_byte & 0b0000_0010 == 0b0000_0010;
+        self.carry = status_byte & 0b0000_0001;
     }
 
-    /// EOR #$44
-    fn eor49(&mut self, operand: u8) {
-        self.a ^= operand;
-        self.zero = self.a == 0;
-        self.negative = (0b1000_0000 & self.a) == 0b1000_0000;
+    /// reads a pointer from the zero page, wrapping around within it
+    fn get_indirect_addr(&mut self, operand: u8) -> u16 {
+        let lsb = self.read(operand as u16) as u16;
+        let msb = self.read(operand.wrapping_add(1) as u16) as u16;
+        msb << 8 | lsb
     }
 
-    /// EOR $44
-    fn eor45(&mut self, operand: u8) {
-        self.eor49(self.ram[operand as usize]);
+    fn push_to_stack(&mut self, val: u8) {
+        self.write((mmap::ram::stack::START + self.sp as usize) as u16, val);
+        self.sp = self.sp.wrapping_sub(1);
     }
 
-    /// EOR $44,X
-    fn eor55(&mut self, operand: u8) {
-        self.eor49(self.ram[(operand.wrapping_add(self.x)) as usize]);
+    fn pop_from_stack(&mut self) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
+        self.read((mmap::ram::stack::START + self.sp as usize) as u16)
     }
 
-    /// EOR $4400
-    fn eor4d(&mut self, operand: u16) {
-        self.eor49(self.ram[operand as usize]);
+    /// pulling takes an extra cycle to increment SP, spent reading the top of the stack
+    fn stack_dummy_read(&mut self) {
+        self.dummy_read((mmap::ram::stack::START + self.sp as usize) as u16);
     }
 
-    /// EOR $4400,X
-    fn eor5d(&mut self, operand: u16) {
-        self.eor49(self.ram[(operand.wrapping_add(self.x as u16)) as usize]);
-    }
+    // --- INSTRUCTIONS ---
 
-    /// EOR $4400,Y
-    fn eor59(&mut self, operand: u16) {
-        self.eor49(self.ram[(operand.wrapping_add(self.y as u16)) as usize]);
+    /// ADC
+    fn adc(&mut self, operand: Operand) {
+        let value = self.read_operand(operand);
+        self.add_with_carry(value);
     }
 
-    /// EOR ($44,X)
-    fn eor41(&mut self, operand: u8) {
-        let addr = self.get_indirect_addr(operand.wrapping_add(self.x));
-        self.eor49(self.ram[addr as usize]);
-    }
+    /// the ALU's adder, the NES has no decimal mode so D is ignored
+    fn add_with_carry(&mut self, value: u8) {
+        let sum = self.a as u16 + value as u16 + self.carry as u16;
+        let res = sum as u8;
 
-    /// EOR ($44),Y
-    fn eor51(&mut self, operand: u8) {
-        let addr = self.get_indirect_addr(operand);
-        self.eor49(self.ram[(addr.wrapping_add(self.y as u16)) as usize]);
+        self.carry = (sum >> 8) as u8;
+        // the sign of the result is wrong when both inputs share a sign it doesn't
+        self.overflow = (self.a ^ res) & (value ^ res) & 0b1000_0000 != 0;
+        self.a = res;
+        self.set_zn(self.a);
+    }
+
+    /// AND
+    fn and(&mut self, operand: Operand) {
+        self.a &= self.read_operand(operand);
+        self.set_zn(self.a);
+    }
+
+    /// ASL A, ASL $4400
+    fn asl(&mut self, operand: Operand) -> u8 {
+        let value = self.read_operand(operand);
+        self.write_back(operand, value);
+        self.carry = (value & 0b1000_0000) >> 7;
+        let value = value << 1;
+        self.write_operand(operand, value);
+        self.set_zn(value);
+        value
+    }
+
+    /// BCC, BCS, BEQ, BMI, BNE, BPL, BVC, BVS
+    ///
+    /// taken branches cost a cycle, plus one more when landing on another page
+    fn branch(&mut self, condition: bool, operand: Operand) -> u64 {
+        match (condition, operand) {
+            (true, Operand::Address(target)) => {
+                let page_crossed = self.pc & 0xFF00 != target & 0xFF00;
+                // the next opcode is fetched while the offset is added to PC
+                self.dummy_read(self.pc);
+                if page_crossed {
+                    self.dummy_read((self.pc & 0xFF00) | (target & 0x00FF));
+                }
+                self.pc = target;
+                1 + page_crossed as u64
+            }
+            _ => 0,
+        }
     }
 
-    /// INC $4400
-    fn incee(&mut self, operand: u16) {
-        self.ram[operand as usize] = self.ram[operand as usize].wrapping_add(1);
-        let res = self.ram[operand as usize];
-        self.zero = res == 0;
-        self.negative = (0b1000_0000 & res) == 0b1000_0000;
-    }
+    /// BIT
+    fn bit(&mut self, operand: Operand) {
+        let value = self.read_operand(operand);
 
-    /// INC $44
-    fn ince6(&mut self, operand: u8) {
-        self.incee(operand as u16);
+        self.zero = value & self.a == 0;
+        self.negative = value & 0b1000_0000 == 0b1000_0000;
+        self.overflow = value & 0b0100_0000 == 0b0100_0000;
     }
 
-    /// INC $44,X
-    fn incf6(&mut self, operand: u8) {
-        self.ince6(operand.wrapping_add(self.x));
+    /// BRK
+    fn brk(&mut self) {
+        // BRK skips over a padding byte, so it returns 2 bytes after the opcode
+        self.pc = self.pc.wrapping_add(1);
+        self.interrupt(Interrupt::Brk);
     }
 
-    /// INC $4400,X
-    fn incfe(&mut self, operand: u16) {
-        self.incee(operand.wrapping_add(self.x as u16));
+    /// CMP, CPX, CPY
+    fn compare(&mut self, register: u8, operand: Operand) {
+        let value = self.read_operand(operand);
+        self.carry = if register >= value { 1 } else { 0 };
+        self.set_zn(register.wrapping_sub(value));
     }
 
-    /// INX
-    fn inxe8(&mut self) {
-        self.x = self.x.wrapping_add(1);
-        self.zero = self.x == 0;
-        self.negative = (0b1000_0000 & self.x) == 0b1000_0000;
+    /// DEC
+    fn dec(&mut self, operand: Operand) -> u8 {
+        let value = self.read_operand(operand);
+        self.write_back(operand, value);
+        let res = value.wrapping_sub(1);
+        self.write_operand(operand, res);
+        self.set_zn(res);
+        res
     }
 
-    /// INY
-    fn inyc8(&mut self) {
-        self.y = self.y.wrapping_add(1);
-        self.zero = self.y == 0;
-        self.negative = (0b1000_0000 & self.y) == 0b1000_0000;
+    /// EOR
+    fn eor(&mut self, operand: Operand) {
+        self.a ^= self.read_operand(operand);
+        self.set_zn(self.a);
     }
 
-    /// JMP $5597
-    fn jmp4c(&mut self, operand: u16) {
-        self.pc = operand;
+    /// INC
+    fn inc(&mut self, operand: Operand) -> u8 {
+        let value = self.read_operand(operand);
+        self.write_back(operand, value);
+        let res = value.wrapping_add(1);
+        self.write_operand(operand, res);
+        self.set_zn(res);
+        res
     }
 
-    /// JMP ($5597)
-    fn jmp6c(&mut self, operand: u16) {
-        let addr2: u16 = self.ram[operand as usize] as u16;
-
-        // introducing paging bug
-        let operand = if operand & 0x00FF == 0x00FF {
-            operand & 0xFF00
-        } else {
-            operand
-        };
-
-        let addr1: u16 = (self.ram[(operand.wrapping_add(1)) as usize] as u16) << 8;
-        self.pc = addr1 | addr2;
+    /// JMP $5597, JMP ($5597)
+    fn jmp(&mut self, operand: Operand) {
+        if let Operand::Address(addr) = operand {
+            self.pc = addr;
+        }
     }
 
     /// JSR $5597
-    fn jsr20(&mut self, operand: u16) {
-        self.pc -= 1;
-        let lsb = self.pc & 0x00FF;
-        let msb = (self.pc & 0xFF00) >> 8;
-        self.push_to_stack(msb as u8);
-        self.push_to_stack(lsb as u8);
-        self.pc = operand;
+    fn jsr(&mut self, operand: Operand) {
+        // pushes the address of JSR's last byte, RTS adds the missing 1 back
+        let ret = self.pc.wrapping_sub(1);
+        self.stack_dummy_read();
+        self.push_to_stack((ret >> 8) as u8);
+        self.push_to_stack(ret as u8);
+        self.jmp(operand);
     }
 
-    /// LDA #$44
-    fn ldaa9(&mut self, operand: u8) {
-        self.a = operand;
-        self.zero = operand == 0;
-        self.negative = (0b1000_0000 & operand) == 0b1000_0000;
+    /// LSR A, LSR $4400
+    fn lsr(&mut self, operand: Operand) -> u8 {
+        let value = self.read_operand(operand);
+        self.write_back(operand, value);
+        self.carry = value & 0b0000_0001;
+        let value = value >> 1;
+        self.write_operand(operand, value);
+        self.set_zn(value);
+        value
     }
 
-    /// LDA $44
-    fn ldaa5(&mut self, operand: u8) {
-        self.ldaa9(self.ram[operand as usize]);
+    /// ORA
+    fn ora(&mut self, operand: Operand) {
+        self.a |= self.read_operand(operand);
+        self.set_zn(self.a);
     }
 
-    /// LDA $44,X
-    fn ldab5(&mut self, operand: u8) {
-        self.ldaa9(self.ram[(operand.wrapping_add(self.x)) as usize]);
+    /// PHP
+    fn php(&mut self) {
+        // B only exists in copies of P pushed by PHP and BRK
+        self.push_to_stack(self.get_status() | 0b0001_0000);
     }
 
-    /// LDA $4400
-    fn ldaad(&mut self, operand: u16) {
-        self.ldaa9(self.ram[operand as usize]);
-    }
+    /// ROL A, ROL $4400
+    fn rol(&mut self, operand: Operand) -> u8 {
+        let old = self.read_operand(operand);
+        self.write_back(operand, old);
+        let value = (old << 1) | self.carry;
+        self.write_operand(operand, value);
 
-    /// LDA $4400,X
-    fn ldabd(&mut self, operand: u16) {
-        self.ldaa9(self.ram[operand.wrapping_add(self.x as u16) as usize]);
+        self.carry = old >> 7;
+        self.set_zn(value);
+        value
     }
 
-    /// LDA $4400,Y
-    fn ldab9(&mut self, operand: u16) {
-        self.ldaa9(self.ram[operand.wrapping_add(self.y as u16) as usize]);
-    }
+    /// ROR A, ROR $4400
+    fn ror(&mut self, operand: Operand) -> u8 {
+        let old = self.read_operand(operand);
+        self.write_back(operand, old);
+        let value = (old >> 1) | (self.carry << 7);
+        self.write_operand(operand, value);
 
-    /// LDA ($44,X)
-    fn ldaa1(&mut self, operand: u8) {
-        let addr = self.get_indirect_addr(operand.wrapping_add(self.x));
-        self.ldaa9(self.ram[addr as usize]);
+        self.carry = old & 0b0000_0001;
+        self.set_zn(value);
+        value
     }
 
-    /// LDA ($44),Y
-    fn ldab1(&mut self, operand: u8) {
-        let addr = self.get_indirect_addr(operand).wrapping_add(self.y as u16);
-        self.ldaa9(self.ram[addr as usize]);
+    /// RTI
+    fn rti(&mut self) {
+        self.stack_dummy_read();
+        let status = self.pop_from_stack();
+        let pc: u16 = (self.pop_from_stack() as u16) | ((self.pop_from_stack() as u16) << 8);
+        self.set_status(status);
+        self.pc = pc;
     }
 
-    /// LDX #$44
-    fn ldxa2(&mut self, operand: u8) {
-        self.x = operand;
-
-        self.zero = operand == 0;
-        self.negative = (0b1000_0000 & operand) == 0b1000_0000;
+    /// RTS
+    fn rts(&mut self) {
+        self.stack_dummy_read();
+        let pc = self.pop_from_stack() as u16 | ((self.pop_from_stack() as u16) << 8);
+        // PC is incremented past JSR's last byte while that byte is read again
+        self.dummy_read(pc);
+        self.pc = pc.wrapping_add(1);
     }
 
-    /// LDX $44
-    fn ldxa6(&mut self, operand: u8) {
-        self.ldxa2(self.ram[operand as usize]);
+    /// SBC
+    fn sbc(&mut self, operand: Operand) {
+        // A - M - (1 - C) is A + !M + C, with C ending up set when nothing was borrowed
+        let value = self.read_operand(operand);
+        self.add_with_carry(!value);
     }
 
-    /// LDX $44,Y
-    fn ldxb6(&mut self, operand: u8) {
-        self.ldxa2(self.ram[(operand.wrapping_add(self.y)) as usize]);
-    }
+    // --- UNOFFICIAL INSTRUCTIONS ---
 
-    /// LDX $4400
-    fn ldxae(&mut self, operand: u16) {
-        self.ldxa2(self.ram[operand as usize]);
-    }
+    /// ARR #$44
+    fn arr(&mut self, operand: Operand) {
+        let value = self.a & self.read_operand(operand);
+        self.a = (value >> 1) | (self.carry << 7);
+        self.set_zn(self.a);
 
-    /// LDX $4400,Y
-    fn ldxbe(&mut self, operand: u16) {
-        self.ldxa2(self.ram[operand.wrapping_add(self.y as u16) as usize]);
+        // C and V come from the AND result as if it had gone through the adder
+        self.carry = (self.a >> 6) & 1;
+        self.overflow = ((self.a >> 6) ^ (self.a >> 5)) & 1 == 1;
     }
 
-    /// LDY #$44
-    fn ldya0(&mut self, operand: u8) {
-        self.y = operand;
-
-        self.zero = operand == 0;
-        self.negative = (0b1000_0000 & operand) == 0b1000_0000;
+    /// AXS #$44
+    fn axs(&mut self, operand: Operand) {
+        let value = self.read_operand(operand);
+        let ax = self.a & self.x;
+        // a CMP with the result stored in X, C is set when nothing was borrowed
+        self.carry = if ax >= value { 1 } else { 0 };
+        self.x = ax.wrapping_sub(value);
+        self.set_zn(self.x);
     }
 
-    /// LDY $44
-    fn ldya4(&mut self, operand: u8) {
-        self.ldya0(self.ram[operand as usize]);
+    /// KIL
+    fn kil(&mut self) {
+        // the CPU gets stuck fetching the same opcode forever
+        self.pc = self.pc.wrapping_sub(1);
+        self.halted = true;
     }
 
-    /// LDY $44,X
-    fn ldyb4(&mut self, operand: u8) {
-        self.ldya0(self.ram[(operand.wrapping_add(self.x)) as usize]);
-    }
+    /// LAX $4400, LAX #$44
+    fn lax(&mut self, operand: Operand) {
+        let value = match operand {
+            // unstable, the magic constant depends on the chip and its temperature
+            Operand::Immediate(value) => (self.a | 0xEE) & value,
+            _ => self.read_operand(operand),
+        };
+        self.a = value;
+        self.x = value;
+        self.set_zn(value);
+    }
+
+    /// SHA, SHX, SHY, TAS
+    ///
+    /// stores `value & (H + 1)`, H being the high byte of the address before it was indexed.
+    /// when indexing crosses a page that value also replaces the high byte of the address.
+    fn store_high(&mut self, operand: Operand, index: u8, value: u8) {
+        let Operand::Address(addr) = operand else {
+            unreachable!("{operand:?} is not an address")
+        };
 
-    /// LDY $4400
-    fn ldyac(&mut self, operand: u16) {
-        self.ldya0(self.ram[operand as usize]);
+        let base = addr.wrapping_sub(index as u16);
+        let value = value & ((base >> 8) as u8).wrapping_add(1);
+        let addr = if base & 0xFF00 != addr & 0xFF00 {
+            (value as u16) << 8 | (addr & 0x00FF)
+        } else {
+            addr
+        };
+        self.write(addr, value);
     }
+}
 
-    /// LDY $4400,X
-    fn ldybc(&mut self, operand: u16) {
-        self.ldya0(self.ram[operand.wrapping_add(self.x as u16) as usize]);
-    }
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::bus::BusDevice;
+    use crate::mapper::{Mapper, Mirroring};
+    use std::sync::{Arc, Mutex};
 
-    /// LSR A
-    fn lsr4a(&mut self) {
-        self.carry = self.a & 0b0000_0001;
-        self.a = (self.a & 0b0111_1111) >> 1;
-        self.zero = self.a == 0;
-        self.negative = false;
+    /// writable memory covering the whole cartridge space
+    struct TestCartridge {
+        mem: Vec<u8>,
+        irq: bool,
     }
 
-    /// LSR $4400
-    fn lsr4e(&mut self, operand: u16) {
-        let operand = operand as usize;
-        self.carry = self.ram[operand] & 0b0000_0001;
-        self.ram[operand] = (self.ram[operand] & 0b0111_1111) >> 1;
-        self.zero = self.ram[operand] == 0;
-        self.negative = false;
+    impl Default for TestCartridge {
+        fn default() -> Self {
+            TestCartridge {
+                mem: vec![0; 0x10000],
+                irq: false,
+            }
+        }
     }
 
-    /// LSR $44
-    fn lsr46(&mut self, operand: u8) {
-        self.lsr4e(operand as u16);
-    }
+    impl Mapper for TestCartridge {
+        fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+            Some(self.mem[addr as usize])
+        }
 
-    /// LSR $44,X
-    fn lsr56(&mut self, operand: u8) {
-        self.lsr46(operand + self.x);
-    }
+        fn cpu_write(&mut self, addr: u16, val: u8) {
+            self.mem[addr as usize] = val;
+        }
 
-    /// LSR $4400,X
-    fn lsr5e(&mut self, operand: u16) {
-        self.lsr4e(operand + self.x as u16);
-    }
+        fn cpu_peek(&self, addr: u16) -> Option<u8> {
+            Some(self.mem[addr as usize])
+        }
 
-    /// ORA #$44
-    fn ora09(&mut self, operand: u8) {
-        self.a |= operand;
-        self.zero = self.a == 0;
-        self.negative = (operand & 0b1000_0000) == 0b1000_0000;
-    }
+        fn ppu_read(&mut self, _addr: u16) -> u8 {
+            0
+        }
 
-    /// ORA $44
-    fn ora05(&mut self, operand: u8) {
-        self.ora09(self.ram[operand as usize]);
-    }
+        fn ppu_write(&mut self, _addr: u16, _val: u8) {}
 
-    /// ORA $44,X
-    fn ora15(&mut self, operand: u8) {
-        self.ora09(self.ram[(operand + self.x) as usize]);
-    }
+        fn mirroring(&self) -> Mirroring {
+            Mirroring::Horizontal
+        }
 
-    /// ORA $4400
-    fn ora0d(&mut self, operand: u16) {
-        self.ora09(self.ram[operand as usize]);
+        fn irq(&self) -> bool {
+            self.irq
+        }
     }
 
-    /// ORA $4400,X
-    fn ora1d(&mut self, operand: u16) {
-        self.ora09(self.ram[(operand + self.x as u16) as usize]);
+    /// NMI jumps to 0x9000 and IRQ/BRK to 0xa000
+    fn vectored_cpu(cartridge: TestCartridge) -> CPU {
+        let mut cpu = CPU::default();
+        cpu.bus
+            .attach_cartridge(Cartridge::new(Box::new(cartridge)));
+        cpu.bus.write(mmap::cpu::nmi::START as u16, 0x00);
+        cpu.bus.write(mmap::cpu::nmi::END as u16, 0x90);
+        cpu.bus.write(mmap::cpu::irq_brk::START as u16, 0x00);
+        cpu.bus.write(mmap::cpu::irq_brk::END as u16, 0xa0);
+        cpu.pc = 0x8000;
+        cpu
     }
 
-    /// ORA $4400,Y
-    fn ora19(&mut self, operand: u16) {
-        self.ora09(self.ram[(operand + self.y as u16) as usize]);
-    }
+    /// where test programs are loaded in RAM
+    const PROGRAM: u16 = 0x0600;
 
-    /// ORA ($44,X)
-    fn ora01(&mut self, operand: u8) {
-        let operand = self.get_indirect_addr(operand + self.x);
-        self.ora09(self.ram[operand as usize]);
+    /// assembles `source` to `PROGRAM` and points PC at it, returns the program's length
+    fn load(cpu: &mut CPU, source: &str) -> u16 {
+        let program = asm::assemble(&format!(".org ${PROGRAM:04X}\n{source}")).unwrap();
+        for (i, byte) in program.bytes.iter().enumerate() {
+            cpu.bus.write(PROGRAM + i as u16, *byte);
+        }
+        cpu.pc = PROGRAM;
+        program.bytes.len() as u16
     }
 
-    /// ORA ($44),Y
-    fn ora11(&mut self, operand: u8) {
-        let operand = self.get_indirect_addr(operand) + self.y as u16;
-        self.ora09(self.ram[operand as usize]);
+    /// runs `source` until PC leaves it
+    fn run(cpu: &mut CPU, source: &str) {
+        let len = load(cpu, source);
+        while (PROGRAM..PROGRAM + len).contains(&cpu.pc) {
+            cpu.step();
+        }
     }
 
-    /// PHA
-    fn pha48(&mut self) {
-        self.push_to_stack(self.a);
+    #[test]
+    fn step_decodes_and_times_instructions() {
+        let mut cpu = CPU::default();
+        load(
+            &mut cpu,
+            "
+                lda #$42
+                sta $0200
+                asl a
+            ",
+        );
+
+        assert!(cpu.step() == 2);
+        assert!(cpu.pc == PROGRAM + 2);
+        assert!(cpu.step() == 4);
+        assert!(cpu.pc == PROGRAM + 5);
+        assert!(cpu.bus.read(0x0200) == 0x42);
+        assert!(cpu.step() == 2);
+        assert!(cpu.pc == PROGRAM + 6);
+        assert!(cpu.a == 0x84);
+        assert!(cpu.cycles == 8);
     }
 
-    /// PHP
-    fn php08(&mut self) {
-        self.push_to_stack(self.get_status());
+    #[test]
+    fn cycle_mode_takes_one_bus_access_per_cycle() {
+        // covers every opcode with and without page crossings, taken and untaken branches
+        for opcode in 0..=255u8 {
+            for operand in [0x10, 0x80] {
+                for (index, status) in [(0x00, 0x00), (0xff, 0xff)] {
+                    let mut cpu = CPU::default();
+                    cpu.set_timing(Timing::Cycle);
+                    cpu.x = index;
+                    cpu.y = index;
+                    cpu.set_status(status);
+                    load(&mut cpu, &format!(".byte {opcode}, {operand}, $02"));
+                    cpu.bus.write(operand as u16, 0x80);
+                    cpu.bus.write(operand as u16 + 1, 0x02);
+
+                    let start = cpu.bus.cycles();
+                    let cycles = cpu.step();
+                    assert!(
+                        cpu.bus.cycles() - start == cycles,
+                        "${opcode:02X} took {cycles} cycles but {} bus accesses",
+                        cpu.bus.cycles() - start
+                    );
+                }
+            }
+        }
     }
 
-    /// PLA
-    fn pla68(&mut self) {
-        self.a = self.pop_from_stack();
-        self.negative = (self.a & 0b1000_0000) == 0b1000_0000;
-    }
+    type Log = Arc<Mutex<Vec<(char, u16, u8)>>>;
 
-    /// PLP
-    fn plp28(&mut self) {
-        let status = self.pop_from_stack();
-        self.set_status(status);
+    /// logs every access in the order it happened
+    struct Recorder {
+        log: Log,
     }
 
-    /// ROL A
-    fn rol2a(&mut self) {
-        let old = self.a;
-        self.a = (self.a << 1) | self.carry;
+    impl BusDevice for Recorder {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.log.lock().unwrap().push(('r', addr, 0));
+            0x41
+        }
 
-        self.carry = old >> 7;
-        self.zero = self.a == 0;
-        self.negative = (self.a & 0b1000_0000) == 0b1000_0000;
+        fn write(&mut self, addr: u16, val: u8) {
+            self.log.lock().unwrap().push(('w', addr, val));
+        }
     }
 
-    /// ROL $4400
-    fn rol2e(&mut self, operand: u16) {
-        let operand = operand as usize;
-        let old = self.ram[operand];
-        self.ram[operand] = (self.ram[operand] << 1) | self.carry;
-
-        self.carry = old >> 7;
-        self.zero = self.ram[operand] == 0;
-        self.negative = (self.a & 0b1000_0000) == 0b1000_0000;
+    fn recorded_cpu(timing: Timing) -> (CPU, Log) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut cpu = CPU::default();
+        cpu.set_timing(timing);
+        cpu.bus
+            .attach_apu_io(Box::new(Recorder { log: log.clone() }));
+        (cpu, log)
     }
 
-    /// ROL $44
-    fn rol26(&mut self, operand: u8) {
-        self.rol2e(operand as u16);
+    #[test]
+    fn cycle_mode_dummy_accesses() {
+        let (mut cpu, log) = recorded_cpu(Timing::Cycle);
+        // INC $4000 writes the old value back before the new one
+        run(&mut cpu, "inc $4000");
+        assert!(
+            *log.lock().unwrap() == [('r', 0x4000, 0), ('w', 0x4000, 0x41), ('w', 0x4000, 0x42)]
+        );
+
+        log.lock().unwrap().clear();
+        cpu.x = 0x01;
+        // stays on its page, no dummy read
+        run(&mut cpu, "lda $400f,x");
+        assert!(*log.lock().unwrap() == [('r', 0x4010, 0)]);
+        log.lock().unwrap().clear();
+        cpu.x = 0x10;
+        // crosses into $4108, reading $4008 on the way
+        run(&mut cpu, "lda $40f8,x");
+        assert!(*log.lock().unwrap() == [('r', 0x4008, 0)]);
+        log.lock().unwrap().clear();
+        cpu.x = 0x01;
+        // stores always do the dummy read, A got $41 left on the bus by the read of $4008
+        run(&mut cpu, "sta $4000,x");
+        assert!(*log.lock().unwrap() == [('r', 0x4001, 0), ('w', 0x4001, 0x41)]);
+
+        let (mut cpu, log) = recorded_cpu(Timing::Instruction);
+        run(&mut cpu, "inc $4000");
+        assert!(*log.lock().unwrap() == [('r', 0x4000, 0), ('w', 0x4000, 0x42)]);
+        cpu.x = 0x01;
+        run(&mut cpu, "sta $4000,x");
+        assert!(
+            *log.lock().unwrap() == [('r', 0x4000, 0), ('w', 0x4000, 0x42), ('w', 0x4001, 0x00)]
+        );
+    }
+
+    /// a CPU with OAM DMA's source page at $0200 and a PPU logging what OAM gets
+    fn oam_dma_cpu(timing: Timing) -> (CPU, Log) {
+        let (mut cpu, _) = recorded_cpu(timing);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        cpu.bus.attach_ppu(Box::new(Recorder { log: log.clone() }));
+        for i in 0..=255u8 {
+            cpu.bus.write(0x0200 + i as u16, i);
+        }
+        (cpu, log)
     }
 
-    /// ROL $44,X
-    fn rol36(&mut self, operand: u8) {
-        self.rol26(operand + self.x);
-    }
+    #[test]
+    fn oam_dma() {
+        let copied: Vec<_> = (0..=255).map(|i| ('w', 0x2004, i)).collect();
+        for timing in [Timing::Instruction, Timing::Cycle] {
+            let (mut cpu, log) = oam_dma_cpu(timing);
+            load(&mut cpu, "lda #$02\nsta $4014\nnop");
+            cpu.step();
+            cpu.step();
+            assert!(log.lock().unwrap().is_empty());
+
+            // the write landed on an odd cycle, so it takes an extra one to line up
+            assert!(cpu.step() == 2 + 514);
+            assert!(*log.lock().unwrap() == copied);
+        }
 
-    /// ROL $4400,X
-    fn rol3e(&mut self, operand: u16) {
-        self.rol2e(operand + self.x as u16);
+        let (mut cpu, log) = oam_dma_cpu(Timing::Cycle);
+        cpu.bus.write(0x0010, 0x02);
+        load(&mut cpu, "lda $10\nsta $4014\nnop");
+        cpu.step();
+        cpu.step();
+        assert!(cpu.step() == 2 + 513);
+        assert!(*log.lock().unwrap() == copied);
     }
 
-    /// ROR A
-    fn ror6a(&mut self) {
-        let old = self.a;
-        self.a = (self.a >> 1) | self.carry;
-
-        self.carry = old >> 7;
-        self.zero = self.a == 0;
-        self.negative = (self.a & 0b1000_0000) == 0b1000_0000;
+    /// an APU whose DMC asks for a byte from $C000 once it has been clocked `delay` times
+    struct Dmc {
+        log: Log,
+        delay: u64,
+        fetched: bool,
     }
 
-    /// ROR $4400
-    fn ror6e(&mut self, operand: u16) {
-        let operand = operand as usize;
-        let old = self.ram[operand];
-        self.ram[operand] = (self.ram[operand] >> 1) | self.carry;
+    impl BusDevice for Dmc {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.log.lock().unwrap().push(('r', addr, 0));
+            0x41
+        }
 
-        self.carry = old >> 7;
-        self.zero = self.ram[operand] == 0;
-        self.negative = (self.a & 0b1000_0000) == 0b1000_0000;
-    }
+        fn write(&mut self, _addr: u16, _val: u8) {}
 
-    /// ROR $44
-    fn ror66(&mut self, operand: u8) {
-        self.ror6e(operand as u16);
-    }
+        fn tick(&mut self) {
+            self.delay = self.delay.saturating_sub(1);
+        }
 
-    /// ROR $44,X
-    fn ror76(&mut self, operand: u8) {
-        self.ror66(operand + self.x);
-    }
+        fn dma_request(&self) -> Option<u16> {
+            (self.delay == 0 && !self.fetched).then_some(0xc000)
+        }
 
-    /// ROR $4400,X
-    fn ror7e(&mut self, operand: u16) {
-        self.ror6e(operand + self.x as u16);
+        fn dma_complete(&mut self, val: u8) {
+            self.log.lock().unwrap().push(('d', 0xc000, val));
+            self.fetched = true;
+        }
     }
 
-    /// RTI
-    fn rti40(&mut self) {
-        let status = self.pop_from_stack();
-        let pc: u16 = (self.pop_from_stack() as u16) | ((self.pop_from_stack() as u16) << 8);
-        self.set_status(status);
-        self.pc = pc;
+    fn dmc_cpu(delay: u64) -> (CPU, Log) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut cpu = CPU::default();
+        cpu.set_timing(Timing::Cycle);
+        cpu.bus.attach_apu_io(Box::new(Dmc {
+            log: log.clone(),
+            delay,
+            fetched: false,
+        }));
+        (cpu, log)
     }
 
-    /// RTS
-    fn rts60(&mut self) {
-        let pc = self.pop_from_stack() as u16 | ((self.pop_from_stack() as u16) << 8);
-        self.pc = pc;
+    #[test]
+    fn dmc_dma_repeats_the_halted_read() {
+        // LDA $4000 gets halted on the read of $4000, after 3 cycles fetching the instruction
+        let (mut cpu, log) = dmc_cpu(3);
+        run(&mut cpu, "lda $4000");
+        assert!(cpu.cycles == 4 + 4);
+        assert!(
+            *log.lock().unwrap()
+                == [
+                    ('r', 0x4000, 0),
+                    ('r', 0x4000, 0),
+                    ('r', 0x4000, 0),
+                    // nothing is at $C000 so the fetch reads what the halted reads left on the bus
+                    ('d', 0xc000, 0x41),
+                    ('r', 0x4000, 0),
+                ]
+        );
+
+        // the controllers only see the first of back to back reads
+        let (mut cpu, log) = dmc_cpu(3);
+        run(&mut cpu, "lda $4016");
+        assert!(cpu.cycles == 4 + 4);
+        assert!(*log.lock().unwrap() == [('r', 0x4016, 0), ('d', 0xc000, 0x41), ('r', 0x4016, 0)]);
     }
 
-    /// SBC #$44
-    fn sbce9(&mut self, operand: u8) {
-        let (acc, overflowed1) = self.a.overflowing_sub(operand);
-        let (acc, overflowed2) = acc.overflowing_sub(!self.carry & 0b0000_0001);
-
-        self.a = acc;
-        self.zero = self.a == 0;
-        self.negative = (acc & 0b1000_0000) == 0b1000_0000;
-        self.overflow = overflowed1 || overflowed2;
-
-        if overflowed1 || overflowed2 {
-            self.carry = 0;
+    #[test]
+    fn dmc_dma_during_oam_dma() {
+        let (mut cpu, log) = dmc_cpu(100);
+        let oam = Arc::new(Mutex::new(Vec::new()));
+        cpu.bus.attach_ppu(Box::new(Recorder { log: oam.clone() }));
+        for i in 0..=255u8 {
+            cpu.bus.write(0x0200 + i as u16, i);
         }
-    }
+        load(&mut cpu, "lda #$02\nsta $4014\nnop");
+        cpu.step();
+        cpu.step();
 
-    /// SBC $44
-    fn sbce5(&mut self, operand: u8) {
-        self.sbce9(self.ram[operand as usize]);
+        // the fetch takes an OAM read's place and costs another cycle to line back up
+        assert!(cpu.step() == 2 + 514 + 2);
+        assert!(matches!(log.lock().unwrap()[..], [('d', 0xc000, _)]));
+        assert!(oam.lock().unwrap().len() == 256);
     }
 
-    /// SBC $44,X
-    fn sbcf5(&mut self, operand: u8) {
-        self.sbce9(self.ram[(operand + self.x) as usize]);
+    #[test]
+    fn runs_on_another_thread() {
+        let mut cpu = vectored_cpu(TestCartridge::default());
+        load(&mut cpu, "lda #$42");
+        let cpu = std::thread::spawn(move || {
+            cpu.step();
+            cpu
+        })
+        .join()
+        .unwrap();
+        assert!(cpu.a == 0x42);
     }
 
-    /// SBC $4400
-    fn sbced(&mut self, operand: u16) {
-        self.sbce9(self.ram[operand as usize]);
-    }
+    #[test]
+    fn kil_halts_until_reset() {
+        let mut cpu = vectored_cpu(TestCartridge::default());
+        cpu.bus.write(mmap::cpu::reset::START as u16, 0x00);
+        cpu.bus.write(mmap::cpu::reset::END as u16, 0xc0);
+
+        load(&mut cpu, "kil\nlda #$01");
+        cpu.step();
+        assert!(cpu.halted());
+        assert!(cpu.pc == PROGRAM);
+
+        // nothing runs anymore, not even interrupts
+        cpu.set_nmi_line(true);
+        assert!(cpu.step() == 1);
+        assert!(cpu.pc == PROGRAM);
+        assert!(cpu.a == 0);
 
-    /// SBC $4400,X
-    fn sbcfd(&mut self, operand: u16) {
-        self.sbce9(self.ram[(operand + self.x as u16) as usize]);
+        cpu.reset();
+        assert!(!cpu.halted());
+        assert!(cpu.pc == 0xc000);
     }
 
-    /// SBC $4400,Y
-    fn sbcf9(&mut self, operand: u16) {
-        self.sbce9(self.ram[(operand + self.y as u16) as usize]);
+    #[test]
+    fn unofficial_nops() {
+        let mut cpu = CPU::default();
+        cpu.x = 0x10;
+        // reads memory and pays for the page cross
+        load(&mut cpu, "nop $07f0,x");
+        assert!(cpu.step() == 5);
+        assert!(cpu.pc == PROGRAM + 3);
+
+        load(&mut cpu, "nop #$ff");
+        assert!(cpu.step() == 2);
+        assert!(cpu.pc == PROGRAM + 2);
+        assert!(cpu.a == 0);
     }
 
-    /// SBC ($44,X)
-    fn sbce1(&mut self, operand: u8) {
-        let addr = self.get_indirect_addr(operand + self.x);
-        self.sbce9(self.ram[addr as usize]);
-    }
+    #[test]
+    fn unofficial_rmw_opcodes() {
+        let mut cpu = CPU::default();
 
-    /// SBC ($44),Y
-    fn sbcf1(&mut self, operand: u8) {
-        let addr = self.get_indirect_addr(operand);
-        self.sbce9(self.ram[(addr + self.y as u16) as usize]);
-    }
+        cpu.a = 0b0000_0001;
+        cpu.bus.write(0x10, 0b0100_0000);
+        run(&mut cpu, "slo $10");
+        assert!(cpu.bus.read(0x10) == 0b1000_0000);
+        assert!(cpu.a == 0b1000_0001);
+        assert!(cpu.negative);
 
-    /// SEC
-    fn sec38(&mut self) {
-        self.carry = 1;
-    }
+        cpu.a = 0xff;
+        cpu.carry = 1;
+        cpu.bus.write(0x10, 0b0100_0000);
+        run(&mut cpu, "rla $10");
+        assert!(cpu.bus.read(0x10) == 0b1000_0001);
+        assert!(cpu.a == 0b1000_0001);
 
-    /// SEI
-    fn sei78(&mut self) {
-        self.interrupt_disable = true;
-    }
+        cpu.a = 0xff;
+        cpu.bus.write(0x10, 0b0000_0011);
+        run(&mut cpu, "sre $10");
+        assert!(cpu.bus.read(0x10) == 0b0000_0001);
+        assert!(cpu.a == 0b1111_1110);
+        assert!(cpu.carry == 1);
 
-    /// STA $4400
-    fn sta8d(&mut self, operand: u16) {
-        self.ram[operand as usize] = self.a;
-    }
+        cpu.a = 0x10;
+        cpu.carry = 0;
+        cpu.bus.write(0x10, 0b0000_0011);
+        // ROR leaves C set for the ADC
+        run(&mut cpu, "rra $10");
+        assert!(cpu.bus.read(0x10) == 0b0000_0001);
+        assert!(cpu.a == 0x12);
+
+        cpu.a = 0x41;
+        cpu.bus.write(0x10, 0x42);
+        run(&mut cpu, "dcp $10");
+        assert!(cpu.bus.read(0x10) == 0x41);
+        assert!(cpu.zero);
+        assert!(cpu.carry == 1);
 
-    /// STA $44
-    fn sta85(&mut self, operand: u8) {
-        self.sta8d(operand as u16);
+        cpu.a = 0x10;
+        cpu.carry = 1;
+        cpu.bus.write(0x10, 0x04);
+        run(&mut cpu, "isc $10");
+        assert!(cpu.bus.read(0x10) == 0x05);
+        assert!(cpu.a == 0x0b);
+
+        cpu.a = 0x80;
+        cpu.bus.write(0x10, 0x00);
+        // -128 - 1 overflows without borrowing
+        run(&mut cpu, "isc $10");
+        assert!(cpu.a == 0x7f);
+        assert!(cpu.overflow);
+        assert!(cpu.carry == 1);
     }
 
-    /// STA $44,X
-    fn sta95(&mut self, operand: u8) {
-        self.sta85(operand + self.x);
-    }
+    #[test]
+    fn unofficial_load_store_opcodes() {
+        let mut cpu = CPU::default();
 
-    /// STA $4400,X
-    fn sta9d(&mut self, operand: u16) {
-        self.sta8d(operand + self.x as u16);
+        cpu.bus.write(0x20, 0x8f);
+        run(&mut cpu, "lax $20");
+        assert!(cpu.a == 0x8f);
+        assert!(cpu.x == 0x8f);
+        assert!(cpu.negative);
+
+        cpu.a = 0b1100_1100;
+        cpu.x = 0b1010_1010;
+        run(&mut cpu, "sax $21");
+        assert!(cpu.bus.read(0x21) == 0b1000_1000);
+        assert!(cpu.a == 0b1100_1100);
+
+        cpu.sp = 0b1111_0000;
+        cpu.bus.write(0x0700, 0b0011_1100);
+        cpu.y = 0;
+        run(&mut cpu, "las $0700,y");
+        assert!(cpu.a == 0b0011_0000);
+        assert!(cpu.x == 0b0011_0000);
+        assert!(cpu.sp == 0b0011_0000);
+
+        cpu.x = 0xff;
+        cpu.y = 0x01;
+        // stores X & ($02 + 1)
+        run(&mut cpu, "shx $0200,y");
+        assert!(cpu.bus.read(0x0201) == 0x03);
+
+        // crossing a page also corrupts the high byte of the address
+        cpu.x = 0x05;
+        cpu.y = 0x01;
+        run(&mut cpu, "shx $02ff,y");
+        assert!(cpu.bus.read(0x0100) == 0x01);
     }
 
-    /// STA $4400,Y
-    fn sta99(&mut self, operand: u16) {
-        self.sta8d(operand + self.y as u16);
-    }
+    #[test]
+    fn unofficial_immediate_opcodes() {
+        let mut cpu = CPU::default();
 
-    /// STA ($44,X)
-    fn sta81(&mut self, operand: u8) {
-        let addr = self.get_indirect_addr(operand + self.x);
-        self.sta8d(addr);
-    }
+        cpu.a = 0xff;
+        run(&mut cpu, "anc #$80");
+        assert!(cpu.a == 0x80);
+        assert!(cpu.carry == 1);
 
-    /// STA ($44),Y
-    fn sta91(&mut self, operand: u8) {
-        let addr = self.get_indirect_addr(operand);
-        self.sta8d(addr + self.y as u16);
-    }
+        cpu.a = 0xff;
+        run(&mut cpu, "alr #$03");
+        assert!(cpu.a == 0x01);
+        assert!(cpu.carry == 1);
 
-    /// STX $4400
-    fn stx8e(&mut self, operand: u16) {
-        self.ram[operand as usize] = self.x;
-    }
+        cpu.a = 0xff;
+        cpu.carry = 1;
+        run(&mut cpu, "arr #$c0");
+        assert!(cpu.a == 0xe0);
+        assert!(cpu.carry == 1);
+        assert!(!cpu.overflow);
 
-    /// STX $44
-    fn stx86(&mut self, operand: u8) {
-        self.stx8e(operand as u16);
-    }
+        cpu.a = 0xff;
+        cpu.carry = 0;
+        run(&mut cpu, "arr #$40");
+        assert!(cpu.a == 0x20);
+        assert!(cpu.carry == 0);
+        assert!(cpu.overflow);
 
-    /// STX $44,Y
-    fn stx96(&mut self, operand: u8) {
-        self.stx86(operand + self.y);
-    }
+        cpu.a = 0x0f;
+        cpu.x = 0xfc;
+        run(&mut cpu, "axs #$02");
+        assert!(cpu.x == 0x0a);
+        assert!(cpu.carry == 1);
+        cpu.x = 0xfc;
+        run(&mut cpu, "axs #$0d");
+        assert!(cpu.x == 0xff);
+        assert!(cpu.carry == 0);
 
-    /// STY $4400
-    fn sty8c(&mut self, operand: u16) {
-        self.ram[operand as usize] = self.y;
+        cpu.a = 200;
+        cpu.carry = 1;
+        // the unofficial copy of SBC #20, the assembler would pick the official one
+        run(&mut cpu, ".byte $eb, 20");
+        assert!(cpu.a == 180);
     }
 
-    /// STY $44
-    fn sty84(&mut self, operand: u8) {
-        self.stx8e(operand as u16);
-    }
+    #[test]
+    fn page_crossing_penalties() {
+        let mut cpu = CPU::default();
 
-    /// STY $44,X
-    fn sty94(&mut self, operand: u8) {
-        self.stx86(operand + self.x);
-    }
+        cpu.x = 0x10;
+        load(&mut cpu, "lda $07f0,x");
+        assert!(cpu.step() == 5);
+        load(&mut cpu, "lda $0700,x");
+        assert!(cpu.step() == 4);
 
-    /// TAX
-    fn taxaa(&mut self) {
-        self.x = self.a;
-        self.zero = self.x == 0;
-        self.negative = (self.x & 0b1000_0000) == 0b1000_0000;
-    }
+        // stores always take the extra cycle, crossing or not
+        load(&mut cpu, "sta $0700,x");
+        assert!(cpu.step() == 5);
 
-    /// TAY
-    fn taya8(&mut self) {
-        self.y = self.a;
-        self.zero = self.y == 0;
-        self.negative = (self.y & 0b1000_0000) == 0b1000_0000;
+        cpu.y = 0x01;
+        cpu.bus.write(0x40, 0xff);
+        cpu.bus.write(0x41, 0x02);
+        load(&mut cpu, "lda ($40),y");
+        assert!(cpu.step() == 6);
+        cpu.y = 0x00;
+        load(&mut cpu, "lda ($40),y");
+        assert!(cpu.step() == 5);
     }
 
-    /// TSX
-    fn tsxba(&mut self) {
-        self.x = self.sp;
-        self.zero = self.x == 0;
-        self.negative = (self.x & 0b1000_0000) == 0b1000_0000;
-    }
+    #[test]
+    fn branch_penalties() {
+        let mut cpu = CPU::default();
 
-    /// TXA
-    fn txa8a(&mut self) {
-        self.a = self.x;
-        self.zero = self.a == 0;
-        self.negative = (self.a & 0b1000_0000) == 0b1000_0000;
-    }
+        cpu.zero = false;
+        // not taken
+        load(&mut cpu, "beq *+$12");
+        assert!(cpu.step() == 2);
 
-    /// TXS
-    fn txs9a(&mut self) {
-        self.sp = self.x;
-    }
+        cpu.zero = true;
+        load(&mut cpu, "beq *+$12");
+        assert!(cpu.step() == 3);
 
-    /// TYA
-    fn tya98(&mut self) {
-        self.a = self.y;
-        self.zero = self.a == 0;
-        self.negative = (self.a & 0b1000_0000) == 0b1000_0000;
+        // lands on the previous page
+        load(&mut cpu, "beq *-2");
+        assert!(cpu.step() == 4);
+        assert!(cpu.pc == PROGRAM - 2);
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
     #[test]
     fn adc_opcodes() {
         let mut cpu = CPU::default();
 
         cpu.a = 255;
-        cpu.adc69(1);
+        run(&mut cpu, "adc #$01");
         assert!(cpu.a == 0);
         assert!(cpu.carry == 1);
-        assert!(cpu.zero == true);
-        cpu.adc69(254);
-        assert!(cpu.negative == true);
-        cpu.a = unsafe { mem::transmute::<i8, u8>(i8::MAX) };
-        cpu.adc69(1);
-        assert!(cpu.overflow == true);
-
-        // the following opcodes all use adc69 underneath so there's no need to test flags again
-        cpu.ram[0x44] = 29;
+        assert!(cpu.zero);
+        run(&mut cpu, "adc #$fe");
+        assert!(cpu.negative);
+        cpu.a = i8::MAX as u8;
+        run(&mut cpu, "adc #$01");
+        assert!(cpu.overflow);
+
+        // the following opcodes all share the same ADC underneath so there's no need to test flags again
+        cpu.bus.write(0x44, 29);
         cpu.a = 3;
-        cpu.adc65(0x44);
+        run(&mut cpu, "adc $44");
         assert!(cpu.a == 32);
 
         cpu.a = 4;
-        cpu.ram[0xff] = 50;
+        cpu.bus.write(0xff, 50);
         cpu.x = 0x2;
-        cpu.adc75(0xfd);
+        run(&mut cpu, "adc $fd,x");
         assert!(cpu.a == 54);
 
-        cpu.ram[0x1ee] = 244;
+        cpu.bus.write(0x1ee, 244);
         cpu.a = 2;
-        cpu.adc6d(0x1ee);
+        run(&mut cpu, "adc $01ee");
         assert!(cpu.a == 246);
 
-        cpu.ram[0x1ee] = 156;
+        cpu.bus.write(0x1ee, 156);
         cpu.a = 2;
         cpu.x = 0xee;
-        cpu.adc7d(0x100);
+        run(&mut cpu, "adc $0100,x");
         assert!(cpu.a == 158);
 
-        cpu.ram[0x7ee] = 70;
+        cpu.bus.write(0x7ee, 70);
         cpu.a = 90;
         cpu.y = 0xe0;
-        cpu.adc79(0x70e);
+        run(&mut cpu, "adc $070e,y");
         assert!(cpu.a == 160);
 
-        cpu.ram[0x45] = 0xab;
-        cpu.ram[0x46] = 0x01;
-        cpu.ram[0x01ab] = 222;
+        cpu.bus.write(0x45, 0xab);
+        cpu.bus.write(0x46, 0x01);
+        cpu.bus.write(0x01ab, 222);
         cpu.a = 0;
         cpu.x = 1;
-        cpu.adc61(0x44);
+        run(&mut cpu, "adc ($44,x)");
         assert!(cpu.a == 222);
 
         cpu.a = 0;
-        cpu.ram[0xaa] = 0xca;
-        cpu.ram[0xab] = 0x01;
+        cpu.bus.write(0xaa, 0xca);
+        cpu.bus.write(0xab, 0x01);
         cpu.y = 3;
-        cpu.ram[0x01cd] = 111;
-        cpu.adc71(0xaa);
+        cpu.bus.write(0x01cd, 111);
+        run(&mut cpu, "adc ($aa),y");
         assert!(cpu.a == 111);
     }
 
@@ -2056,59 +1664,59 @@ mod tests {
     fn and_opcodes() {
         let mut cpu = CPU::default();
         cpu.a = 0x0f;
-        cpu.and29(0xf0);
+        run(&mut cpu, "and #$f0");
         assert!(cpu.a == 0);
-        assert!(cpu.zero == true);
+        assert!(cpu.zero);
         cpu.a = 0xfa;
-        cpu.and29(0x0f);
+        run(&mut cpu, "and #$0f");
         assert!(cpu.a == 0x0a);
         cpu.a = 0xff;
-        cpu.and29(0xff);
-        assert!(cpu.negative == true);
+        run(&mut cpu, "and #$ff");
+        assert!(cpu.negative);
 
-        // the following opcodes all use and29 underneath so there's no need to test flags again
-        cpu.ram[0xaa] = 0xf0;
+        // the following opcodes all share the same AND underneath so there's no need to test flags again
+        cpu.bus.write(0xaa, 0xf0);
         cpu.a = 0xea;
-        cpu.and25(0xaa);
+        run(&mut cpu, "and $aa");
         assert!(cpu.a == 0xe0);
 
-        cpu.ram[40] = 0xff;
+        cpu.bus.write(40, 0xff);
         cpu.a = 0xEE;
         cpu.x = 2;
-        cpu.and35(38);
+        run(&mut cpu, "and $26,x");
         assert!(cpu.a == 0xee);
 
-        cpu.ram[2000] = 0x0f;
+        cpu.bus.write(2000, 0x0f);
         cpu.a = 0xac;
-        cpu.and2d(2000);
+        run(&mut cpu, "and $07d0");
         assert!(cpu.a == 0xc);
 
-        cpu.ram[2005] = 0x56;
+        cpu.bus.write(2005, 0x56);
         cpu.a = 0xf0;
         cpu.x = 5;
-        cpu.and3d(2000);
+        run(&mut cpu, "and $07d0,x");
         assert!(cpu.a == 0x50);
 
-        cpu.ram[2046] = 0xa7;
+        cpu.bus.write(2046, 0xa7);
         cpu.a = 0x0f;
         cpu.y = 10;
-        cpu.and39(2036);
+        run(&mut cpu, "and $07f4,y");
         assert!(cpu.a == 0x7);
 
-        cpu.ram[10] = 0x0e;
-        cpu.ram[11] = 0x1;
-        cpu.ram[0x10e] = 0xcc;
+        cpu.bus.write(10, 0x0e);
+        cpu.bus.write(11, 0x1);
+        cpu.bus.write(0x10e, 0xcc);
         cpu.a = 0xf0;
         cpu.x = 3;
-        cpu.and21(7);
+        run(&mut cpu, "and ($07,x)");
         assert!(cpu.a == 0xc0);
 
-        cpu.ram[10] = 0x0f;
-        cpu.ram[11] = 0x02;
-        cpu.ram[0x211] = 0xdd;
+        cpu.bus.write(10, 0x0f);
+        cpu.bus.write(11, 0x02);
+        cpu.bus.write(0x211, 0xdd);
         cpu.a = 0x0f;
         cpu.y = 2;
-        cpu.and31(10);
+        run(&mut cpu, "and ($0a),y");
         assert!(cpu.a == 0xd);
     }
 
@@ -2117,87 +1725,80 @@ mod tests {
         let mut cpu = CPU::default();
 
         cpu.a = 0b1011_1111;
-        cpu.asl0a();
+        run(&mut cpu, "asl a");
         assert!(cpu.a == 0b0111_1110);
         assert!(cpu.carry == 1);
-        assert!(cpu.zero == false);
-        assert!(cpu.negative == false);
+        assert!(!cpu.zero);
+        assert!(!cpu.negative);
         cpu.a = 0b1000_0000;
-        cpu.asl0a();
-        assert!(cpu.zero == true);
+        run(&mut cpu, "asl a");
+        assert!(cpu.zero);
         cpu.a = 0b0100_0000;
-        cpu.asl0a();
-        assert!(cpu.negative == true);
+        run(&mut cpu, "asl a");
+        assert!(cpu.negative);
 
-        // has the same logic as asl0a so flag testing is skipped
-        // also skips testing asl0e as underneath asl06 uses it to limit to 8 bits
-        cpu.ram[150] = 0b1011_1111;
-        cpu.asl06(150);
-        assert!(cpu.ram[150] == 0b0111_1110);
-        // also skips the other intructions because they all rely on the opcode above
+        // has the same logic as ASL A so flag testing is skipped
+        cpu.bus.write(150, 0b1011_1111);
+        run(&mut cpu, "asl $96");
+        assert!(cpu.bus.read(150) == 0b0111_1110);
+        // also skips the other addressing modes because they all rely on the same code
     }
 
     #[test]
     fn branch_opcodes() {
         let mut cpu = CPU::default();
 
+        // offsets are relative to the next instruction
         cpu.carry = 0;
-        cpu.bcc90(200);
-        assert!(cpu.pc == 200);
+        run(&mut cpu, "bcc *+$12");
+        assert!(cpu.pc == PROGRAM + 0x12);
         cpu.carry = 1;
-        cpu.bcc90(50);
-        assert!(cpu.pc != 250);
+        run(&mut cpu, "bcc *+$12");
+        assert!(cpu.pc == PROGRAM + 2);
 
-        cpu.pc = 0;
         cpu.carry = 1;
-        cpu.bcsb0(200);
-        assert!(cpu.pc == 200);
+        run(&mut cpu, "bcs *+$12");
+        assert!(cpu.pc == PROGRAM + 0x12);
         cpu.carry = 0;
-        cpu.bcsb0(50);
-        assert!(cpu.pc != 250);
+        run(&mut cpu, "bcs *+$12");
+        assert!(cpu.pc == PROGRAM + 2);
 
-        cpu.pc = 0;
         cpu.zero = true;
-        cpu.beqf0(100);
-        assert!(cpu.pc == 100);
+        run(&mut cpu, "beq *+$66");
+        assert!(cpu.pc == PROGRAM + 102);
         cpu.zero = false;
-        cpu.beqf0(100);
-        assert!(cpu.pc != 200);
+        run(&mut cpu, "beq *+$66");
+        assert!(cpu.pc == PROGRAM + 2);
 
-        cpu.pc = 0;
         cpu.negative = true;
-        cpu.bmi30(100);
-        assert!(cpu.pc == 100);
+        run(&mut cpu, "bmi *+$66");
+        assert!(cpu.pc == PROGRAM + 102);
         cpu.negative = false;
-        cpu.bmi30(100);
-        assert!(cpu.pc != 200);
+        run(&mut cpu, "bmi *+$66");
+        assert!(cpu.pc == PROGRAM + 2);
 
-        cpu.pc = 0;
+        // negative offsets branch backwards
         cpu.zero = false;
-        cpu.bned0(100);
-        assert!(cpu.pc == 100);
+        run(&mut cpu, "bne *-2");
+        assert!(cpu.pc == PROGRAM - 2);
         cpu.zero = true;
-        cpu.bned0(100);
-        assert!(cpu.pc != 200);
+        run(&mut cpu, "bne *-2");
+        assert!(cpu.pc == PROGRAM + 2);
 
-        // BPL $44
-        cpu.pc = 0;
         cpu.negative = false;
-        cpu.bpl10(100);
-        assert!(cpu.pc == 100);
+        run(&mut cpu, "bpl *+$66");
+        assert!(cpu.pc == PROGRAM + 102);
         cpu.negative = true;
-        cpu.bpl10(100);
-        assert!(cpu.pc != 200);
+        run(&mut cpu, "bpl *+$66");
+        assert!(cpu.pc == PROGRAM + 2);
 
-        cpu.pc = 0;
         cpu.overflow = false;
-        cpu.bvc50(25);
-        assert!(cpu.pc == 25);
+        run(&mut cpu, "bvc *+$1b");
+        assert!(cpu.pc == PROGRAM + 27);
 
-        cpu.pc = 0;
         cpu.overflow = true;
-        cpu.bvs70(100);
-        assert!(cpu.pc == 100);
+        run(&mut cpu, "bvs *+$66");
+        assert!(cpu.pc == PROGRAM + 102);
     }
 
     #[test]
@@ -2205,170 +1806,290 @@ mod tests {
         let mut cpu = CPU::default();
 
         cpu.a = 0b1111_0000;
-        cpu.ram[55] = 0b1100_1111;
-        cpu.bit24(55);
-        assert!(cpu.negative == true);
-        assert!(cpu.overflow == true);
+        cpu.bus.write(55, 0b1100_1111);
+        run(&mut cpu, "bit $37");
+        assert!(cpu.negative);
+        assert!(cpu.overflow);
         cpu.a = 0b1111_0000;
-        cpu.ram[55] = 0b0011_1111;
-        cpu.bit24(55);
-        assert!(cpu.negative == false);
-        assert!(cpu.overflow == false);
-        assert!(cpu.zero == false);
+        cpu.bus.write(55, 0b0011_1111);
+        run(&mut cpu, "bit $37");
+        assert!(!cpu.negative);
+        assert!(!cpu.overflow);
+        assert!(!cpu.zero);
         cpu.a = 0b0000_1111;
-        cpu.ram[55] = 0b1111_0000;
-        cpu.bit24(55);
-        assert!(cpu.zero == true);
-        // no need to test bit2c, it has the same implementation
+        cpu.bus.write(55, 0b1111_0000);
+        run(&mut cpu, "bit $37");
+        assert!(cpu.zero);
+        // no need to test BIT $4400, it has the same implementation
     }
 
     #[test]
     fn brk_opcode() {
-        // TODO uncomment once PPU memory is implemented
-        // let mut cpu = CPU::default();
-        // cpu.ram[mmap::cpu::irq_brk::START] = 0xff;
-        // cpu.ram[mmap::cpu::irq_brk::END] = 0x02;
-        // cpu.brk00();
-        // assert!(cpu.pc == 0xea);
+        let mut cpu = CPU::default();
+        cpu.bus
+            .attach_cartridge(Cartridge::new(Box::new(TestCartridge::default())));
+        cpu.bus.write(mmap::cpu::irq_brk::START as u16, 0xff);
+        cpu.bus.write(mmap::cpu::irq_brk::END as u16, 0x02);
+        run(&mut cpu, "brk");
+        assert!(cpu.pc == 0x02ff);
+        assert!(cpu.interrupt_disable);
+
+        // pushes the address after the padding byte and sets B
+        assert!(cpu.pop_from_stack() & 0b0001_0000 != 0);
+        assert!(cpu.pop_from_stack() == 0x02);
+        assert!(cpu.pop_from_stack() == 0x06);
+    }
+
+    #[test]
+    fn nmi_is_edge_triggered() {
+        let mut cpu = vectored_cpu(TestCartridge::default());
+        cpu.interrupt_disable = true;
+
+        cpu.set_nmi_line(true);
+        assert!(cpu.poll_interrupts(0xea, true) == 7);
+        assert!(cpu.pc == 0x9000);
+        // B is clear for hardware interrupts
+        assert!(cpu.pop_from_stack() & 0b0001_0000 == 0);
+        assert!(cpu.pop_from_stack() == 0x00);
+        assert!(cpu.pop_from_stack() == 0x80);
+
+        // holding the line does not trigger again
+        cpu.set_nmi_line(true);
+        assert!(cpu.poll_interrupts(0xea, true) == 0);
+
+        cpu.set_nmi_line(false);
+        cpu.set_nmi_line(true);
+        assert!(cpu.poll_interrupts(0xea, true) == 7);
+    }
+
+    #[test]
+    fn irq_is_level_triggered() {
+        let mut cpu = vectored_cpu(TestCartridge::default());
+        cpu.interrupt_disable = true;
+        cpu.set_irq_line(true);
+        assert!(cpu.poll_interrupts(0xea, true) == 0);
+
+        cpu.interrupt_disable = false;
+        assert!(cpu.poll_interrupts(0xea, false) == 7);
+        assert!(cpu.pc == 0xa000);
+        assert!(cpu.interrupt_disable);
+
+        // still asserted, fires again once I is cleared
+        cpu.interrupt_disable = false;
+        assert!(cpu.poll_interrupts(0xea, false) == 7);
+
+        cpu.set_irq_line(false);
+        cpu.interrupt_disable = false;
+        assert!(cpu.poll_interrupts(0xea, false) == 0);
+    }
+
+    #[test]
+    fn mapper_irq() {
+        let mut cpu = vectored_cpu(TestCartridge {
+            irq: true,
+            ..Default::default()
+        });
+        cpu.interrupt_disable = false;
+        assert!(cpu.poll_interrupts(0xea, false) == 7);
+        assert!(cpu.pc == 0xa000);
+    }
+
+    #[test]
+    fn cli_sei_plp_delay_interrupts() {
+        let mut cpu = vectored_cpu(TestCartridge::default());
+        cpu.set_irq_line(true);
+
+        // the IRQ is only taken after the instruction following CLI
+        cpu.interrupt_disable = true;
+        load(&mut cpu, "cli\nnop");
+        assert!(cpu.step() == 2);
+        assert!(cpu.step() == 2 + 7);
+        assert!(cpu.pc == 0xa000);
+
+        // an IRQ can still slip in right after SEI
+        cpu.interrupt_disable = false;
+        load(&mut cpu, "sei");
+        assert!(cpu.step() == 2 + 7);
+
+        cpu.interrupt_disable = false;
+        cpu.push_to_stack(0b0000_0100);
+        load(&mut cpu, "plp");
+        assert!(cpu.step() == 4 + 7);
+    }
+
+    #[test]
+    fn nmi_hijacks_brk() {
+        let mut cpu = vectored_cpu(TestCartridge::default());
+        cpu.nmi_pending = true;
+        cpu.brk();
+        assert!(cpu.pc == 0x9000);
+        assert!(!cpu.nmi_pending);
+        assert!(cpu.pop_from_stack() & 0b0001_0000 != 0);
+    }
+
+    #[test]
+    fn power_on_and_reset() {
+        let mut cpu = CPU::default();
+        cpu.bus
+            .attach_cartridge(Cartridge::new(Box::new(TestCartridge::default())));
+        cpu.bus.write(mmap::cpu::reset::START as u16, 0x00);
+        cpu.bus.write(mmap::cpu::reset::END as u16, 0xc0);
+
+        cpu.power_on();
+        assert!(cpu.pc == 0xc000);
+        assert!(cpu.sp == 0xfd);
+        assert!(cpu.interrupt_disable);
+        assert!(cpu.cycles == 7);
+
+        cpu.a = 0x12;
+        cpu.interrupt_disable = false;
+        cpu.pc = 0x1234;
+        cpu.reset();
+        assert!(cpu.pc == 0xc000);
+        assert!(cpu.sp == 0xfa);
+        assert!(cpu.interrupt_disable);
+        assert!(cpu.a == 0x12);
+        assert!(cpu.cycles == 14);
     }
 
     #[test]
     fn clear_opcodes() {
         let mut cpu = CPU::default();
         cpu.carry = 1;
-        cpu.clc18();
+        run(&mut cpu, "clc");
         assert!(cpu.carry == 0);
 
         cpu.decimal = true;
-        cpu.cldd8();
-        assert!(cpu.decimal == false);
+        run(&mut cpu, "cld");
+        assert!(!cpu.decimal);
 
         cpu.interrupt_disable = true;
-        cpu.cli58();
-        assert!(cpu.interrupt_disable == false);
+        run(&mut cpu, "cli");
+        assert!(!cpu.interrupt_disable);
 
         cpu.overflow = true;
-        cpu.clvb8();
-        assert!(cpu.overflow == false);
+        run(&mut cpu, "clv");
+        assert!(!cpu.overflow);
     }
 
     #[test]
     fn cmp_opcodes() {
         let mut cpu = CPU::default();
         cpu.a = 0xfe;
-        cpu.cmpc9(0xfe);
-        assert!(cpu.zero == true);
+        run(&mut cpu, "cmp #$fe");
+        assert!(cpu.zero);
         cpu.a = 0xfe;
-        cpu.cmpc9(0x10);
+        run(&mut cpu, "cmp #$10");
         assert!(cpu.carry == 1);
-        assert!(cpu.negative == true);
+        assert!(cpu.negative);
 
         cpu.a = 0xfe;
-        cpu.ram[0x80] = 0xfe;
-        cpu.cmpc5(0x80);
-        assert!(cpu.zero == true);
+        cpu.bus.write(0x80, 0xfe);
+        run(&mut cpu, "cmp $80");
+        assert!(cpu.zero);
 
         cpu.a = 0xfe;
-        cpu.ram[0x81] = 0xfe;
+        cpu.bus.write(0x81, 0xfe);
         cpu.x = 1;
-        cpu.cmpd5(0x80);
-        assert!(cpu.zero == true);
+        run(&mut cpu, "cmp $80,x");
+        assert!(cpu.zero);
 
         cpu.a = 0xfe;
-        cpu.ram[0x80] = 0xfe;
-        cpu.cmpcd(0x80);
-        assert!(cpu.zero == true);
+        cpu.bus.write(0x280, 0xfe);
+        run(&mut cpu, "cmp $0280");
+        assert!(cpu.zero);
 
         cpu.a = 0xfe;
-        cpu.ram[0x701] = 0xfe;
+        cpu.bus.write(0x701, 0xfe);
         cpu.x = 1;
-        cpu.cmpdd(0x700);
-        assert!(cpu.zero == true);
+        run(&mut cpu, "cmp $0700,x");
+        assert!(cpu.zero);
 
         cpu.a = 0xfe;
-        cpu.ram[0x701] = 0xfe;
+        cpu.bus.write(0x701, 0xfe);
         cpu.y = 1;
-        cpu.cmpd9(0x700);
-        assert!(cpu.zero == true);
+        run(&mut cpu, "cmp $0700,y");
+        assert!(cpu.zero);
 
         cpu.a = 0xfe;
-        cpu.ram[240] = 0xfe;
-        cpu.ram[241] = 0x0;
-        cpu.ram[80] = 240;
+        cpu.bus.write(240, 0xfe);
+        cpu.bus.write(80, 240);
+        cpu.bus.write(81, 0x0);
         cpu.x = 1;
-        cpu.cmpc1(79);
+        run(&mut cpu, "cmp ($4f,x)");
+        assert!(cpu.zero);
 
-        // CMP ($44),Y
         cpu.a = 0xfe;
-        cpu.ram[240] = 0xfe;
-        cpu.ram[241] = 0x01;
-        cpu.ram[0x1ff] = 0xfe;
+        cpu.bus.write(240, 0xfe);
+        cpu.bus.write(241, 0x01);
+        cpu.bus.write(0x1ff, 0xfe);
         cpu.y = 1;
-        cpu.cmpd1(240);
+        run(&mut cpu, "cmp ($f0),y");
+        assert!(cpu.zero);
     }
 
     #[test]
     fn cpx_opcodes() {
         let mut cpu = CPU::default();
         cpu.x = 0xea;
-        cpu.cpxe0(0xea);
-        assert!(cpu.zero == true);
+        run(&mut cpu, "cpx #$ea");
+        assert!(cpu.zero);
 
         cpu.x = 0xea;
-        cpu.ram[200] = 0xea;
-        cpu.cpxe4(200);
-        assert!(cpu.zero == true);
+        cpu.bus.write(200, 0xea);
+        run(&mut cpu, "cpx $c8");
+        assert!(cpu.zero);
 
         cpu.x = 0xea;
-        cpu.ram[2023] = 0xea;
-        cpu.cpxec(2023);
-        assert!(cpu.zero == true);
+        cpu.bus.write(2023, 0xea);
+        run(&mut cpu, "cpx $07e7");
+        assert!(cpu.zero);
     }
 
     #[test]
     fn cpy_opcodes() {
         let mut cpu = CPU::default();
         cpu.y = 0xea;
-        cpu.cpyc0(0xea);
-        assert!(cpu.zero == true);
+        run(&mut cpu, "cpy #$ea");
+        assert!(cpu.zero);
 
         cpu.y = 0xea;
-        cpu.ram[200] = 0xea;
-        cpu.cpyc4(200);
-        assert!(cpu.zero == true);
+        cpu.bus.write(200, 0xea);
+        run(&mut cpu, "cpy $c8");
+        assert!(cpu.zero);
 
         cpu.y = 0xea;
-        cpu.ram[2023] = 0xea;
-        cpu.cpycc(2023);
-        assert!(cpu.zero == true);
+        cpu.bus.write(2023, 0xea);
+        run(&mut cpu, "cpy $07e7");
+        assert!(cpu.zero);
     }
 
     #[test]
     fn dec_opcodes() {
         let mut cpu = CPU::default();
-        cpu.ram[0x80] = 2;
-        cpu.decc6(0x80);
-        assert!(cpu.ram[0x80] == 1);
+        cpu.bus.write(0x80, 2);
+        run(&mut cpu, "dec $80");
+        assert!(cpu.bus.read(0x80) == 1);
 
         cpu.x = 1;
-        cpu.ram[0x81] = 2;
-        cpu.decd6(0x80);
-        assert!(cpu.ram[0x81] == 1);
+        cpu.bus.write(0x81, 2);
+        run(&mut cpu, "dec $80,x");
+        assert!(cpu.bus.read(0x81) == 1);
 
-        cpu.ram[1400] = 5;
-        cpu.decce(1400);
-        assert!(cpu.ram[1400] == 4);
+        cpu.bus.write(1400, 5);
+        run(&mut cpu, "dec $0578");
+        assert!(cpu.bus.read(1400) == 4);
 
-        cpu.ram[1401] = 5;
-        cpu.decde(1400);
-        assert!(cpu.ram[1401] == 4);
+        cpu.bus.write(1401, 5);
+        run(&mut cpu, "dec $0578,x");
+        assert!(cpu.bus.read(1401) == 4);
 
         cpu.x = 1;
-        cpu.dexca();
+        run(&mut cpu, "dex");
         assert!(cpu.x == 0);
 
         cpu.y = 2;
-        cpu.dey88();
+        run(&mut cpu, "dey");
         assert!(cpu.y == 1);
     }
 
@@ -2376,38 +2097,38 @@ mod tests {
     fn eor_opcodes() {
         let mut cpu = CPU::default();
         cpu.a = 100;
-        cpu.eor49(50);
+        run(&mut cpu, "eor #$32");
         assert!(cpu.a == 86);
 
         cpu.a = 100;
-        cpu.ram[200] = 50;
-        cpu.eor45(200);
+        cpu.bus.write(200, 50);
+        run(&mut cpu, "eor $c8");
         assert!(cpu.a == 86);
 
         cpu.a = 100;
         cpu.x = 2;
-        cpu.ram[52] = 100;
-        cpu.eor55(50);
-        assert!(cpu.zero == true);
+        cpu.bus.write(52, 100);
+        run(&mut cpu, "eor $32,x");
+        assert!(cpu.zero);
     }
 
     #[test]
     fn inc_opcodes() {
         let mut cpu = CPU::default();
-        cpu.ram[122] = 10;
-        cpu.ince6(122);
-        assert!(cpu.ram[122] == 11);
+        cpu.bus.write(122, 10);
+        run(&mut cpu, "inc $7a");
+        assert!(cpu.bus.read(122) == 11);
 
-        cpu.ram[122] = 255;
-        cpu.ince6(122);
-        assert!(cpu.ram[122] == 0);
+        cpu.bus.write(122, 255);
+        run(&mut cpu, "inc $7a");
+        assert!(cpu.bus.read(122) == 0);
 
         cpu.x = 20;
-        cpu.inxe8();
+        run(&mut cpu, "inx");
         assert!(cpu.x == 21);
 
         cpu.y = 20;
-        cpu.inyc8();
+        run(&mut cpu, "iny");
         assert!(cpu.y == 21);
     }
 
@@ -2415,116 +2136,117 @@ mod tests {
     fn jmp_opcodes() {
         let mut cpu = CPU::default();
 
-        cpu.jmp4c(670);
+        run(&mut cpu, "jmp $029e");
         assert!(cpu.pc == 670);
 
-        cpu.ram[700] = 0xff;
-        cpu.ram[701] = 0x0a;
-        cpu.jmp6c(700);
+        cpu.bus.write(700, 0xff);
+        cpu.bus.write(701, 0x0a);
+        run(&mut cpu, "jmp ($02bc)");
         assert!(cpu.pc == 0x0aff);
 
-        cpu.ram[0x1ff] = 0xff;
-        cpu.ram[0x200] = 0x0a;
-        cpu.ram[0x100] = 0x01;
-        cpu.jmp6c(0x1ff);
+        // the pointer's MSB is read from the start of the same page
+        cpu.bus.write(0x1ff, 0xff);
+        cpu.bus.write(0x200, 0x0a);
+        cpu.bus.write(0x100, 0x01);
+        run(&mut cpu, "jmp ($01ff)");
         assert!(cpu.pc != 0x0aff);
-        assert!(cpu.pc != 0x01ff);
+        assert!(cpu.pc == 0x01ff);
 
-        cpu.jsr20(2300);
+        run(&mut cpu, "jsr $08fc");
         assert!(cpu.pc == 2300);
     }
 
     #[test]
     fn lda_opcodes() {
         let mut cpu = CPU::default();
-        cpu.ldaa9(0xff);
+        run(&mut cpu, "lda #$ff");
         assert!(cpu.a == 0xff);
 
-        cpu.ram[0xff] = 0xfe;
-        cpu.ldaa5(0xff);
+        cpu.bus.write(0xff, 0xfe);
+        run(&mut cpu, "lda $ff");
         assert!(cpu.a == 0xfe);
 
         cpu.x = 2;
-        cpu.ram[3] = 59;
-        cpu.ldab5(1);
+        cpu.bus.write(3, 59);
+        run(&mut cpu, "lda $01,x");
         assert!(cpu.a == 59);
 
-        cpu.ram[2000] = 55;
-        cpu.ldaad(2000);
+        cpu.bus.write(2000, 55);
+        run(&mut cpu, "lda $07d0");
         assert!(cpu.a == 55);
 
         cpu.x = 1;
-        cpu.ram[2001] = 222;
-        cpu.ldabd(2000);
+        cpu.bus.write(2001, 222);
+        run(&mut cpu, "lda $07d0,x");
         assert!(cpu.a == 222);
 
         cpu.y = 1;
-        cpu.ram[2001] = 223;
-        cpu.ldab9(2000);
+        cpu.bus.write(2001, 223);
+        run(&mut cpu, "lda $07d0,y");
         assert!(cpu.a == 223);
 
-        cpu.ram[142] = 0xbb;
-        cpu.ram[143] = 0x01;
-        cpu.ram[0x01bb] = 0xee;
+        cpu.bus.write(142, 0xbb);
+        cpu.bus.write(143, 0x01);
+        cpu.bus.write(0x01bb, 0xee);
         cpu.x = 2;
-        cpu.ldaa1(140);
+        run(&mut cpu, "lda ($8c,x)");
         assert!(cpu.a == 0xee);
 
-        cpu.ram[142] = 0xb3;
-        cpu.ram[143] = 0x01;
-        cpu.ram[0x01b5] = 0xaa;
+        cpu.bus.write(142, 0xb3);
+        cpu.bus.write(143, 0x01);
+        cpu.bus.write(0x01b5, 0xaa);
         cpu.y = 2;
-        cpu.ldab1(142);
+        run(&mut cpu, "lda ($8e),y");
         assert!(cpu.a == 0xaa);
     }
 
     #[test]
     fn ldx_opcodes() {
         let mut cpu = CPU::default();
-        cpu.ldxa2(0xff);
+        run(&mut cpu, "ldx #$ff");
         assert!(cpu.x == 0xff);
 
-        cpu.ram[0xff] = 0xfe;
-        cpu.ldxa6(0xff);
+        cpu.bus.write(0xff, 0xfe);
+        run(&mut cpu, "ldx $ff");
         assert!(cpu.x == 0xfe);
 
         cpu.y = 2;
-        cpu.ram[3] = 59;
-        cpu.ldxb6(1);
+        cpu.bus.write(3, 59);
+        run(&mut cpu, "ldx $01,y");
         assert!(cpu.x == 59);
 
-        cpu.ram[2000] = 55;
-        cpu.ldxae(2000);
+        cpu.bus.write(2000, 55);
+        run(&mut cpu, "ldx $07d0");
         assert!(cpu.x == 55);
 
         cpu.y = 1;
-        cpu.ram[2001] = 222;
-        cpu.ldxbe(2000);
+        cpu.bus.write(2001, 222);
+        run(&mut cpu, "ldx $07d0,y");
         assert!(cpu.x == 222);
     }
 
     #[test]
     fn ldy_opcodes() {
         let mut cpu = CPU::default();
-        cpu.ldya0(0xff);
+        run(&mut cpu, "ldy #$ff");
         assert!(cpu.y == 0xff);
 
-        cpu.ram[0xff] = 0xfe;
-        cpu.ldya4(0xff);
+        cpu.bus.write(0xff, 0xfe);
+        run(&mut cpu, "ldy $ff");
         assert!(cpu.y == 0xfe);
 
         cpu.x = 2;
-        cpu.ram[3] = 59;
-        cpu.ldyb4(1);
+        cpu.bus.write(3, 59);
+        run(&mut cpu, "ldy $01,x");
         assert!(cpu.y == 59);
 
-        cpu.ram[2000] = 55;
-        cpu.ldyac(2000);
+        cpu.bus.write(2000, 55);
+        run(&mut cpu, "ldy $07d0");
         assert!(cpu.y == 55);
 
         cpu.x = 1;
-        cpu.ram[2001] = 222;
-        cpu.ldybc(2000);
+        cpu.bus.write(2001, 222);
+        run(&mut cpu, "ldy $07d0,x");
         assert!(cpu.y == 222);
     }
 
@@ -2533,12 +2255,13 @@ mod tests {
         let mut cpu = CPU::default();
 
         cpu.a = 0b0101_0101;
-        cpu.lsr4a();
+        run(&mut cpu, "lsr a");
         assert!(cpu.a == 0b0010_1010);
+        assert!(cpu.carry == 1);
 
-        cpu.ram[100] = 0b0101_0101;
-        cpu.lsr4e(100);
-        assert!(cpu.ram[100] == 0b0010_1010);
+        cpu.bus.write(0x264, 0b0101_0101);
+        run(&mut cpu, "lsr $0264");
+        assert!(cpu.bus.read(0x264) == 0b0010_1010);
     }
 
     #[test]
@@ -2546,26 +2269,29 @@ mod tests {
         let mut cpu = CPU::default();
 
         cpu.a = 0b1010_1010;
-        cpu.ora09(0b0101_0101);
+        run(&mut cpu, "ora #$55");
         assert!(cpu.a == 0xff);
+        assert!(cpu.negative);
 
         cpu.a = 0b1010_1010;
-        cpu.ram[1600] = 0b0101_0101;
-        cpu.ora0d(1600);
+        cpu.bus.write(1600, 0b0101_0101);
+        run(&mut cpu, "ora $0640");
         assert!(cpu.a == 0xff);
 
+        cpu.a = 0b1010_1010;
         cpu.x = 2;
-        cpu.ram[0xaa] = 0x10;
-        cpu.ram[0xab] = 0x02;
-        cpu.ram[0x0210] = 0b0101_0101;
-        cpu.ora01(98);
+        cpu.bus.write(0xaa, 0x10);
+        cpu.bus.write(0xab, 0x02);
+        cpu.bus.write(0x0210, 0b0101_0101);
+        run(&mut cpu, "ora ($a8,x)");
         assert!(cpu.a == 0xff);
 
-        cpu.x = 2;
-        cpu.ram[0xaa] = 0x10;
-        cpu.ram[0xab] = 0x02;
-        cpu.ram[0x0212] = 0b0101_0101;
-        cpu.ora11(100);
+        cpu.a = 0b1010_1010;
+        cpu.y = 2;
+        cpu.bus.write(0xaa, 0x10);
+        cpu.bus.write(0xab, 0x02);
+        cpu.bus.write(0x0212, 0b0101_0101);
+        run(&mut cpu, "ora ($aa),y");
         assert!(cpu.a == 0xff);
     }
 
@@ -2574,10 +2300,11 @@ mod tests {
         let mut cpu = CPU::default();
 
         cpu.a = 43;
-        cpu.pha48();
+        run(&mut cpu, "pha");
         cpu.a = 23;
-        cpu.pla68();
+        run(&mut cpu, "pla");
         assert!(cpu.a == 43);
+        assert!(!cpu.zero);
     }
 
     #[test]
@@ -2590,9 +2317,10 @@ mod tests {
         cpu.decimal = false;
         cpu.interrupt_disable = true;
         cpu.zero = false;
-        cpu.b = true;
         let status = cpu.get_status();
-        cpu.php08();
+        run(&mut cpu, "php");
+        // the pushed copy has B set
+        assert!(cpu.bus.read(0x0100 + cpu.sp as u16 + 1) == status | 0b0001_0000);
 
         cpu.carry = 0;
         cpu.negative = true;
@@ -2600,8 +2328,7 @@ mod tests {
         cpu.decimal = true;
         cpu.interrupt_disable = true;
         cpu.zero = true;
-        cpu.b = false;
-        cpu.plp28();
+        run(&mut cpu, "plp");
         assert!(cpu.get_status() == status);
     }
 
@@ -2611,65 +2338,100 @@ mod tests {
 
         cpu.a = 0b0111_0101;
         cpu.carry = 1;
-        cpu.rol2a();
+        run(&mut cpu, "rol a");
         assert!(cpu.a == 0b1110_1011);
 
-        cpu.ram[1200] = 0b0111_0101;
+        cpu.bus.write(1200, 0b0111_0101);
         cpu.carry = 1;
-        cpu.rol2e(1200);
-        assert!(cpu.ram[1200] == 0b1110_1011);
+        run(&mut cpu, "rol $04b0");
+        assert!(cpu.bus.read(1200) == 0b1110_1011);
 
-        cpu.ram[1202] = 0b0111_0101;
+        cpu.bus.write(1202, 0b0111_0101);
         cpu.carry = 1;
         cpu.x = 2;
-        cpu.rol3e(1200);
-        assert!(cpu.ram[1202] == 0b1110_1011);
+        run(&mut cpu, "rol $04b0,x");
+        assert!(cpu.bus.read(1202) == 0b1110_1011);
     }
 
     #[test]
     fn ror_opcodes() {
         let mut cpu = CPU::default();
 
+        // carry goes into bit 7 and bit 0 into carry
         cpu.a = 0b0111_0101;
         cpu.carry = 1;
-        cpu.ror6a();
-        assert!(cpu.a == 0b0011_1011);
+        run(&mut cpu, "ror a");
+        assert!(cpu.a == 0b1011_1010);
+        assert!(cpu.carry == 1);
 
-        cpu.ram[1200] = 0b0111_0101;
+        cpu.bus.write(1200, 0b0111_0100);
         cpu.carry = 1;
-        cpu.ror6e(1200);
-        assert!(cpu.ram[1200] == 0b0011_1011);
+        run(&mut cpu, "ror $04b0");
+        assert!(cpu.bus.read(1200) == 0b1011_1010);
+        assert!(cpu.carry == 0);
 
-        cpu.ram[1202] = 0b0111_0101;
+        cpu.bus.write(1202, 0b0111_0101);
         cpu.carry = 1;
         cpu.x = 2;
-        cpu.ror7e(1200);
-        assert!(cpu.ram[1202] == 0b0011_1011);
+        run(&mut cpu, "ror $04b0,x");
+        assert!(cpu.bus.read(1202) == 0b1011_1010);
     }
 
     #[test]
     fn rti_opcode() {
         let mut cpu = CPU::default();
 
-        cpu.pc = 545;
-        cpu.jsr20(200);
+        run(&mut cpu, "jsr $00c8");
         cpu.negative = true;
         cpu.push_to_stack(cpu.get_status());
         cpu.negative = false;
-        cpu.pc = 111;
-        cpu.rti40();
-        assert!(cpu.pc == 544);
-        assert!(cpu.negative == true);
+        run(&mut cpu, "rti");
+        // unlike RTS, RTI returns to the exact address that was pushed
+        assert!(cpu.pc == PROGRAM + 2);
+        assert!(cpu.negative);
     }
 
     #[test]
     fn rts_opcode() {
         let mut cpu = CPU::default();
 
-        cpu.pc = 200;
-        cpu.jsr20(1000);
-        cpu.rts60();
-        assert!(cpu.pc == 200 - 1);
+        run(&mut cpu, "jsr $03e8");
+        assert!(cpu.pc == 1000);
+        run(&mut cpu, "rts");
+        assert!(cpu.pc == PROGRAM + 3);
+    }
+
+    #[test]
+    fn alu_is_exhaustively_correct() {
+        let mut cpu = CPU::default();
+        for a in 0..=255u8 {
+            for value in 0..=255u8 {
+                for carry in 0..=1u8 {
+                    let sum = a as i16 + value as i16 + carry as i16;
+                    let signed_sum = a as i8 as i16 + value as i8 as i16 + carry as i16;
+                    cpu.a = a;
+                    cpu.carry = carry;
+                    cpu.adc(Operand::Immediate(value));
+                    assert!(cpu.a == sum as u8, "{a} + {value} + {carry}");
+                    assert!(cpu.carry == (sum > 0xff) as u8, "{a} + {value} + {carry}");
+                    assert!(cpu.overflow == i8::try_from(signed_sum).is_err());
+                    assert!(cpu.zero == (sum as u8 == 0));
+                    assert!(cpu.negative == (sum as u8 >= 0x80));
+
+                    let borrow = 1 - carry as i16;
+                    let diff = a as i16 - value as i16 - borrow;
+                    let signed_diff = a as i8 as i16 - value as i8 as i16 - borrow;
+                    cpu.a = a;
+                    cpu.carry = carry;
+                    cpu.sbc(Operand::Immediate(value));
+                    assert!(cpu.a == diff as u8, "{a} - {value} - {borrow}");
+                    assert!(cpu.carry == (diff >= 0) as u8, "{a} - {value} - {borrow}");
+                    assert!(cpu.overflow == i8::try_from(signed_diff).is_err());
+                    assert!(cpu.zero == (diff as u8 == 0));
+                    assert!(cpu.negative == (diff as u8 >= 0x80));
+                }
+            }
+        }
     }
 
     #[test]
@@ -2677,12 +2439,12 @@ mod tests {
         let mut cpu = CPU::default();
         cpu.a = 200;
         cpu.carry = 1;
-        cpu.sbce9(20);
+        run(&mut cpu, "sbc #$14");
         assert!(cpu.a == 180);
 
         cpu.a = 200;
         cpu.carry = 0;
-        cpu.sbce9(20);
+        run(&mut cpu, "sbc #$14");
         assert!(cpu.a == 179);
     }
 
@@ -2690,40 +2452,53 @@ mod tests {
     fn sec_opcode() {
         let mut cpu = CPU::default();
         assert!(cpu.carry == 0);
-        cpu.sec38();
+        run(&mut cpu, "sec");
         assert!(cpu.carry == 1);
     }
 
+    #[test]
+    fn sed_opcode() {
+        let mut cpu = CPU::default();
+        cpu.decimal = false;
+        run(&mut cpu, "sed");
+        assert!(cpu.decimal);
+    }
+
     #[test]
     fn sei_opcode() {
         let mut cpu = CPU::default();
         cpu.interrupt_disable = false;
-        cpu.sei78();
-        assert!(cpu.interrupt_disable == true);
+        run(&mut cpu, "sei");
+        assert!(cpu.interrupt_disable);
     }
 
     #[test]
     fn sta_opcodes() {
         let mut cpu = CPU::default();
         cpu.a = 123;
-        cpu.sta85(100);
-        assert!(cpu.ram[100] == 123);
+        run(&mut cpu, "sta $64");
+        assert!(cpu.bus.read(100) == 123);
     }
 
     #[test]
     fn stx_opcodes() {
         let mut cpu = CPU::default();
         cpu.x = 145;
-        cpu.stx8e(1233);
-        assert!(cpu.ram[1233] == 145);
+        run(&mut cpu, "stx $04d1");
+        assert!(cpu.bus.read(1233) == 145);
     }
 
     #[test]
     fn sty_opcodes() {
         let mut cpu = CPU::default();
         cpu.y = 233;
-        cpu.sty8c(2000);
-        assert!(cpu.ram[2000] == 233);
+        run(&mut cpu, "sty $07d0");
+        assert!(cpu.bus.read(2000) == 233);
+
+        cpu.y = 12;
+        cpu.x = 1;
+        run(&mut cpu, "sty $10,x");
+        assert!(cpu.bus.read(0x11) == 12);
     }
 
     #[test]
@@ -2731,27 +2506,27 @@ mod tests {
         let mut cpu = CPU::default();
 
         cpu.a = 50;
-        cpu.taxaa();
+        run(&mut cpu, "tax");
         assert!(cpu.x == cpu.a);
 
         cpu.a = 90;
-        cpu.taya8();
+        run(&mut cpu, "tay");
         assert!(cpu.y == cpu.a);
 
         cpu.sp = 200;
-        cpu.tsxba();
+        run(&mut cpu, "tsx");
         assert!(cpu.x == cpu.sp);
 
         cpu.x = 155;
-        cpu.txa8a();
+        run(&mut cpu, "txa");
         assert!(cpu.x == cpu.a);
 
         cpu.x = 111;
-        cpu.txs9a();
+        run(&mut cpu, "txs");
         assert!(cpu.x == cpu.sp);
 
         cpu.y = 222;
-        cpu.tya98();
+        run(&mut cpu, "tya");
         assert!(cpu.y == cpu.a);
     }
 }
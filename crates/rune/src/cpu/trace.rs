@@ -0,0 +1,212 @@
+use super::CPU;
+use crate::opcodes::{self, AddrMode, Mnemonic, Opcode};
+use std::io::Write;
+
+impl CPU {
+    /// the instruction at PC and the state it runs in, formatted like nestest.log
+    ///
+    /// `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`
+    pub fn trace_line(&self) -> String {
+        let op = opcodes::decode(self.bus.peek(self.pc));
+        let bytes = (0..op.len as u16)
+            .map(|i| format!("{:02X}", self.bus.peek(self.pc.wrapping_add(i))))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let marker = if op.official { ' ' } else { '*' };
+        let (scanline, dot) = self.bus.ppu_position();
+
+        format!(
+            "{:04X}  {bytes:<9}{marker}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{scanline:>3},{dot:>3} CYC:{}",
+            self.pc,
+            self.annotate(op),
+            self.a,
+            self.x,
+            self.y,
+            self.get_status(),
+            self.sp,
+            self.cycles
+        )
+    }
+
+    /// writes the trace line for the instruction about to run
+    pub(super) fn trace(&mut self) {
+        let line = self.trace_line();
+        if let Some(ref mut tracer) = self.tracer {
+            if writeln!(tracer, "{line}").is_err() {
+                // the log went away, there's no reason to stop the game for it
+                self.tracer = None;
+            }
+        }
+    }
+
+    /// disassembles the instruction at PC along with the addresses and values it will use
+    fn annotate(&self, op: Opcode) -> String {
+        let peek_u16 =
+            |lo: u16, hi: u16| (self.bus.peek(hi) as u16) << 8 | self.bus.peek(lo) as u16;
+        let arg8 = self.bus.peek(self.pc.wrapping_add(1));
+        let arg16 = peek_u16(self.pc.wrapping_add(1), self.pc.wrapping_add(2));
+
+        let operand = match op.mode {
+            AddrMode::Implied => String::new(),
+            AddrMode::Accumulator => "A".to_string(),
+            AddrMode::Immediate => format!("#${arg8:02X}"),
+            AddrMode::ZeroPage => format!("${arg8:02X} = {:02X}", self.bus.peek(arg8 as u16)),
+            AddrMode::ZeroPageX | AddrMode::ZeroPageY => {
+                let (index, name) = if op.mode == AddrMode::ZeroPageX {
+                    (self.x, 'X')
+                } else {
+                    (self.y, 'Y')
+                };
+                let addr = arg8.wrapping_add(index);
+                format!(
+                    "${arg8:02X},{name} @ {addr:02X} = {:02X}",
+                    self.bus.peek(addr as u16)
+                )
+            }
+            AddrMode::Relative => {
+                let target = self.pc.wrapping_add(2).wrapping_add(arg8 as i8 as u16);
+                format!("${target:04X}")
+            }
+            AddrMode::Absolute => match op.mnemonic {
+                Mnemonic::Jmp | Mnemonic::Jsr => format!("${arg16:04X}"),
+                _ => format!("${arg16:04X} = {:02X}", self.bus.peek(arg16)),
+            },
+            AddrMode::AbsoluteX | AddrMode::AbsoluteY => {
+                let (index, name) = if op.mode == AddrMode::AbsoluteX {
+                    (self.x, 'X')
+                } else {
+                    (self.y, 'Y')
+                };
+                let addr = arg16.wrapping_add(index as u16);
+                format!(
+                    "${arg16:04X},{name} @ {addr:04X} = {:02X}",
+                    self.bus.peek(addr)
+                )
+            }
+            AddrMode::Indirect => {
+                // same page wrapping bug as the real thing
+                let msb = (arg16 & 0xFF00) | (arg16.wrapping_add(1) & 0x00FF);
+                format!("(${arg16:04X}) = {:04X}", peek_u16(arg16, msb))
+            }
+            AddrMode::IndirectX => {
+                let pointer = arg8.wrapping_add(self.x);
+                let addr = peek_u16(pointer as u16, pointer.wrapping_add(1) as u16);
+                format!(
+                    "(${arg8:02X},X) @ {pointer:02X} = {addr:04X} = {:02X}",
+                    self.bus.peek(addr)
+                )
+            }
+            AddrMode::IndirectY => {
+                let base = peek_u16(arg8 as u16, arg8.wrapping_add(1) as u16);
+                let addr = base.wrapping_add(self.y as u16);
+                format!(
+                    "(${arg8:02X}),Y = {base:04X} @ {addr:04X} = {:02X}",
+                    self.bus.peek(addr)
+                )
+            }
+        };
+
+        // nestest.log spells ISC as ISB
+        let mnemonic = match op.mnemonic {
+            Mnemonic::Isc => "ISB".to_string(),
+            mnemonic => mnemonic.to_string(),
+        };
+
+        if operand.is_empty() {
+            mnemonic
+        } else {
+            format!("{mnemonic} {operand}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Timing;
+    use std::sync::{Arc, Mutex};
+
+    /// a log that can still be read after being handed to the CPU
+    #[derive(Clone, Default)]
+    struct SharedLog(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedLog {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn nestest_format() {
+        let mut cpu = CPU::default();
+        cpu.set_timing(Timing::Cycle);
+        cpu.power_on();
+        let program = [
+            0xa9, 0x42, // LDA #$42
+            0x85, 0x10, // STA $10
+            0xb5, 0x0f, // LDA $0F,X
+            0x99, 0x00, 0x03, // STA $0300,Y
+            0xa1, 0x1f, // LDA ($1F,X)
+            0xb1, 0x20, // LDA ($20),Y
+            0x04, 0x10, // NOP $10
+            0xd0, 0x00, // BNE $0611
+            0x6c, 0x22, 0x00, // JMP ($0022)
+            0x00, 0x00, // skipped by the JMP
+            0xe7, 0x10, // ISC $10
+        ];
+        for (i, byte) in program.iter().enumerate() {
+            cpu.bus.write(0x0600 + i as u16, *byte);
+        }
+        for (addr, val) in [(0x20, 0x00), (0x21, 0x03), (0x22, 0x16), (0x23, 0x06)] {
+            cpu.bus.write(addr, val);
+        }
+        cpu.pc = 0x0600;
+        cpu.x = 1;
+        cpu.y = 2;
+
+        let log = SharedLog::default();
+        cpu.set_tracer(Some(Box::new(log.clone())));
+        for _ in 0..10 {
+            cpu.step();
+        }
+
+        let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        let expected = [
+            "0600  A9 42     LDA #$42                        A:00 X:01 Y:02 P:24 SP:FD PPU:  0, 21 CYC:7",
+            "0602  85 10     STA $10 = 00                    A:42 X:01 Y:02 P:24 SP:FD PPU:  0, 27 CYC:9",
+            "0604  B5 0F     LDA $0F,X @ 10 = 42             A:42 X:01 Y:02 P:24 SP:FD PPU:  0, 36 CYC:12",
+            "0606  99 00 03  STA $0300,Y @ 0302 = 00         A:42 X:01 Y:02 P:24 SP:FD PPU:  0, 48 CYC:16",
+            "0609  A1 1F     LDA ($1F,X) @ 20 = 0300 = 00    A:42 X:01 Y:02 P:24 SP:FD PPU:  0, 63 CYC:21",
+            "060B  B1 20     LDA ($20),Y = 0300 @ 0302 = 42  A:00 X:01 Y:02 P:26 SP:FD PPU:  0, 81 CYC:27",
+            "060D  04 10    *NOP $10 = 42                    A:42 X:01 Y:02 P:24 SP:FD PPU:  0, 96 CYC:32",
+            "060F  D0 00     BNE $0611                       A:42 X:01 Y:02 P:24 SP:FD PPU:  0,105 CYC:35",
+            "0611  6C 22 00  JMP ($0022) = 0616              A:42 X:01 Y:02 P:24 SP:FD PPU:  0,114 CYC:38",
+            "0616  E7 10    *ISB $10 = 42                    A:42 X:01 Y:02 P:24 SP:FD PPU:  0,129 CYC:43",
+        ];
+        for (line, expected) in log.lines().zip(expected) {
+            assert!(line == expected, "\n{line}\n{expected}");
+        }
+        assert!(log.lines().count() == 10);
+    }
+
+    #[test]
+    fn tracing_can_be_turned_off() {
+        let mut cpu = CPU::default();
+        let log = SharedLog::default();
+        cpu.set_tracer(Some(Box::new(log.clone())));
+        cpu.step();
+        cpu.set_tracer(None);
+        cpu.step();
+        assert!(
+            String::from_utf8(log.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .count()
+                == 1
+        );
+    }
+}
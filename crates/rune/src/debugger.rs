@@ -0,0 +1,454 @@
+use crate::cpu::{Registers, CPU};
+use crate::opcodes;
+use crate::scheduler;
+use std::fmt;
+
+mod repl;
+
+pub use repl::repl;
+
+/// JSR, it nests one subroutine deeper
+const JSR: u8 = 0x20;
+/// RTS, it comes back out of one subroutine
+const RTS: u8 = 0x60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    Sp,
+    P,
+    Pc,
+}
+
+impl Register {
+    fn get(self, regs: Registers) -> u16 {
+        match self {
+            Register::A => regs.a as u16,
+            Register::X => regs.x as u16,
+            Register::Y => regs.y as u16,
+            Register::Sp => regs.sp as u16,
+            Register::P => regs.p as u16,
+            Register::Pc => regs.pc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// `register comparison value`, e.g. `X == 5`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition {
+    pub fn holds(&self, regs: Registers) -> bool {
+        let register = self.register.get(regs);
+        match self.comparison {
+            Comparison::Eq => register == self.value,
+            Comparison::Ne => register != self.value,
+            Comparison::Lt => register < self.value,
+            Comparison::Le => register <= self.value,
+            Comparison::Gt => register > self.value,
+            Comparison::Ge => register >= self.value,
+        }
+    }
+}
+
+/// Stops before the instruction at `addr` runs, if `condition` holds then
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    pub condition: Option<Condition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Execute,
+}
+
+/// Stops when the CPU accesses anything in `start..=end`
+///
+/// reads and writes stop after the instruction that made them, executes before it runs.
+/// Operand fetches don't count as reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+/// Why the debugger handed control back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// the requested step is done
+    Step,
+    Breakpoint(u16),
+    Watchpoint {
+        kind: WatchKind,
+        addr: u16,
+        val: u8,
+    },
+    /// the CPU ran into a KIL opcode
+    Halted,
+    /// ran for as many instructions as it was allowed to
+    Limit,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::Step => write!(f, "stepped"),
+            Stop::Breakpoint(addr) => write!(f, "breakpoint at ${addr:04X}"),
+            Stop::Watchpoint { kind, addr, val } => {
+                let access = match kind {
+                    WatchKind::Read => "read",
+                    WatchKind::Write => "write",
+                    WatchKind::Execute => "execute",
+                };
+                write!(f, "watchpoint: {access} ${addr:04X} = {val:02X}")
+            }
+            Stop::Halted => write!(f, "the CPU is jammed by a KIL opcode"),
+            Stop::Limit => write!(f, "instruction limit reached"),
+        }
+    }
+}
+
+/// Runs the CPU under control of breakpoints and watchpoints
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    /// the PC a breakpoint or execute watchpoint last stopped on, so resuming gets past it
+    reported: Option<u16>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger::default()
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+    }
+
+    /// `index` is the breakpoint's position in `breakpoints`
+    pub fn remove_breakpoint(&mut self, index: usize) -> Option<Breakpoint> {
+        (index < self.breakpoints.len()).then(|| self.breakpoints.remove(index))
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// `index` is the watchpoint's position in `watchpoints`
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        (index < self.watchpoints.len()).then(|| self.watchpoints.remove(index))
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// runs a single instruction, going into subroutines
+    pub fn step_in(&mut self, cpu: &mut CPU) -> Stop {
+        self.run(cpu, 1, None, false).unwrap_or(Stop::Step)
+    }
+
+    /// runs a single instruction, or a whole subroutine if it's a JSR
+    pub fn step_over(&mut self, cpu: &mut CPU, limit: u64) -> Stop {
+        if cpu.bus.peek(cpu.pc()) == JSR {
+            self.run(cpu, limit, Some(0), true).unwrap_or(Stop::Limit)
+        } else {
+            self.step_in(cpu)
+        }
+    }
+
+    /// runs until the current subroutine returns
+    pub fn step_out(&mut self, cpu: &mut CPU, limit: u64) -> Stop {
+        self.run(cpu, limit, Some(-1), true).unwrap_or(Stop::Limit)
+    }
+
+    /// runs until something stops it or `limit` instructions went by
+    pub fn resume(&mut self, cpu: &mut CPU, limit: u64) -> Stop {
+        self.run(cpu, limit, None, true).unwrap_or(Stop::Limit)
+    }
+
+    /// runs up to `limit` instructions, `None` when all of them went by uninterrupted
+    ///
+    /// with `stop_depth` it also stops once an RTS leaves it that many subroutines deep,
+    /// counting from where it started. `check_first` is whether breakpoints apply to the
+    /// first instruction, one that was already stopped on is never checked again.
+    fn run(
+        &mut self,
+        cpu: &mut CPU,
+        limit: u64,
+        stop_depth: Option<i32>,
+        check_first: bool,
+    ) -> Option<Stop> {
+        if cpu.halted() {
+            return Some(Stop::Halted);
+        }
+
+        let record = self
+            .watchpoints
+            .iter()
+            .any(|watch| watch.kind != WatchKind::Execute);
+        cpu.bus.record_accesses(record);
+        let check_first = check_first && self.reported != Some(cpu.pc());
+        let stop = self.run_recorded(cpu, limit, stop_depth, check_first);
+        cpu.bus.record_accesses(false);
+
+        self.reported = match stop {
+            Some(Stop::Breakpoint(_))
+            | Some(Stop::Watchpoint {
+                kind: WatchKind::Execute,
+                ..
+            }) => Some(cpu.pc()),
+            _ => None,
+        };
+        stop
+    }
+
+    fn run_recorded(
+        &mut self,
+        cpu: &mut CPU,
+        limit: u64,
+        stop_depth: Option<i32>,
+        check_first: bool,
+    ) -> Option<Stop> {
+        let mut depth = 0;
+        for i in 0..limit {
+            let pc = cpu.pc();
+            if i > 0 || check_first {
+                if let Some(stop) = self.check_pc(cpu) {
+                    return Some(stop);
+                }
+            }
+
+            let opcode = cpu.bus.peek(pc);
+            scheduler::step(cpu);
+            if cpu.halted() {
+                return Some(Stop::Halted);
+            }
+            if let Some(stop) = self.check_accesses(cpu, pc, opcodes::decode(opcode).len) {
+                return Some(stop);
+            }
+
+            match opcode {
+                JSR => depth += 1,
+                RTS => depth -= 1,
+                _ => continue,
+            }
+            if opcode == RTS && Some(depth) == stop_depth {
+                return Some(Stop::Step);
+            }
+        }
+        None
+    }
+
+    /// breakpoints and execute watchpoints on the instruction about to run
+    fn check_pc(&self, cpu: &CPU) -> Option<Stop> {
+        let regs = cpu.registers();
+        let pc = regs.pc;
+        if let Some(breakpoint) = self.breakpoints.iter().find(|breakpoint| {
+            breakpoint.addr == pc && breakpoint.condition.is_none_or(|cond| cond.holds(regs))
+        }) {
+            return Some(Stop::Breakpoint(breakpoint.addr));
+        }
+
+        self.watchpoints
+            .iter()
+            .find(|watch| {
+                watch.kind == WatchKind::Execute && (watch.start..=watch.end).contains(&pc)
+            })
+            .map(|_| Stop::Watchpoint {
+                kind: WatchKind::Execute,
+                addr: pc,
+                val: cpu.bus.peek(pc),
+            })
+    }
+
+    /// reads and writes made by the instruction of `len` bytes at `pc`
+    fn check_accesses(&self, cpu: &mut CPU, pc: u16, len: u8) -> Option<Stop> {
+        let fetched = pc..pc.wrapping_add(len as u16);
+        cpu.bus.take_accesses().into_iter().find_map(|access| {
+            let kind = if access.write {
+                WatchKind::Write
+            } else if fetched.contains(&access.addr) {
+                return None;
+            } else {
+                WatchKind::Read
+            };
+
+            self.watchpoints
+                .iter()
+                .any(|watch| watch.kind == kind && (watch.start..=watch.end).contains(&access.addr))
+                .then_some(Stop::Watchpoint {
+                    kind,
+                    addr: access.addr,
+                    val: access.val,
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    /// a CPU about to run `source` out of RAM at 0x0600
+    fn cpu_running(source: &str) -> CPU {
+        let program = asm::assemble(&format!(".org $0600\n{source}")).unwrap();
+        let mut cpu = CPU::default();
+        for (i, byte) in program.bytes.into_iter().enumerate() {
+            cpu.bus.write(program.origin + i as u16, byte);
+        }
+        cpu.set_pc(program.origin);
+        cpu
+    }
+
+    const COUNTER: &str = "
+                ldx #0
+        loop:   inx         ; $0602
+                stx $10
+                jmp loop    ; $0605
+    ";
+
+    #[test]
+    fn breakpoints() {
+        let mut cpu = cpu_running(COUNTER);
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(Breakpoint {
+            addr: 0x0602,
+            condition: None,
+        });
+
+        assert!(debugger.resume(&mut cpu, 100) == Stop::Breakpoint(0x0602));
+        assert!(cpu.registers().x == 0);
+        // resuming runs past the breakpoint it's sitting on
+        assert!(debugger.resume(&mut cpu, 100) == Stop::Breakpoint(0x0602));
+        assert!(cpu.registers().x == 1);
+
+        debugger.remove_breakpoint(0);
+        assert!(debugger.resume(&mut cpu, 100) == Stop::Limit);
+    }
+
+    #[test]
+    fn conditional_breakpoints() {
+        let mut cpu = cpu_running(COUNTER);
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(Breakpoint {
+            addr: 0x0605,
+            condition: Some(Condition {
+                register: Register::X,
+                comparison: Comparison::Ge,
+                value: 5,
+            }),
+        });
+
+        assert!(debugger.resume(&mut cpu, 100) == Stop::Breakpoint(0x0605));
+        assert!(cpu.registers().x == 5);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut cpu = cpu_running("lda $10\nsta $11\nnop");
+        cpu.bus.write(0x10, 0x42);
+        let mut debugger = Debugger::new();
+        for (addr, kind) in [
+            (0x10, WatchKind::Read),
+            (0x11, WatchKind::Write),
+            (0x0604, WatchKind::Execute),
+        ] {
+            debugger.add_watchpoint(Watchpoint {
+                start: addr,
+                end: addr,
+                kind,
+            });
+        }
+
+        let stops = [
+            Stop::Watchpoint {
+                kind: WatchKind::Read,
+                addr: 0x10,
+                val: 0x42,
+            },
+            Stop::Watchpoint {
+                kind: WatchKind::Write,
+                addr: 0x11,
+                val: 0x42,
+            },
+            Stop::Watchpoint {
+                kind: WatchKind::Execute,
+                addr: 0x0604,
+                val: 0xea,
+            },
+        ];
+        for stop in stops {
+            assert!(debugger.resume(&mut cpu, 100) == stop);
+        }
+        // the instruction is stopped before it runs
+        assert!(cpu.pc() == 0x0604);
+    }
+
+    #[test]
+    fn stepping() {
+        let mut cpu = cpu_running(
+            "
+                    jsr sub
+                    nop         ; $0603
+                    nop
+            sub:    inx         ; $0605
+                    jsr inner
+                    inx         ; $0609
+                    rts
+            inner:  iny
+                    rts
+            ",
+        );
+        let mut debugger = Debugger::new();
+
+        assert!(debugger.step_in(&mut cpu) == Stop::Step);
+        assert!(cpu.pc() == 0x0605);
+        assert!(debugger.step_in(&mut cpu) == Stop::Step);
+        // over the nested JSR
+        assert!(debugger.step_over(&mut cpu, 100) == Stop::Step);
+        assert!(cpu.pc() == 0x0609);
+        assert!(cpu.registers().y == 1);
+        assert!(debugger.step_out(&mut cpu, 100) == Stop::Step);
+        assert!(cpu.pc() == 0x0603);
+        assert!(cpu.registers().x == 2);
+
+        // stepping over anything else is a single step
+        assert!(debugger.step_over(&mut cpu, 100) == Stop::Step);
+        assert!(cpu.pc() == 0x0604);
+    }
+
+    #[test]
+    fn halted() {
+        let mut cpu = cpu_running("kil");
+        let mut debugger = Debugger::new();
+        assert!(debugger.resume(&mut cpu, 100) == Stop::Halted);
+        assert!(debugger.step_in(&mut cpu) == Stop::Halted);
+    }
+}
@@ -0,0 +1,343 @@
+use super::{Breakpoint, Comparison, Condition, Debugger, Register, Stop, WatchKind, Watchpoint};
+use crate::cpu::CPU;
+use crate::disasm;
+use crate::mmap;
+use std::io::{self, BufRead, Write};
+
+/// instructions `continue`, `next` and `finish` run before giving the prompt back,
+/// so a program that never stops doesn't take the debugger with it
+const RUN_LIMIT: u64 = 10_000_000;
+
+const HELP: &str = "\
+addresses and values are hex, counts are decimal
+  s, step [count]              run one instruction, going into subroutines
+  n, next                      run one instruction, or a whole JSR
+  f, finish                    run until the current subroutine returns
+  c, continue [count]          run until a breakpoint or watchpoint, or for count instructions
+                               (10000000 by default, continue again to go on)
+  b, break <addr> [if <reg> <op> <value>]
+                               break at addr, reg is a/x/y/sp/p/pc, op is == != < <= > >=
+  w, watch <r|w|x> <addr>[-<end>]
+                               stop on reads, writes or execution in a range
+  d, delete <b|w> <index>      remove a breakpoint or watchpoint
+  l, list                      show breakpoints and watchpoints
+  r, regs                      show the registers and the next instruction
+  m, mem <addr> [len]          dump memory
+  u, disasm [addr] [count]     disassemble from addr, PC by default
+  stack                        dump the stack
+  h, help                      show this
+  q, quit                      leave the debugger";
+
+/// reads commands from `input` until it runs out or the user quits
+///
+/// nothing runs between commands, the CPU only moves when told to
+pub fn repl(
+    cpu: &mut CPU,
+    debugger: &mut Debugger,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<()> {
+    writeln!(output, "{}", cpu.trace_line())?;
+    loop {
+        write!(output, "(rune) ")?;
+        output.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = args.split_first() else {
+            continue;
+        };
+
+        match command {
+            "q" | "quit" => return Ok(()),
+            "h" | "help" => writeln!(output, "{HELP}")?,
+            "s" | "step" => {
+                let count = args.first().map_or(Some(1), |count| count.parse().ok());
+                let Some(count) = count else {
+                    writeln!(output, "bad count")?;
+                    continue;
+                };
+                let mut stop = Stop::Step;
+                for _ in 0..count {
+                    stop = debugger.step_in(cpu);
+                    if stop != Stop::Step {
+                        break;
+                    }
+                }
+                report(cpu, stop, &mut output)?;
+            }
+            "n" | "next" => {
+                let stop = debugger.step_over(cpu, RUN_LIMIT);
+                report(cpu, stop, &mut output)?;
+            }
+            "f" | "finish" => {
+                let stop = debugger.step_out(cpu, RUN_LIMIT);
+                report(cpu, stop, &mut output)?;
+            }
+            "c" | "continue" => {
+                let limit = args
+                    .first()
+                    .map_or(Some(RUN_LIMIT), |count| count.parse().ok());
+                let Some(limit) = limit else {
+                    writeln!(output, "bad count")?;
+                    continue;
+                };
+                let stop = debugger.resume(cpu, limit);
+                report(cpu, stop, &mut output)?;
+            }
+            "b" | "break" => match parse_breakpoint(args) {
+                Some(breakpoint) => debugger.add_breakpoint(breakpoint),
+                None => writeln!(output, "usage: break <addr> [if <reg> <op> <value>]")?,
+            },
+            "w" | "watch" => match parse_watchpoint(args) {
+                Some(watchpoint) => debugger.add_watchpoint(watchpoint),
+                None => writeln!(output, "usage: watch <r|w|x> <addr>[-<end>]")?,
+            },
+            "d" | "delete" => {
+                let index = args.get(1).and_then(|index| index.parse().ok());
+                let removed = match (args.first(), index) {
+                    (Some(&"b"), Some(index)) => debugger.remove_breakpoint(index).is_some(),
+                    (Some(&"w"), Some(index)) => debugger.remove_watchpoint(index).is_some(),
+                    _ => {
+                        writeln!(output, "usage: delete <b|w> <index>")?;
+                        continue;
+                    }
+                };
+                if !removed {
+                    writeln!(output, "no such index")?;
+                }
+            }
+            "l" | "list" => list(debugger, &mut output)?,
+            "r" | "regs" => writeln!(output, "{}", cpu.trace_line())?,
+            "m" | "mem" => {
+                let addr = args.first().and_then(|addr| parse_hex(addr));
+                let len = args.get(1).map_or(Some(0x40), |len| parse_hex(len));
+                match (addr, len) {
+                    (Some(addr), Some(len)) => dump(cpu, addr, len, &mut output)?,
+                    _ => writeln!(output, "usage: mem <addr> [len]")?,
+                }
+            }
+            "u" | "disasm" => {
+                let addr = args.first().map_or(Some(cpu.pc()), |addr| parse_hex(addr));
+                let count = args.get(1).map_or(Some(10), |count| count.parse().ok());
+                match (addr, count) {
+                    (Some(addr), Some(count)) => {
+                        let mut last = addr;
+                        for _ in 1..count {
+                            let (_, len) = disasm::disassemble(&cpu.bus, last);
+                            last = last.saturating_add(len as u16);
+                        }
+                        write!(output, "{}", disasm::listing(&cpu.bus, addr..=last))?;
+                    }
+                    _ => writeln!(output, "usage: disasm [addr] [count]")?,
+                }
+            }
+            "stack" => {
+                let sp = cpu.registers().sp;
+                let top = mmap::ram::stack::START as u16 + sp as u16 + 1;
+                let len = mmap::ram::stack::END as u16 + 1 - top;
+                dump(cpu, top, len, &mut output)?;
+            }
+            _ => writeln!(output, "unknown command {command}, try help")?,
+        }
+    }
+}
+
+fn report(cpu: &CPU, stop: Stop, output: &mut impl Write) -> io::Result<()> {
+    if stop != Stop::Step {
+        writeln!(output, "{stop}")?;
+    }
+    writeln!(output, "{}", cpu.trace_line())
+}
+
+fn list(debugger: &Debugger, output: &mut impl Write) -> io::Result<()> {
+    for (i, breakpoint) in debugger.breakpoints().iter().enumerate() {
+        write!(output, "b {i}: ${:04X}", breakpoint.addr)?;
+        if let Some(cond) = breakpoint.condition {
+            write!(
+                output,
+                " if {:?} {} ${:X}",
+                cond.register,
+                comparison_symbol(cond.comparison),
+                cond.value
+            )?;
+        }
+        writeln!(output)?;
+    }
+    for (i, watchpoint) in debugger.watchpoints().iter().enumerate() {
+        writeln!(
+            output,
+            "w {i}: {:?} ${:04X}-${:04X}",
+            watchpoint.kind, watchpoint.start, watchpoint.end
+        )?;
+    }
+    Ok(())
+}
+
+/// 16 bytes per row, read without side effects
+fn dump(cpu: &CPU, addr: u16, len: u16, output: &mut impl Write) -> io::Result<()> {
+    for row in (0..len).step_by(16) {
+        let start = addr.wrapping_add(row);
+        write!(output, "{start:04X}:")?;
+        for i in 0..(len - row).min(16) {
+            write!(output, " {:02X}", cpu.bus.peek(start.wrapping_add(i)))?;
+        }
+        writeln!(output)?;
+    }
+    Ok(())
+}
+
+/// `C000`, `$C000` and `0xC000` are all the same address
+fn parse_hex(arg: &str) -> Option<u16> {
+    let digits = arg
+        .strip_prefix('$')
+        .or_else(|| arg.strip_prefix("0x"))
+        .unwrap_or(arg);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn parse_breakpoint(args: &[&str]) -> Option<Breakpoint> {
+    let condition = match args {
+        [_] => None,
+        [_, "if", register, comparison, value] => Some(Condition {
+            register: match register.to_lowercase().as_str() {
+                "a" => Register::A,
+                "x" => Register::X,
+                "y" => Register::Y,
+                "sp" => Register::Sp,
+                "p" => Register::P,
+                "pc" => Register::Pc,
+                _ => return None,
+            },
+            comparison: match *comparison {
+                "==" => Comparison::Eq,
+                "!=" => Comparison::Ne,
+                "<" => Comparison::Lt,
+                "<=" => Comparison::Le,
+                ">" => Comparison::Gt,
+                ">=" => Comparison::Ge,
+                _ => return None,
+            },
+            value: parse_hex(value)?,
+        }),
+        _ => return None,
+    };
+    Some(Breakpoint {
+        addr: parse_hex(args[0])?,
+        condition,
+    })
+}
+
+fn parse_watchpoint(args: &[&str]) -> Option<Watchpoint> {
+    let [kind, range] = args else {
+        return None;
+    };
+    let kind = match *kind {
+        "r" => WatchKind::Read,
+        "w" => WatchKind::Write,
+        "x" => WatchKind::Execute,
+        _ => return None,
+    };
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse_hex(start)?, parse_hex(end)?),
+        None => (parse_hex(range)?, parse_hex(range)?),
+    };
+    Some(Watchpoint { start, end, kind })
+}
+
+fn comparison_symbol(comparison: Comparison) -> &'static str {
+    match comparison {
+        Comparison::Eq => "==",
+        Comparison::Ne => "!=",
+        Comparison::Lt => "<",
+        Comparison::Le => "<=",
+        Comparison::Gt => ">",
+        Comparison::Ge => ">=",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    /// runs the commands against a counting loop and returns everything printed
+    fn session(commands: &str) -> String {
+        let program = asm::assemble(
+            "
+                    .org $0600
+                    ldx #0
+            loop:   inx         ; $0602
+                    stx $10
+                    jmp loop    ; $0605
+            ",
+        )
+        .unwrap();
+        let mut cpu = CPU::default();
+        for (i, byte) in program.bytes.into_iter().enumerate() {
+            cpu.bus.write(program.origin + i as u16, byte);
+        }
+        cpu.set_pc(program.origin);
+
+        let mut output = vec![];
+        repl(
+            &mut cpu,
+            &mut Debugger::new(),
+            commands.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn breaks_and_inspects() {
+        let output = session("b 0605 if x == 3\nw w 10\nl\nc\nd w 0\nc\nm 10 2\nq\n");
+        assert!(output.contains("b 0: $0605 if X == $3"));
+        assert!(output.contains("w 0: Write $0010-$0010"));
+        assert!(output.contains("watchpoint: write $0010 = 01"));
+        assert!(output.contains("breakpoint at $0605"));
+        assert!(output.contains("0010: 03 00"));
+    }
+
+    #[test]
+    fn steps() {
+        let output = session("s 2\nstack\nu 0602 2\nbogus\n");
+        assert!(output.contains("0603  86 10     STX $10 = 00"));
+        assert!(output.contains("0602  E8        INX\n0603  86 10     STX $10\n(rune)"));
+        assert!(output.contains("unknown command bogus"));
+    }
+
+    #[test]
+    fn continues_for_a_count() {
+        // nothing stops the loop, so it only runs as long as it's told to
+        let output = session(
+            "c 4
+m 10 1
+c many
+",
+        );
+        assert!(output.contains("instruction limit reached"));
+        assert!(output.contains("0010: 01"));
+        assert!(output.contains("bad count"));
+    }
+
+    #[test]
+    fn parses_arguments() {
+        assert!(parse_hex("$c000") == Some(0xc000));
+        assert!(parse_hex("0x10") == Some(0x10));
+        assert!(parse_hex("zz").is_none());
+        assert!(parse_breakpoint(&["c000", "if", "a"]).is_none());
+        assert!(
+            parse_watchpoint(&["r", "2000-2007"])
+                == Some(Watchpoint {
+                    start: 0x2000,
+                    end: 0x2007,
+                    kind: WatchKind::Read
+                })
+        );
+    }
+}
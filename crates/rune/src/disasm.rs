@@ -0,0 +1,231 @@
+use crate::bus::Bus;
+use crate::mmap;
+use crate::opcodes::{self, AddrMode, Mnemonic, Opcode};
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// A decoded instruction and where it sits in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub addr: u16,
+    pub opcode: u8,
+    pub op: Opcode,
+    /// the operand bytes as a little endian number, 0 when there are none
+    pub operand: u16,
+}
+
+impl Instruction {
+    /// where a branch, JMP or JSR goes, indirect jumps aren't known until they run
+    pub fn target(&self) -> Option<u16> {
+        match (self.op.mode, self.op.mnemonic) {
+            (AddrMode::Relative, _) => Some(
+                self.addr
+                    .wrapping_add(2)
+                    .wrapping_add(self.operand as u8 as i8 as u16),
+            ),
+            (AddrMode::Absolute, Mnemonic::Jmp | Mnemonic::Jsr) => Some(self.operand),
+            _ => None,
+        }
+    }
+}
+
+/// standard assembler syntax, e.g. `LDA ($44),Y` or `BNE $C084`
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic = self.op.mnemonic;
+        let operand = self.operand;
+        match self.op.mode {
+            AddrMode::Implied => write!(f, "{mnemonic}"),
+            AddrMode::Accumulator => write!(f, "{mnemonic} A"),
+            AddrMode::Immediate => write!(f, "{mnemonic} #${operand:02X}"),
+            AddrMode::ZeroPage => write!(f, "{mnemonic} ${operand:02X}"),
+            AddrMode::ZeroPageX => write!(f, "{mnemonic} ${operand:02X},X"),
+            AddrMode::ZeroPageY => write!(f, "{mnemonic} ${operand:02X},Y"),
+            AddrMode::Relative => write!(f, "{mnemonic} ${:04X}", self.target().unwrap()),
+            AddrMode::Absolute => write!(f, "{mnemonic} ${operand:04X}"),
+            AddrMode::AbsoluteX => write!(f, "{mnemonic} ${operand:04X},X"),
+            AddrMode::AbsoluteY => write!(f, "{mnemonic} ${operand:04X},Y"),
+            AddrMode::Indirect => write!(f, "{mnemonic} (${operand:04X})"),
+            AddrMode::IndirectX => write!(f, "{mnemonic} (${operand:02X},X)"),
+            AddrMode::IndirectY => write!(f, "{mnemonic} (${operand:02X}),Y"),
+        }
+    }
+}
+
+/// decodes the instruction at `addr` without side effects, returns it with its size in bytes
+pub fn disassemble(bus: &Bus, addr: u16) -> (Instruction, u8) {
+    let opcode = bus.peek(addr);
+    let op = opcodes::decode(opcode);
+    let operand = match op.len {
+        2 => bus.peek(addr.wrapping_add(1)) as u16,
+        3 => (bus.peek(addr.wrapping_add(2)) as u16) << 8 | bus.peek(addr.wrapping_add(1)) as u16,
+        _ => 0,
+    };
+
+    (
+        Instruction {
+            addr,
+            opcode,
+            op,
+            operand,
+        },
+        op.len,
+    )
+}
+
+/// decodes instructions back to back, starting at the beginning of `range`
+///
+/// the last one may run past the end of the range, but never past 0xffff
+pub fn disassemble_range(bus: &Bus, range: RangeInclusive<u16>) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    let mut addr = *range.start() as u32;
+    while addr <= *range.end() as u32 {
+        let (instruction, len) = disassemble(bus, addr as u16);
+        instructions.push(instruction);
+        addr += len as u32;
+    }
+    instructions
+}
+
+/// names for where the interrupt vectors currently point
+pub fn vector_labels(bus: &Bus) -> [(u16, &'static str); 3] {
+    let vector =
+        |addr: usize| (bus.peek(addr as u16 + 1) as u16) << 8 | bus.peek(addr as u16) as u16;
+    [
+        (vector(mmap::cpu::nmi::START), "nmi"),
+        (vector(mmap::cpu::reset::START), "reset"),
+        (vector(mmap::cpu::irq_brk::START), "irq"),
+    ]
+}
+
+/// a listing of `range` with raw bytes and the vectors' labels, one instruction per line
+///
+/// jumps and branches into a vector's handler are annotated with its label
+///
+/// ```text
+/// reset:
+/// C000  78        SEI
+/// C001  4C 00 C0  JMP $C000 ; reset
+/// ```
+pub fn listing(bus: &Bus, range: RangeInclusive<u16>) -> String {
+    let labels = vector_labels(bus);
+    let mut listing = String::new();
+    for instruction in disassemble_range(bus, range) {
+        for (_, label) in labels.iter().filter(|(addr, _)| *addr == instruction.addr) {
+            listing += &format!("{label}:\n");
+        }
+
+        let bytes = (0..instruction.op.len as u16)
+            .map(|i| format!("{:02X}", bus.peek(instruction.addr.wrapping_add(i))))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let marker = if instruction.op.official { ' ' } else { '*' };
+        listing += &format!("{:04X}  {bytes:<9}{marker}{instruction}", instruction.addr);
+        let targets = labels
+            .iter()
+            .filter(|(addr, _)| Some(*addr) == instruction.target())
+            .map(|(_, label)| *label)
+            .collect::<Vec<_>>();
+        if !targets.is_empty() {
+            listing += &format!(" ; {}", targets.join(", "));
+        }
+        listing += "\n";
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::cartridge::Cartridge;
+    use rune_ines::InesFile;
+
+    fn bus_with(addr: u16, bytes: &[u8]) -> Bus {
+        let mut bus = Bus::default();
+        for (i, byte) in bytes.iter().enumerate() {
+            bus.write(addr + i as u16, *byte);
+        }
+        bus
+    }
+
+    #[test]
+    fn operand_syntax() {
+        let cases: [(&[u8], &str); 13] = [
+            (&[0x18], "CLC"),
+            (&[0x0a], "ASL A"),
+            (&[0xa9, 0x44], "LDA #$44"),
+            (&[0xa5, 0x44], "LDA $44"),
+            (&[0xb5, 0x44], "LDA $44,X"),
+            (&[0xb6, 0x44], "LDX $44,Y"),
+            (&[0xd0, 0xfe], "BNE $0200"),
+            (&[0xad, 0x00, 0x44], "LDA $4400"),
+            (&[0xbd, 0x00, 0x44], "LDA $4400,X"),
+            (&[0xb9, 0x00, 0x44], "LDA $4400,Y"),
+            (&[0x6c, 0x97, 0x55], "JMP ($5597)"),
+            (&[0xa1, 0x44], "LDA ($44,X)"),
+            (&[0xb1, 0x44], "LDA ($44),Y"),
+        ];
+        for (bytes, text) in cases {
+            let (instruction, len) = disassemble(&bus_with(0x0200, bytes), 0x0200);
+            assert!(instruction.to_string() == text, "{instruction}");
+            assert!(len as usize == bytes.len());
+        }
+    }
+
+    #[test]
+    fn branch_targets() {
+        // BCC *+$12, BMI *-$10
+        let bus = bus_with(0x0200, &[0x90, 0x10, 0x30, 0xee]);
+        let (forward, _) = disassemble(&bus, 0x0200);
+        let (backward, _) = disassemble(&bus, 0x0202);
+        assert!(forward.target() == Some(0x0212));
+        assert!(backward.target() == Some(0x01f2));
+        assert!(backward.to_string() == "BMI $01F2");
+    }
+
+    #[test]
+    fn ranges() {
+        // SEI, JSR $0200, unofficial NOP $44, RTS
+        let bus = bus_with(0x0000, &[0x78, 0x20, 0x00, 0x02, 0x04, 0x44, 0x60]);
+        let instructions = disassemble_range(&bus, 0x0000..=0x0005);
+        let addrs: Vec<u16> = instructions.iter().map(|i| i.addr).collect();
+        assert!(addrs == [0x0000, 0x0001, 0x0004]);
+    }
+
+    #[test]
+    fn labels_vectors() {
+        let rom = asm::assemble_ines(
+            "
+                    .org $8000
+            reset:  sei
+                    jsr sub
+                    .byte $04, $44
+                    jmp reset
+            sub:    bne irq
+            irq:
+            nmi:    rti
+                    .org $fffa
+                    .word nmi, reset, irq
+            ",
+        )
+        .unwrap();
+        let mut bus = Bus::default();
+        bus.attach_cartridge(Cartridge::from_ines(InesFile::from_bytes(&rom)).unwrap());
+
+        let listing = listing(&bus, 0x8000..=0x800b);
+        assert!(listing.starts_with("reset:\n8000  78        SEI\n"));
+        assert!(listing.contains("8001  20 09 80  JSR $8009\n"));
+        assert!(listing.contains("8004  04 44    *NOP $44\n"));
+        assert!(listing.contains("8006  4C 00 80  JMP $8000 ; reset\n"));
+        assert!(listing.contains("8009  D0 00     BNE $800B ; nmi, irq\n"));
+        assert!(listing.ends_with("nmi:\nirq:\n800B  40        RTI\n"));
+    }
+
+    #[test]
+    fn stops_at_the_end_of_memory() {
+        // reads as BRKs with nothing attached
+        let bus = Bus::default();
+        assert!(disassemble_range(&bus, 0xfffe..=0xffff).len() == 2);
+    }
+}
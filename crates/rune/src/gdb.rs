@@ -0,0 +1,480 @@
+use crate::cpu::{Registers, CPU};
+use crate::debugger::{Breakpoint, Debugger, Stop, WatchKind, Watchpoint};
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+/// instructions run between checks for an interrupt from the debugger
+const CONTINUE_CHUNK: u64 = 10_000;
+
+/// Exposes the CPU to a debugger over the GDB remote serial protocol
+///
+/// https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+///
+/// registers are sent as A, X, Y, P and SP bytes followed by PC in little endian.
+/// Z0/Z1 set breakpoints, Z2/Z3/Z4 write/read/access watchpoints.
+pub struct GdbStub {
+    stream: TcpStream,
+    debugger: Debugger,
+    /// bytes that arrived while the CPU was running, read before the stream
+    pending: VecDeque<u8>,
+}
+
+/// waits for a debugger to connect then serves it until it detaches
+pub fn serve(cpu: &mut CPU, listener: &TcpListener) -> io::Result<()> {
+    let (stream, _) = listener.accept()?;
+    GdbStub::new(stream)?.run(cpu)
+}
+
+impl GdbStub {
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(GdbStub {
+            stream,
+            debugger: Debugger::new(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// answers packets until the debugger detaches, kills the session or hangs up
+    pub fn run(&mut self, cpu: &mut CPU) -> io::Result<()> {
+        while let Some(packet) = self.receive()? {
+            let reply = match packet.as_bytes().first() {
+                Some(b'D') => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+                Some(b'k') => return Ok(()),
+                _ => self.handle(cpu, &packet)?,
+            };
+            self.send(&reply)?;
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, cpu: &mut CPU, packet: &str) -> io::Result<String> {
+        let (Some(command), Some(args)) = (packet.get(..1), packet.get(1..)) else {
+            return Ok(String::new());
+        };
+        Ok(match command {
+            "?" => "S05".to_string(),
+            "g" => hex(&register_bytes(cpu.registers())),
+            "G" => match unhex(args).and_then(|bytes| registers_from(&bytes)) {
+                Some(regs) => {
+                    cpu.set_registers(regs);
+                    "OK".to_string()
+                }
+                None => "E01".to_string(),
+            },
+            "m" => match parse_range(args) {
+                Some((addr, len)) => {
+                    let bytes: Vec<u8> = (0..len)
+                        .map(|i| cpu.bus.peek(addr.wrapping_add(i)))
+                        .collect();
+                    hex(&bytes)
+                }
+                None => "E01".to_string(),
+            },
+            "M" => {
+                let write = args
+                    .split_once(':')
+                    .and_then(|(range, data)| Some((parse_range(range)?, unhex(data)?)));
+                match write {
+                    Some(((addr, len), data)) if data.len() == len as usize => {
+                        for (i, byte) in data.into_iter().enumerate() {
+                            cpu.bus.write(addr.wrapping_add(i as u16), byte);
+                        }
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "Z" | "z" => self.toggle_point(command == "Z", args),
+            "s" | "c" if !args.is_empty() => match u16::from_str_radix(args, 16) {
+                Ok(addr) => {
+                    cpu.set_pc(addr);
+                    return self.handle(cpu, command);
+                }
+                Err(_) => "E01".to_string(),
+            },
+            "s" => stop_reply(self.debugger.step_in(cpu)),
+            "c" => self.resume(cpu)?,
+            "q" if args.starts_with("Supported") => "PacketSize=4000".to_string(),
+            "q" if args == "Attached" => "1".to_string(),
+            "H" => "OK".to_string(),
+            // an empty reply tells the debugger the packet isn't supported
+            _ => String::new(),
+        })
+    }
+
+    /// runs until something stops it, or the debugger interrupts with ^C
+    fn resume(&mut self, cpu: &mut CPU) -> io::Result<String> {
+        loop {
+            match self.debugger.resume(cpu, CONTINUE_CHUNK) {
+                Stop::Limit => (),
+                stop => return Ok(stop_reply(stop)),
+            }
+
+            if self.poll_interrupt()? {
+                return Ok("S02".to_string());
+            }
+        }
+    }
+
+    /// reads whatever the debugger sent while the CPU ran, true if it was a ^C
+    ///
+    /// acks between packets are dropped, anything else is kept for `receive`
+    fn poll_interrupt(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut interrupted = false;
+        let mut byte = [0];
+        let result = loop {
+            match self.stream.read(&mut byte) {
+                Ok(0) => break Err(ErrorKind::UnexpectedEof.into()),
+                Ok(_) if self.pending.is_empty() && byte[0] == 0x03 => interrupted = true,
+                Ok(_) if self.pending.is_empty() && matches!(byte[0], b'+' | b'-') => (),
+                Ok(_) => self.pending.push_back(byte[0]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break Ok(interrupted),
+                Err(err) => break Err(err),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result
+    }
+
+    /// `Z<type>,<addr>,<kind>` inserts and `z` removes
+    fn toggle_point(&mut self, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let kind = fields.next();
+        let addr = fields
+            .next()
+            .and_then(|addr| u16::from_str_radix(addr, 16).ok());
+        let len = fields
+            .next()
+            .and_then(|len| u16::from_str_radix(len, 16).ok());
+        let (Some(kind), Some(addr)) = (kind, addr) else {
+            return "E01".to_string();
+        };
+
+        let watch_kinds: &[WatchKind] = match kind {
+            "0" | "1" => &[],
+            "2" => &[WatchKind::Write],
+            "3" => &[WatchKind::Read],
+            "4" => &[WatchKind::Read, WatchKind::Write],
+            _ => return String::new(),
+        };
+
+        if watch_kinds.is_empty() {
+            let breakpoint = Breakpoint {
+                addr,
+                condition: None,
+            };
+            let existing = self
+                .debugger
+                .breakpoints()
+                .iter()
+                .position(|b| *b == breakpoint);
+            match (insert, existing) {
+                (true, None) => self.debugger.add_breakpoint(breakpoint),
+                (false, Some(index)) => {
+                    self.debugger.remove_breakpoint(index);
+                }
+                _ => (),
+            }
+        }
+
+        let end = addr.saturating_add(len.unwrap_or(1).max(1) - 1);
+        for &kind in watch_kinds {
+            let watchpoint = Watchpoint {
+                start: addr,
+                end,
+                kind,
+            };
+            let existing = self
+                .debugger
+                .watchpoints()
+                .iter()
+                .position(|w| *w == watchpoint);
+            match (insert, existing) {
+                (true, None) => self.debugger.add_watchpoint(watchpoint),
+                (false, Some(index)) => {
+                    self.debugger.remove_watchpoint(index);
+                }
+                _ => (),
+            }
+        }
+        "OK".to_string()
+    }
+
+    /// the next packet's data, `None` once the connection is closed
+    ///
+    /// acknowledges packets with good checksums and asks for the others again
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // skip acks and anything else outside of a packet
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'$') => break,
+                    Some(_) => (),
+                }
+            }
+
+            let mut data = vec![];
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let (Some(hi), Some(lo)) = (self.read_byte()?, self.read_byte()?) else {
+                return Ok(None);
+            };
+
+            let expected = std::str::from_utf8(&[hi, lo])
+                .ok()
+                .and_then(|sum| u8::from_str_radix(sum, 16).ok());
+            if expected == Some(checksum(&data)) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${data}#{:02x}", checksum(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.pending.pop_front() {
+            return Ok(Some(byte));
+        }
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+}
+
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        // SIGILL
+        Stop::Halted => "S04".to_string(),
+        // SIGTRAP
+        _ => "S05".to_string(),
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// `addr,len` in hex
+fn parse_range(args: &str) -> Option<(u16, u16)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((
+        u16::from_str_radix(addr, 16).ok()?,
+        u16::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+fn register_bytes(regs: Registers) -> [u8; 7] {
+    let [pc_lo, pc_hi] = regs.pc.to_le_bytes();
+    [regs.a, regs.x, regs.y, regs.p, regs.sp, pc_lo, pc_hi]
+}
+
+fn registers_from(bytes: &[u8]) -> Option<Registers> {
+    let [a, x, y, p, sp, pc_lo, pc_hi] = bytes.try_into().ok()?;
+    Some(Registers {
+        pc: u16::from_le_bytes([pc_lo, pc_hi]),
+        sp,
+        a,
+        x,
+        y,
+        p,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use std::thread;
+
+    /// a debugger frontend talking to a stub on another thread
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        /// sends a packet and returns the reply's data
+        fn send(&mut self, data: &str) -> String {
+            let packet = format!("${data}#{:02x}", checksum(data.as_bytes()));
+            self.stream.write_all(packet.as_bytes()).unwrap();
+            self.reply()
+        }
+
+        fn reply(&mut self) -> String {
+            let mut reply = vec![];
+            let mut byte = [0];
+            while reply.last() != Some(&b'#') {
+                self.stream.read_exact(&mut byte).unwrap();
+                if !(reply.is_empty() && byte[0] == b'+') {
+                    reply.push(byte[0]);
+                }
+            }
+            let mut sum = [0; 2];
+            self.stream.read_exact(&mut sum).unwrap();
+            self.stream.write_all(b"+").unwrap();
+
+            let data = &reply[1..reply.len() - 1];
+            assert!(reply[0] == b'$');
+            assert!(
+                u8::from_str_radix(std::str::from_utf8(&sum).unwrap(), 16).unwrap()
+                    == checksum(data)
+            );
+            String::from_utf8(data.to_vec()).unwrap()
+        }
+    }
+
+    /// starts a stub on a loopback port with `source` loaded at 0x0600
+    fn connect(source: &str) -> (Client, thread::JoinHandle<CPU>) {
+        let program = asm::assemble(&format!(".org $0600\n{source}")).unwrap();
+        let mut cpu = CPU::default();
+        for (i, byte) in program.bytes.into_iter().enumerate() {
+            cpu.bus.write(0x0600 + i as u16, byte);
+        }
+        cpu.set_pc(0x0600);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stub = thread::spawn(move || {
+            serve(&mut cpu, &listener).unwrap();
+            cpu
+        });
+        let client = Client {
+            stream: TcpStream::connect(addr).unwrap(),
+        };
+        (client, stub)
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let (mut client, stub) = connect("nop");
+        assert!(client.send("qSupported:swbreak+") == "PacketSize=4000");
+        assert!(client.send("?") == "S05");
+        assert!(client.send("g") == "00000024ff0006");
+        assert!(client.send("G0102032440fe07") == "OK");
+        assert!(client.send("g") == "0102032440fe07");
+
+        assert!(client.send("m0600,2") == "ea00");
+        assert!(client.send("M0010,3:aabbcc") == "OK");
+        assert!(client.send("m0010,3") == "aabbcc");
+        assert!(client.send("M0010,3:aa") == "E01");
+        assert!(client.send("vMustReplyEmpty").is_empty());
+        assert!(client.send("D") == "OK");
+
+        let cpu = stub.join().unwrap();
+        assert!(cpu.pc() == 0x07fe);
+        assert!(cpu.bus.peek(0x11) == 0xbb);
+    }
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        let (mut client, stub) = connect(
+            "
+                    ldx #0
+            loop:   inx         ; $0602
+                    stx $10
+                    jmp loop
+            ",
+        );
+        assert!(client.send("s") == "S05");
+        assert!(client.send("g").ends_with("0206"));
+
+        assert!(client.send("Z0,0605,1") == "OK");
+        assert!(client.send("c") == "S05");
+        assert!(client.send("g").ends_with("0506"));
+        assert!(client.send("c") == "S05");
+        assert!(client.send("m0010,1") == "02");
+        assert!(client.send("z0,0605,1") == "OK");
+
+        assert!(client.send("Z2,0010,1") == "OK");
+        assert!(client.send("c") == "S05");
+        assert!(client.send("m0010,1") == "03");
+        assert!(client.send("z2,0010,1") == "OK");
+
+        // resuming from somewhere else
+        assert!(client.send("s0602") == "S05");
+        assert!(client.send("g").ends_with("0306"));
+        assert!(client.send("c06zz") == "E01");
+
+        // nothing left to stop it, so interrupt it
+        let packet = "$c#63";
+        client.stream.write_all(packet.as_bytes()).unwrap();
+        client.stream.write_all(&[0x03]).unwrap();
+        assert!(client.reply() == "S02");
+
+        assert!(client.send("D") == "OK");
+        stub.join().unwrap();
+    }
+
+    #[test]
+    fn packets_sent_while_running_are_kept() {
+        let (mut client, stub) = connect(
+            "
+                    ldy #0
+            outer:  ldx #0
+            inner:  dex
+                    bne inner
+                    dey
+                    bne outer
+                    sty $10
+            done:   jmp done    ; $060c
+            ",
+        );
+        assert!(client.send("Z0,060c,1") == "OK");
+        client.stream.write_all(b"$c#63").unwrap();
+        // a stray ack and a whole packet arrive before the breakpoint is hit
+        client.stream.write_all(b"+").unwrap();
+        let packet = "M0010,1:aa";
+        let packet = format!("${packet}#{:02x}", checksum(packet.as_bytes()));
+        client.stream.write_all(packet.as_bytes()).unwrap();
+
+        assert!(client.reply() == "S05");
+        assert!(client.reply() == "OK");
+        assert!(client.send("m0010,1") == "aa");
+        client.send("D");
+        stub.join().unwrap();
+    }
+
+    #[test]
+    fn bad_checksums_are_retransmitted() {
+        let (mut client, stub) = connect("nop");
+        client.stream.write_all(b"$?#00").unwrap();
+        let mut nack = [0];
+        client.stream.read_exact(&mut nack).unwrap();
+        assert!(nack == *b"-");
+        assert!(client.send("?") == "S05");
+        client.send("D");
+        stub.join().unwrap();
+    }
+}
@@ -0,0 +1,13 @@
+pub mod asm;
+pub mod blargg;
+pub mod bus;
+pub mod cartridge;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod gdb;
+pub mod mapper;
+pub mod mmap;
+pub mod opcodes;
+pub mod ppu;
+pub mod scheduler;
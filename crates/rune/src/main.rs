@@ -1,14 +1,154 @@
-mod cpu;
-mod mmap;
-
+use rune::blargg;
+use rune::bus::Bus;
+use rune::cartridge::Cartridge;
+use rune::cpu;
+use rune::debugger::{self, Debugger};
+use rune::disasm;
+use rune::gdb;
+use rune::ppu::Ppu;
+use rune::scheduler::{Scheduler, Throttle};
 use rune_ines::InesFile;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::TcpListener;
+
+const USAGE: &str = "usage: rune [rom] [--trace <file>] [--blargg] [--debug] [--gdb <port>]
+       rune disasm <rom>";
+
+/// how long a test ROM gets to report a result, a minute of emulated time
+const BLARGG_TIMEOUT_FRAMES: u64 = 3600;
 
 fn main() {
+    let mut rom_path = String::from("./test.nes");
+    let mut trace_path = None;
+    let mut blargg = false;
+    let mut debug = false;
+    let mut gdb_port = None;
+
+    let mut args = std::env::args().skip(1).peekable();
+    if args.peek().is_some_and(|arg| arg == "disasm") {
+        match args.nth(1) {
+            Some(path) => print_disassembly(&path),
+            None => {
+                eprintln!("{USAGE}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => match args.next() {
+                Some(path) => trace_path = Some(path),
+                None => {
+                    eprintln!("{USAGE}");
+                    std::process::exit(1);
+                }
+            },
+            "--blargg" => blargg = true,
+            "--debug" => debug = true,
+            "--gdb" => match args.next().and_then(|port| port.parse::<u16>().ok()) {
+                Some(port) => gdb_port = Some(port),
+                None => {
+                    eprintln!("{USAGE}");
+                    std::process::exit(1);
+                }
+            },
+            _ => rom_path = arg,
+        }
+    }
+
+    let cartridge = match Cartridge::from_ines(InesFile::open(&rom_path)) {
+        Ok(cartridge) => cartridge,
+        Err(mapper) => {
+            eprintln!("mapper {mapper} is not supported");
+            std::process::exit(1);
+        }
+    };
+
+    let mut scheduler = Scheduler::new(cartridge.region(), Throttle::WallClock);
     let mut cpu = cpu::CPU::default();
-    let rom = InesFile::open("./test.nes");
-    cpu.load_rom(rom.prg_rom);
+    cpu.bus.attach_ppu(Box::new(Ppu::new(cartridge.region())));
+    cpu.bus.attach_cartridge(cartridge);
+    cpu.power_on();
+
+    if let Some(path) = trace_path {
+        match File::create(&path) {
+            Ok(file) => cpu.set_tracer(Some(Box::new(BufWriter::new(file)))),
+            Err(err) => {
+                eprintln!("could not create {path}: {err}");
+                std::process::exit(1);
+            }
+        }
+    }
 
-    loop {
-        cpu.cycle();
+    if let Some(port) = gdb_port {
+        let result = TcpListener::bind(("127.0.0.1", port)).and_then(|listener| {
+            eprintln!("waiting for a debugger on 127.0.0.1:{port}");
+            gdb::serve(&mut cpu, &listener)
+        });
+        cpu.set_tracer(None);
+        if let Err(err) = result {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        return;
     }
+
+    if debug {
+        let stdin = std::io::stdin();
+        if let Err(err) = debugger::repl(
+            &mut cpu,
+            &mut Debugger::new(),
+            stdin.lock(),
+            std::io::stdout(),
+        ) {
+            eprintln!("{err}");
+        }
+        cpu.set_tracer(None);
+        return;
+    }
+
+    if blargg {
+        let result = blargg::run(&mut cpu, BLARGG_TIMEOUT_FRAMES);
+        cpu.set_tracer(None);
+        match result {
+            Ok(outcome) => {
+                println!("{}", outcome.text.trim_end());
+                std::process::exit(if outcome.passed() { 0 } else { 1 });
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    while !cpu.halted() {
+        scheduler.run_frame(&mut cpu);
+    }
+
+    // flushes the trace
+    cpu.set_tracer(None);
+    eprintln!("the CPU ran into a KIL opcode and locked up");
+    std::process::exit(1);
+}
+
+/// lists all of the ROM's PRG as code, from wherever the mapper maps it at power on
+fn print_disassembly(rom_path: &str) {
+    let rom = InesFile::open(rom_path);
+    let prg_size = rom.prg_rom.len().min(0x8000) as u16;
+    let mut bus = Bus::default();
+    match Cartridge::from_ines(rom) {
+        Ok(cartridge) => bus.attach_cartridge(cartridge),
+        Err(mapper) => {
+            eprintln!("mapper {mapper} is not supported");
+            std::process::exit(1);
+        }
+    }
+
+    let start = 0u16.wrapping_sub(prg_size);
+    // a closed pipe (e.g. piping into head) is not worth a panic
+    let _ = std::io::stdout().write_all(disasm::listing(&bus, start..=0xffff).as_bytes());
 }
@@ -0,0 +1,123 @@
+mod nrom;
+
+pub use nrom::Nrom;
+
+use rune_ines::InesFile;
+
+/// How the PPU's two physical nametables are laid out in its 4 logical ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    /// all 4 nametables show the first physical one
+    SingleScreenA,
+    /// all 4 nametables show the second physical one
+    SingleScreenB,
+    /// the board brings 2 KiB of VRAM of its own so every nametable is distinct
+    FourScreen,
+}
+
+impl Mirroring {
+    pub fn from_header(rom: &InesFile) -> Mirroring {
+        if rom.header.ignores_mirroring_ctl() {
+            Mirroring::FourScreen
+        } else if rom.header.has_vertical_arrangement() {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        }
+    }
+
+    /// where a nametable address (0x2000 - 0x3eff) lands in the console's 2 KiB of VRAM,
+    /// followed by the cartridge's 2 KiB for four-screen boards
+    ///
+    /// horizontal mirroring stacks 0x2000 over 0x2800, vertical puts 0x2000 beside 0x2400
+    pub fn vram_index(self, addr: u16) -> usize {
+        let table = match self {
+            Mirroring::Horizontal => (addr >> 11) & 1,
+            Mirroring::Vertical => (addr >> 10) & 1,
+            Mirroring::SingleScreenA => 0,
+            Mirroring::SingleScreenB => 1,
+            Mirroring::FourScreen => (addr >> 10) & 0b11,
+        };
+        table as usize * 0x400 + (addr & 0x3ff) as usize
+    }
+}
+
+/// The circuitry on a cartridge board that decides what the CPU and PPU see
+pub trait Mapper: Send {
+    /// handles CPU accesses to 0x4020 - 0xffff
+    ///
+    /// `None` when nothing on the board answers, the CPU then reads open bus
+    fn cpu_read(&mut self, addr: u16) -> Option<u8>;
+    fn cpu_write(&mut self, addr: u16, val: u8);
+    /// what `cpu_read` would return, without any of its side effects
+    fn cpu_peek(&self, addr: u16) -> Option<u8>;
+
+    /// handles PPU accesses to the pattern tables at 0x0000 - 0x1fff
+    fn ppu_read(&mut self, addr: u16) -> u8;
+    fn ppu_write(&mut self, addr: u16, val: u8);
+
+    /// looked up on every nametable access, so boards that switch mirroring
+    /// only have to return their current setting
+    fn mirroring(&self) -> Mirroring;
+
+    /// whether the board is currently asserting the CPU's IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// called once per CPU cycle, for boards that count cycles
+    fn cpu_tick(&mut self) {}
+}
+
+/// builds the mapper declared in the ROM's header
+///
+/// returns the mapper number back if it is not supported
+pub fn from_ines(rom: InesFile) -> Result<Box<dyn Mapper>, u8> {
+    match rom.header.get_mapper() {
+        0 => Ok(Box::new(Nrom::new(rom))),
+        mapper => Err(mapper),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_unsupported_mappers() {
+        let mut header = [0u8; 16];
+        header[0..4].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a]);
+        header[4] = 1;
+        // mapper 1 (MMC1)
+        header[6] = 0b0001_0000;
+
+        let rom = InesFile {
+            header: rune_ines::InesHeader::parse(&header).unwrap(),
+            trainer: None,
+            prg_rom: vec![0; 16384],
+            chr_rom: vec![],
+            inst_rom: None,
+            prom: None,
+        };
+
+        assert!(matches!(from_ines(rom), Err(1)));
+    }
+
+    #[test]
+    fn vram_index() {
+        let tables = |mirroring: Mirroring| {
+            [0x2000, 0x2400, 0x2800, 0x2c00].map(|addr| mirroring.vram_index(addr) / 0x400)
+        };
+        assert!(tables(Mirroring::Horizontal) == [0, 0, 1, 1]);
+        assert!(tables(Mirroring::Vertical) == [0, 1, 0, 1]);
+        assert!(tables(Mirroring::SingleScreenA) == [0, 0, 0, 0]);
+        assert!(tables(Mirroring::SingleScreenB) == [1, 1, 1, 1]);
+        assert!(tables(Mirroring::FourScreen) == [0, 1, 2, 3]);
+
+        // 0x3000 - 0x3eff mirrors 0x2000 - 0x2eff
+        assert!(Mirroring::FourScreen.vram_index(0x3c05) == 0xc05);
+        assert!(Mirroring::Vertical.vram_index(0x2fff) == 0x7ff);
+    }
+}
@@ -0,0 +1,172 @@
+use super::{Mapper, Mirroring};
+use crate::mmap::{cartrige, vram};
+use rune_ines::InesFile;
+
+/// Mapper 0, no bank switching at all
+///
+/// NROM-128 boards have 16 KiB of PRG ROM that is mirrored over 0x8000 - 0xffff,
+/// NROM-256 boards fill the whole range with 32 KiB.
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    /// Family Basic style 8 KiB work RAM at 0x6000 - 0x7fff
+    prg_ram: [u8; 0x2000],
+    /// the pattern tables at PPU 0x0000 - 0x1fff
+    chr: Vec<u8>,
+    /// boards whose header declares no CHR ROM carry 8 KiB of CHR RAM instead
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(rom: InesFile) -> Nrom {
+        // a header promising CHR ROM without any in the image gets RAM as well
+        let chr_is_ram = rom.header.chr_size == 0 || rom.chr_rom.is_empty();
+        Nrom {
+            mirroring: Mirroring::from_header(&rom),
+            prg_rom: rom.prg_rom,
+            prg_ram: [0; 0x2000],
+            chr: if chr_is_ram {
+                vec![0; vram::pattern_tables::END + 1]
+            } else {
+                rom.chr_rom
+            },
+            chr_is_ram,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        let addr = addr as usize;
+        match addr {
+            cartrige::prg_rom::START..=cartrige::prg_rom::END => {
+                Some(self.prg_rom[(addr - cartrige::prg_rom::START) % self.prg_rom.len()])
+            }
+            cartrige::prg_ram::START..=cartrige::prg_ram::END => {
+                Some(self.prg_ram[addr - cartrige::prg_ram::START])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        let addr = addr as usize;
+        if let cartrige::prg_ram::START..=cartrige::prg_ram::END = addr {
+            self.prg_ram[addr - cartrige::prg_ram::START] = val;
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[addr as usize % self.chr.len()]
+    }
+
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        if self.chr_is_ram {
+            let len = self.chr.len();
+            self.chr[addr as usize % len] = val;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rune_ines::InesHeader;
+
+    fn rom(prg_banks: u8, flags6: u8) -> InesFile {
+        let mut header = [0u8; 16];
+        header[0..4].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a]);
+        header[4] = prg_banks;
+        header[5] = 1;
+        header[6] = flags6;
+
+        let prg_rom = (0..prg_banks as usize * 16384)
+            .map(|i| (i / 16384) as u8 + 1)
+            .collect();
+
+        InesFile {
+            header: InesHeader::parse(&header).unwrap(),
+            trainer: None,
+            prg_rom,
+            chr_rom: vec![0xcc; 8192],
+            inst_rom: None,
+            prom: None,
+        }
+    }
+
+    #[test]
+    fn nrom128_mirrors_prg() {
+        let mut nrom = Nrom::new(rom(1, 0));
+        assert!(nrom.cpu_read(0x8000) == Some(1));
+        assert!(nrom.cpu_read(0xc000) == Some(1));
+        assert!(nrom.cpu_read(0xffff) == Some(1));
+    }
+
+    #[test]
+    fn nrom256_maps_both_banks() {
+        let mut nrom = Nrom::new(rom(2, 0));
+        assert!(nrom.cpu_read(0x8000) == Some(1));
+        assert!(nrom.cpu_read(0xbfff) == Some(1));
+        assert!(nrom.cpu_read(0xc000) == Some(2));
+        assert!(nrom.cpu_read(0xffff) == Some(2));
+        // nothing on the board answers below the work RAM
+        assert!(nrom.cpu_read(0x5fff).is_none());
+    }
+
+    #[test]
+    fn prg_rom_is_read_only() {
+        let mut nrom = Nrom::new(rom(1, 0));
+        nrom.cpu_write(0x8000, 0xff);
+        assert!(nrom.cpu_read(0x8000) == Some(1));
+
+        nrom.cpu_write(0x6000, 0xab);
+        assert!(nrom.cpu_read(0x6000) == Some(0xab));
+    }
+
+    #[test]
+    fn mirroring_comes_from_header() {
+        assert!(Nrom::new(rom(1, 0)).mirroring() == Mirroring::Horizontal);
+        assert!(Nrom::new(rom(1, 1)).mirroring() == Mirroring::Vertical);
+        // the four-screen bit wins over the arrangement bit
+        assert!(Nrom::new(rom(1, 0b1001)).mirroring() == Mirroring::FourScreen);
+    }
+
+    #[test]
+    fn chr_rom() {
+        let mut nrom = Nrom::new(rom(1, 0));
+        assert!(nrom.ppu_read(0x0000) == 0xcc);
+        assert!(nrom.ppu_read(0x1fff) == 0xcc);
+
+        nrom.ppu_write(0x0010, 0xab);
+        assert!(nrom.ppu_read(0x0010) == 0xcc);
+    }
+
+    #[test]
+    fn chr_ram() {
+        let mut ram_board = rom(1, 0);
+        ram_board.header.chr_size = 0;
+        ram_board.chr_rom = vec![];
+        let mut nrom = Nrom::new(ram_board);
+        assert!(nrom.ppu_read(0x1fff) == 0);
+
+        nrom.ppu_write(0x0010, 0xab);
+        nrom.ppu_write(0x1fff, 0xcd);
+        assert!(nrom.ppu_read(0x0010) == 0xab);
+        assert!(nrom.ppu_read(0x1fff) == 0xcd);
+
+        // the header says there's CHR ROM but the image has none
+        let mut malformed = rom(1, 0);
+        malformed.chr_rom = vec![];
+        let mut nrom = Nrom::new(malformed);
+        nrom.ppu_write(0x1000, 0xef);
+        assert!(nrom.ppu_read(0x1000) == 0xef);
+    }
+}
@@ -1,6 +1,7 @@
 pub mod ram {
     pub const START: usize = 0x0;
     pub const END: usize = 0x07FF;
+    pub const MIRRORS_END: usize = 0x1FFF;
 
     pub mod zero_page {
         pub const START: usize = 0x0;
@@ -33,14 +34,51 @@ pub mod cpu {
 pub mod ppu {
     pub const START: usize = 0x2000;
     pub const END: usize = 0x2007;
+    pub const MIRRORS_END: usize = 0x3FFF;
+    /// OAMDATA, where OAM DMA writes every byte it copies
+    pub const OAM_DATA: usize = 0x2004;
 }
 
 pub mod apu_io_registers {
     pub const START: usize = 0x4000;
     pub const END: usize = 0x4017;
+    /// writing a page number here copies that page to the PPU's OAM
+    pub const OAM_DMA: usize = 0x4014;
+    pub const JOY1: usize = 0x4016;
+    pub const JOY2: usize = 0x4017;
 }
 
 pub mod cartrige {
     pub const START: usize = 0x4020;
     pub const END: usize = 0xFFFF;
+
+    pub mod prg_ram {
+        pub const START: usize = 0x6000;
+        pub const END: usize = 0x7FFF;
+    }
+
+    pub mod prg_rom {
+        pub const START: usize = 0x8000;
+        pub const END: usize = 0xFFFF;
+    }
+}
+
+/// PPU address space
+pub mod vram {
+    pub mod pattern_tables {
+        pub const START: usize = 0x0000;
+        pub const END: usize = 0x1FFF;
+    }
+
+    pub mod nametables {
+        pub const START: usize = 0x2000;
+        pub const END: usize = 0x2FFF;
+        pub const MIRRORS_END: usize = 0x3EFF;
+    }
+
+    pub mod palette {
+        pub const START: usize = 0x3F00;
+        pub const END: usize = 0x3F1F;
+        pub const MIRRORS_END: usize = 0x3FFF;
+    }
 }
@@ -0,0 +1,583 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddrMode {
+    /// CLC
+    Implied,
+    /// ASL A
+    Accumulator,
+    /// LDA #$44
+    Immediate,
+    /// LDA $44
+    ZeroPage,
+    /// LDA $44,X
+    ZeroPageX,
+    /// LDX $44,Y
+    ZeroPageY,
+    /// BNE $44, the operand is a signed offset from the next instruction
+    Relative,
+    /// LDA $4400
+    Absolute,
+    /// LDA $4400,X
+    AbsoluteX,
+    /// LDA $4400,Y
+    AbsoluteY,
+    /// JMP ($5597)
+    Indirect,
+    /// LDA ($44,X)
+    IndirectX,
+    /// LDA ($44),Y
+    IndirectY,
+}
+
+impl AddrMode {
+    /// size of an instruction using this mode, opcode included
+    pub const fn size(self) -> u8 {
+        match self {
+            AddrMode::Implied | AddrMode::Accumulator => 1,
+            AddrMode::Immediate
+            | AddrMode::ZeroPage
+            | AddrMode::ZeroPageX
+            | AddrMode::ZeroPageY
+            | AddrMode::Relative
+            | AddrMode::IndirectX
+            | AddrMode::IndirectY => 2,
+            AddrMode::Absolute | AddrMode::AbsoluteX | AddrMode::AbsoluteY | AddrMode::Indirect => {
+                3
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mnemonic {
+    Adc,
+    And,
+    Asl,
+    Bcc,
+    Bcs,
+    Beq,
+    Bit,
+    Bmi,
+    Bne,
+    Bpl,
+    Brk,
+    Bvc,
+    Bvs,
+    Clc,
+    Cld,
+    Cli,
+    Clv,
+    Cmp,
+    Cpx,
+    Cpy,
+    Dec,
+    Dex,
+    Dey,
+    Eor,
+    Inc,
+    Inx,
+    Iny,
+    Jmp,
+    Jsr,
+    Lda,
+    Ldx,
+    Ldy,
+    Lsr,
+    Nop,
+    Ora,
+    Pha,
+    Php,
+    Pla,
+    Plp,
+    Rol,
+    Ror,
+    Rti,
+    Rts,
+    Sbc,
+    Sec,
+    Sed,
+    Sei,
+    Sta,
+    Stx,
+    Sty,
+    Tax,
+    Tay,
+    Tsx,
+    Txa,
+    Txs,
+    Tya,
+
+    // unofficial instructions
+    /// AND #imm then LSR A
+    Alr,
+    /// AND #imm, C takes bit 7
+    Anc,
+    /// AND #imm then ROR A with odd C and V
+    Arr,
+    /// X = (A & X) - imm
+    Axs,
+    /// DEC then CMP
+    Dcp,
+    /// INC then SBC
+    Isc,
+    /// locks up the CPU until reset
+    Kil,
+    /// A, X and SP = memory & SP
+    Las,
+    /// LDA then TAX
+    Lax,
+    /// ROL then AND
+    Rla,
+    /// ROR then ADC
+    Rra,
+    /// stores A & X
+    Sax,
+    /// stores A & X & (H + 1)
+    Sha,
+    /// stores X & (H + 1)
+    Shx,
+    /// stores Y & (H + 1)
+    Shy,
+    /// ASL then ORA
+    Slo,
+    /// LSR then EOR
+    Sre,
+    /// SP = A & X, stores A & X & (H + 1)
+    Tas,
+    /// A = (A | magic) & X & imm
+    Xaa,
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format!("{self:?}").to_uppercase())
+    }
+}
+
+/// Everything needed to decode and time an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Opcode {
+    pub mnemonic: Mnemonic,
+    pub mode: AddrMode,
+    /// size in bytes, opcode included
+    pub len: u8,
+    /// cycles taken when no page boundary is crossed
+    pub cycles: u8,
+    /// takes an extra cycle when indexing crosses a page boundary
+    pub page_penalty: bool,
+    /// part of the documented instruction set
+    pub official: bool,
+}
+
+const fn op(mnemonic: Mnemonic, mode: AddrMode, cycles: u8, page_penalty: bool) -> Option<Opcode> {
+    Some(Opcode {
+        mnemonic,
+        mode,
+        len: mode.size(),
+        cycles,
+        page_penalty,
+        official: true,
+    })
+}
+
+const fn unofficial(
+    mnemonic: Mnemonic,
+    mode: AddrMode,
+    cycles: u8,
+    page_penalty: bool,
+) -> Option<Opcode> {
+    Some(Opcode {
+        mnemonic,
+        mode,
+        len: mode.size(),
+        cycles,
+        page_penalty,
+        official: false,
+    })
+}
+
+/// decodes an opcode byte, every one of the 256 values does something
+pub fn decode(opcode: u8) -> Opcode {
+    OPCODES[opcode as usize]
+}
+
+pub static OPCODES: [Opcode; 256] = {
+    use AddrMode::*;
+    use Mnemonic::*;
+
+    let mut table: [Option<Opcode>; 256] = [None; 256];
+
+    table[0x69] = op(Adc, Immediate, 2, false);
+    table[0x65] = op(Adc, ZeroPage, 3, false);
+    table[0x75] = op(Adc, ZeroPageX, 4, false);
+    table[0x6D] = op(Adc, Absolute, 4, false);
+    table[0x7D] = op(Adc, AbsoluteX, 4, true);
+    table[0x79] = op(Adc, AbsoluteY, 4, true);
+    table[0x61] = op(Adc, IndirectX, 6, false);
+    table[0x71] = op(Adc, IndirectY, 5, true);
+
+    table[0x29] = op(And, Immediate, 2, false);
+    table[0x25] = op(And, ZeroPage, 3, false);
+    table[0x35] = op(And, ZeroPageX, 4, false);
+    table[0x2D] = op(And, Absolute, 4, false);
+    table[0x3D] = op(And, AbsoluteX, 4, true);
+    table[0x39] = op(And, AbsoluteY, 4, true);
+    table[0x21] = op(And, IndirectX, 6, false);
+    table[0x31] = op(And, IndirectY, 5, true);
+
+    table[0x0A] = op(Asl, Accumulator, 2, false);
+    table[0x06] = op(Asl, ZeroPage, 5, false);
+    table[0x16] = op(Asl, ZeroPageX, 6, false);
+    table[0x0E] = op(Asl, Absolute, 6, false);
+    table[0x1E] = op(Asl, AbsoluteX, 7, false);
+
+    table[0x90] = op(Bcc, Relative, 2, false);
+    table[0xB0] = op(Bcs, Relative, 2, false);
+    table[0xF0] = op(Beq, Relative, 2, false);
+    table[0x30] = op(Bmi, Relative, 2, false);
+    table[0xD0] = op(Bne, Relative, 2, false);
+    table[0x10] = op(Bpl, Relative, 2, false);
+    table[0x50] = op(Bvc, Relative, 2, false);
+    table[0x70] = op(Bvs, Relative, 2, false);
+
+    table[0x24] = op(Bit, ZeroPage, 3, false);
+    table[0x2C] = op(Bit, Absolute, 4, false);
+
+    table[0x00] = op(Brk, Implied, 7, false);
+
+    table[0x18] = op(Clc, Implied, 2, false);
+    table[0xD8] = op(Cld, Implied, 2, false);
+    table[0x58] = op(Cli, Implied, 2, false);
+    table[0xB8] = op(Clv, Implied, 2, false);
+
+    table[0xC9] = op(Cmp, Immediate, 2, false);
+    table[0xC5] = op(Cmp, ZeroPage, 3, false);
+    table[0xD5] = op(Cmp, ZeroPageX, 4, false);
+    table[0xCD] = op(Cmp, Absolute, 4, false);
+    table[0xDD] = op(Cmp, AbsoluteX, 4, true);
+    table[0xD9] = op(Cmp, AbsoluteY, 4, true);
+    table[0xC1] = op(Cmp, IndirectX, 6, false);
+    table[0xD1] = op(Cmp, IndirectY, 5, true);
+
+    table[0xE0] = op(Cpx, Immediate, 2, false);
+    table[0xE4] = op(Cpx, ZeroPage, 3, false);
+    table[0xEC] = op(Cpx, Absolute, 4, false);
+
+    table[0xC0] = op(Cpy, Immediate, 2, false);
+    table[0xC4] = op(Cpy, ZeroPage, 3, false);
+    table[0xCC] = op(Cpy, Absolute, 4, false);
+
+    table[0xC6] = op(Dec, ZeroPage, 5, false);
+    table[0xD6] = op(Dec, ZeroPageX, 6, false);
+    table[0xCE] = op(Dec, Absolute, 6, false);
+    table[0xDE] = op(Dec, AbsoluteX, 7, false);
+
+    table[0xCA] = op(Dex, Implied, 2, false);
+    table[0x88] = op(Dey, Implied, 2, false);
+
+    table[0x49] = op(Eor, Immediate, 2, false);
+    table[0x45] = op(Eor, ZeroPage, 3, false);
+    table[0x55] = op(Eor, ZeroPageX, 4, false);
+    table[0x4D] = op(Eor, Absolute, 4, false);
+    table[0x5D] = op(Eor, AbsoluteX, 4, true);
+    table[0x59] = op(Eor, AbsoluteY, 4, true);
+    table[0x41] = op(Eor, IndirectX, 6, false);
+    table[0x51] = op(Eor, IndirectY, 5, true);
+
+    table[0xE6] = op(Inc, ZeroPage, 5, false);
+    table[0xF6] = op(Inc, ZeroPageX, 6, false);
+    table[0xEE] = op(Inc, Absolute, 6, false);
+    table[0xFE] = op(Inc, AbsoluteX, 7, false);
+
+    table[0xE8] = op(Inx, Implied, 2, false);
+    table[0xC8] = op(Iny, Implied, 2, false);
+
+    table[0x4C] = op(Jmp, Absolute, 3, false);
+    table[0x6C] = op(Jmp, Indirect, 5, false);
+
+    table[0x20] = op(Jsr, Absolute, 6, false);
+
+    table[0xA9] = op(Lda, Immediate, 2, false);
+    table[0xA5] = op(Lda, ZeroPage, 3, false);
+    table[0xB5] = op(Lda, ZeroPageX, 4, false);
+    table[0xAD] = op(Lda, Absolute, 4, false);
+    table[0xBD] = op(Lda, AbsoluteX, 4, true);
+    table[0xB9] = op(Lda, AbsoluteY, 4, true);
+    table[0xA1] = op(Lda, IndirectX, 6, false);
+    table[0xB1] = op(Lda, IndirectY, 5, true);
+
+    table[0xA2] = op(Ldx, Immediate, 2, false);
+    table[0xA6] = op(Ldx, ZeroPage, 3, false);
+    table[0xB6] = op(Ldx, ZeroPageY, 4, false);
+    table[0xAE] = op(Ldx, Absolute, 4, false);
+    table[0xBE] = op(Ldx, AbsoluteY, 4, true);
+
+    table[0xA0] = op(Ldy, Immediate, 2, false);
+    table[0xA4] = op(Ldy, ZeroPage, 3, false);
+    table[0xB4] = op(Ldy, ZeroPageX, 4, false);
+    table[0xAC] = op(Ldy, Absolute, 4, false);
+    table[0xBC] = op(Ldy, AbsoluteX, 4, true);
+
+    table[0x4A] = op(Lsr, Accumulator, 2, false);
+    table[0x46] = op(Lsr, ZeroPage, 5, false);
+    table[0x56] = op(Lsr, ZeroPageX, 6, false);
+    table[0x4E] = op(Lsr, Absolute, 6, false);
+    table[0x5E] = op(Lsr, AbsoluteX, 7, false);
+
+    table[0xEA] = op(Nop, Implied, 2, false);
+
+    table[0x09] = op(Ora, Immediate, 2, false);
+    table[0x05] = op(Ora, ZeroPage, 3, false);
+    table[0x15] = op(Ora, ZeroPageX, 4, false);
+    table[0x0D] = op(Ora, Absolute, 4, false);
+    table[0x1D] = op(Ora, AbsoluteX, 4, true);
+    table[0x19] = op(Ora, AbsoluteY, 4, true);
+    table[0x01] = op(Ora, IndirectX, 6, false);
+    table[0x11] = op(Ora, IndirectY, 5, true);
+
+    table[0x48] = op(Pha, Implied, 3, false);
+    table[0x08] = op(Php, Implied, 3, false);
+    table[0x68] = op(Pla, Implied, 4, false);
+    table[0x28] = op(Plp, Implied, 4, false);
+
+    table[0x2A] = op(Rol, Accumulator, 2, false);
+    table[0x26] = op(Rol, ZeroPage, 5, false);
+    table[0x36] = op(Rol, ZeroPageX, 6, false);
+    table[0x2E] = op(Rol, Absolute, 6, false);
+    table[0x3E] = op(Rol, AbsoluteX, 7, false);
+
+    table[0x6A] = op(Ror, Accumulator, 2, false);
+    table[0x66] = op(Ror, ZeroPage, 5, false);
+    table[0x76] = op(Ror, ZeroPageX, 6, false);
+    table[0x6E] = op(Ror, Absolute, 6, false);
+    table[0x7E] = op(Ror, AbsoluteX, 7, false);
+
+    table[0x40] = op(Rti, Implied, 6, false);
+    table[0x60] = op(Rts, Implied, 6, false);
+
+    table[0xE9] = op(Sbc, Immediate, 2, false);
+    table[0xE5] = op(Sbc, ZeroPage, 3, false);
+    table[0xF5] = op(Sbc, ZeroPageX, 4, false);
+    table[0xED] = op(Sbc, Absolute, 4, false);
+    table[0xFD] = op(Sbc, AbsoluteX, 4, true);
+    table[0xF9] = op(Sbc, AbsoluteY, 4, true);
+    table[0xE1] = op(Sbc, IndirectX, 6, false);
+    table[0xF1] = op(Sbc, IndirectY, 5, true);
+
+    table[0x38] = op(Sec, Implied, 2, false);
+    table[0xF8] = op(Sed, Implied, 2, false);
+    table[0x78] = op(Sei, Implied, 2, false);
+
+    table[0x85] = op(Sta, ZeroPage, 3, false);
+    table[0x95] = op(Sta, ZeroPageX, 4, false);
+    table[0x8D] = op(Sta, Absolute, 4, false);
+    table[0x9D] = op(Sta, AbsoluteX, 5, false);
+    table[0x99] = op(Sta, AbsoluteY, 5, false);
+    table[0x81] = op(Sta, IndirectX, 6, false);
+    table[0x91] = op(Sta, IndirectY, 6, false);
+
+    table[0x86] = op(Stx, ZeroPage, 3, false);
+    table[0x96] = op(Stx, ZeroPageY, 4, false);
+    table[0x8E] = op(Stx, Absolute, 4, false);
+
+    table[0x84] = op(Sty, ZeroPage, 3, false);
+    table[0x94] = op(Sty, ZeroPageX, 4, false);
+    table[0x8C] = op(Sty, Absolute, 4, false);
+
+    table[0xAA] = op(Tax, Implied, 2, false);
+    table[0xA8] = op(Tay, Implied, 2, false);
+    table[0xBA] = op(Tsx, Implied, 2, false);
+    table[0x8A] = op(Txa, Implied, 2, false);
+    table[0x9A] = op(Txs, Implied, 2, false);
+    table[0x98] = op(Tya, Implied, 2, false);
+
+    // --- UNOFFICIAL ---
+    // https://www.nesdev.org/wiki/CPU_unofficial_opcodes
+
+    table[0x1A] = unofficial(Nop, Implied, 2, false);
+    table[0x3A] = unofficial(Nop, Implied, 2, false);
+    table[0x5A] = unofficial(Nop, Implied, 2, false);
+    table[0x7A] = unofficial(Nop, Implied, 2, false);
+    table[0xDA] = unofficial(Nop, Implied, 2, false);
+    table[0xFA] = unofficial(Nop, Implied, 2, false);
+    table[0x80] = unofficial(Nop, Immediate, 2, false);
+    table[0x82] = unofficial(Nop, Immediate, 2, false);
+    table[0x89] = unofficial(Nop, Immediate, 2, false);
+    table[0xC2] = unofficial(Nop, Immediate, 2, false);
+    table[0xE2] = unofficial(Nop, Immediate, 2, false);
+    table[0x04] = unofficial(Nop, ZeroPage, 3, false);
+    table[0x44] = unofficial(Nop, ZeroPage, 3, false);
+    table[0x64] = unofficial(Nop, ZeroPage, 3, false);
+    table[0x14] = unofficial(Nop, ZeroPageX, 4, false);
+    table[0x34] = unofficial(Nop, ZeroPageX, 4, false);
+    table[0x54] = unofficial(Nop, ZeroPageX, 4, false);
+    table[0x74] = unofficial(Nop, ZeroPageX, 4, false);
+    table[0xD4] = unofficial(Nop, ZeroPageX, 4, false);
+    table[0xF4] = unofficial(Nop, ZeroPageX, 4, false);
+    table[0x0C] = unofficial(Nop, Absolute, 4, false);
+    table[0x1C] = unofficial(Nop, AbsoluteX, 4, true);
+    table[0x3C] = unofficial(Nop, AbsoluteX, 4, true);
+    table[0x5C] = unofficial(Nop, AbsoluteX, 4, true);
+    table[0x7C] = unofficial(Nop, AbsoluteX, 4, true);
+    table[0xDC] = unofficial(Nop, AbsoluteX, 4, true);
+    table[0xFC] = unofficial(Nop, AbsoluteX, 4, true);
+
+    table[0x02] = unofficial(Kil, Implied, 2, false);
+    table[0x12] = unofficial(Kil, Implied, 2, false);
+    table[0x22] = unofficial(Kil, Implied, 2, false);
+    table[0x32] = unofficial(Kil, Implied, 2, false);
+    table[0x42] = unofficial(Kil, Implied, 2, false);
+    table[0x52] = unofficial(Kil, Implied, 2, false);
+    table[0x62] = unofficial(Kil, Implied, 2, false);
+    table[0x72] = unofficial(Kil, Implied, 2, false);
+    table[0x92] = unofficial(Kil, Implied, 2, false);
+    table[0xB2] = unofficial(Kil, Implied, 2, false);
+    table[0xD2] = unofficial(Kil, Implied, 2, false);
+    table[0xF2] = unofficial(Kil, Implied, 2, false);
+
+    table[0x07] = unofficial(Slo, ZeroPage, 5, false);
+    table[0x17] = unofficial(Slo, ZeroPageX, 6, false);
+    table[0x0F] = unofficial(Slo, Absolute, 6, false);
+    table[0x1F] = unofficial(Slo, AbsoluteX, 7, false);
+    table[0x1B] = unofficial(Slo, AbsoluteY, 7, false);
+    table[0x03] = unofficial(Slo, IndirectX, 8, false);
+    table[0x13] = unofficial(Slo, IndirectY, 8, false);
+
+    table[0x27] = unofficial(Rla, ZeroPage, 5, false);
+    table[0x37] = unofficial(Rla, ZeroPageX, 6, false);
+    table[0x2F] = unofficial(Rla, Absolute, 6, false);
+    table[0x3F] = unofficial(Rla, AbsoluteX, 7, false);
+    table[0x3B] = unofficial(Rla, AbsoluteY, 7, false);
+    table[0x23] = unofficial(Rla, IndirectX, 8, false);
+    table[0x33] = unofficial(Rla, IndirectY, 8, false);
+
+    table[0x47] = unofficial(Sre, ZeroPage, 5, false);
+    table[0x57] = unofficial(Sre, ZeroPageX, 6, false);
+    table[0x4F] = unofficial(Sre, Absolute, 6, false);
+    table[0x5F] = unofficial(Sre, AbsoluteX, 7, false);
+    table[0x5B] = unofficial(Sre, AbsoluteY, 7, false);
+    table[0x43] = unofficial(Sre, IndirectX, 8, false);
+    table[0x53] = unofficial(Sre, IndirectY, 8, false);
+
+    table[0x67] = unofficial(Rra, ZeroPage, 5, false);
+    table[0x77] = unofficial(Rra, ZeroPageX, 6, false);
+    table[0x6F] = unofficial(Rra, Absolute, 6, false);
+    table[0x7F] = unofficial(Rra, AbsoluteX, 7, false);
+    table[0x7B] = unofficial(Rra, AbsoluteY, 7, false);
+    table[0x63] = unofficial(Rra, IndirectX, 8, false);
+    table[0x73] = unofficial(Rra, IndirectY, 8, false);
+
+    table[0xC7] = unofficial(Dcp, ZeroPage, 5, false);
+    table[0xD7] = unofficial(Dcp, ZeroPageX, 6, false);
+    table[0xCF] = unofficial(Dcp, Absolute, 6, false);
+    table[0xDF] = unofficial(Dcp, AbsoluteX, 7, false);
+    table[0xDB] = unofficial(Dcp, AbsoluteY, 7, false);
+    table[0xC3] = unofficial(Dcp, IndirectX, 8, false);
+    table[0xD3] = unofficial(Dcp, IndirectY, 8, false);
+
+    table[0xE7] = unofficial(Isc, ZeroPage, 5, false);
+    table[0xF7] = unofficial(Isc, ZeroPageX, 6, false);
+    table[0xEF] = unofficial(Isc, Absolute, 6, false);
+    table[0xFF] = unofficial(Isc, AbsoluteX, 7, false);
+    table[0xFB] = unofficial(Isc, AbsoluteY, 7, false);
+    table[0xE3] = unofficial(Isc, IndirectX, 8, false);
+    table[0xF3] = unofficial(Isc, IndirectY, 8, false);
+
+    table[0x87] = unofficial(Sax, ZeroPage, 3, false);
+    table[0x97] = unofficial(Sax, ZeroPageY, 4, false);
+    table[0x8F] = unofficial(Sax, Absolute, 4, false);
+    table[0x83] = unofficial(Sax, IndirectX, 6, false);
+
+    table[0xA7] = unofficial(Lax, ZeroPage, 3, false);
+    table[0xB7] = unofficial(Lax, ZeroPageY, 4, false);
+    table[0xAF] = unofficial(Lax, Absolute, 4, false);
+    table[0xBF] = unofficial(Lax, AbsoluteY, 4, true);
+    table[0xA3] = unofficial(Lax, IndirectX, 6, false);
+    table[0xB3] = unofficial(Lax, IndirectY, 5, true);
+    table[0xAB] = unofficial(Lax, Immediate, 2, false);
+
+    table[0x0B] = unofficial(Anc, Immediate, 2, false);
+    table[0x2B] = unofficial(Anc, Immediate, 2, false);
+    table[0x4B] = unofficial(Alr, Immediate, 2, false);
+    table[0x6B] = unofficial(Arr, Immediate, 2, false);
+    table[0xCB] = unofficial(Axs, Immediate, 2, false);
+    table[0xEB] = unofficial(Sbc, Immediate, 2, false);
+    table[0x8B] = unofficial(Xaa, Immediate, 2, false);
+
+    table[0x93] = unofficial(Sha, IndirectY, 6, false);
+    table[0x9F] = unofficial(Sha, AbsoluteY, 5, false);
+    table[0x9E] = unofficial(Shx, AbsoluteY, 5, false);
+    table[0x9C] = unofficial(Shy, AbsoluteX, 5, false);
+    table[0x9B] = unofficial(Tas, AbsoluteY, 5, false);
+    table[0xBB] = unofficial(Las, AbsoluteY, 4, true);
+
+    // every opcode must be in the table, this fails to compile otherwise
+    let mut opcodes = [Opcode {
+        mnemonic: Kil,
+        mode: Implied,
+        len: 1,
+        cycles: 2,
+        page_penalty: false,
+        official: false,
+    }; 256];
+    let mut i = 0;
+    while i < 256 {
+        opcodes[i] = match table[i] {
+            Some(opcode) => opcode,
+            None => panic!("missing opcode"),
+        };
+        i += 1;
+    }
+
+    opcodes
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn official_opcode_count() {
+        assert!(OPCODES.iter().filter(|op| op.official).count() == 151);
+    }
+
+    #[test]
+    fn lengths_follow_addressing_mode() {
+        let jmp = decode(0x6C);
+        assert!(jmp.mnemonic == Mnemonic::Jmp);
+        assert!(jmp.len == 3);
+
+        let lda = decode(0xB1);
+        assert!(lda.mode == AddrMode::IndirectY);
+        assert!(lda.len == 2);
+        assert!(lda.page_penalty);
+
+        assert!(decode(0x0A).len == 1);
+    }
+
+    #[test]
+    fn unofficial_opcodes() {
+        assert!(decode(0x02).mnemonic == Mnemonic::Kil);
+        assert!(!decode(0x02).official);
+
+        let nop = decode(0x0C);
+        assert!(nop.mnemonic == Mnemonic::Nop);
+        assert!(nop.len == 3);
+        assert!(!nop.official);
+
+        let dcp = decode(0xD3);
+        assert!(dcp.mnemonic == Mnemonic::Dcp);
+        assert!(dcp.mode == AddrMode::IndirectY);
+        assert!(dcp.cycles == 8);
+
+        assert!(decode(0xEB).mnemonic == Mnemonic::Sbc);
+        assert!(!decode(0xEB).official);
+    }
+
+    #[test]
+    fn mnemonic_display() {
+        assert!(Mnemonic::Adc.to_string() == "ADC");
+        assert!(Mnemonic::Tya.to_string() == "TYA");
+    }
+}
@@ -0,0 +1,647 @@
+mod background;
+mod sprites;
+
+use crate::bus::PpuDevice;
+use crate::mapper::{Mapper, Mirroring};
+use crate::mmap::vram;
+use crate::scheduler::Region;
+
+// PPUCTRL
+const CTRL_NAMETABLE: u8 = 0b0000_0011;
+const CTRL_INCREMENT_32: u8 = 0b0000_0100;
+const CTRL_SPRITE_TABLE: u8 = 0b0000_1000;
+const CTRL_BACKGROUND_TABLE: u8 = 0b0001_0000;
+const CTRL_SPRITE_16: u8 = 0b0010_0000;
+const CTRL_NMI: u8 = 0b1000_0000;
+
+// PPUMASK
+const MASK_GRAYSCALE: u8 = 0b0000_0001;
+const MASK_BACKGROUND_LEFT: u8 = 0b0000_0010;
+const MASK_SPRITES_LEFT: u8 = 0b0000_0100;
+const MASK_BACKGROUND: u8 = 0b0000_1000;
+const MASK_SPRITES: u8 = 0b0001_0000;
+
+// PPUSTATUS
+const STATUS_OVERFLOW: u8 = 0b0010_0000;
+const STATUS_SPRITE_0_HIT: u8 = 0b0100_0000;
+const STATUS_VBLANK: u8 = 0b1000_0000;
+
+/// size of the picture in pixels
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+/// dots per scanline, for both regions
+const DOTS: u16 = 341;
+/// the first scanline of vblank
+const VBLANK_SCANLINE: u16 = 241;
+
+/// The picture processing unit, seen from the CPU through its 8 registers
+///
+/// https://www.nesdev.org/wiki/PPU_registers
+pub struct Ppu {
+    region: Region,
+    /// PPUCTRL
+    ctrl: u8,
+    /// PPUMASK
+    mask: u8,
+    /// the flags in PPUSTATUS's top 3 bits
+    status: u8,
+    /// OAMADDR
+    oam_addr: u8,
+    /// sprite attribute memory, 4 bytes for each of the 64 sprites
+    oam: [u8; 256],
+    /// the VRAM address $2007 accesses (loopy v)
+    v: u16,
+    /// the VRAM address the next frame starts rendering from (loopy t)
+    t: u16,
+    /// fine X scroll
+    x: u8,
+    /// whether the next $2005 or $2006 write is the second one, they share the toggle
+    w: bool,
+    /// $2007 reads below the palette return what the previous read fetched
+    read_buffer: u8,
+    /// the console's 2 KiB of nametable RAM, then the 2 KiB four-screen cartridges add
+    vram: [u8; 0x1000],
+    palette: [u8; 32],
+    background: background::Background,
+    sprites: sprites::Sprites,
+    /// the picture as palette RAM values, one byte per pixel
+    framebuffer: Box<[u8; WIDTH * HEIGHT]>,
+    scanline: u16,
+    dot: u16,
+    frames: u64,
+}
+
+impl Ppu {
+    pub fn new(region: Region) -> Self {
+        Ppu {
+            region,
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            oam: [0; 256],
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            read_buffer: 0,
+            vram: [0; 0x1000],
+            palette: [0; 32],
+            background: background::Background::default(),
+            sprites: sprites::Sprites::default(),
+            framebuffer: Box::new([0; WIDTH * HEIGHT]),
+            scanline: 0,
+            dot: 0,
+            frames: 0,
+        }
+    }
+
+    /// where the PPU is in the frame as (scanline, dot)
+    pub fn position(&self) -> (u16, u16) {
+        (self.scanline, self.dot)
+    }
+
+    /// number of frames finished since power on
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// the scanline before the picture starts, where vblank ends
+    fn pre_render_scanline(&self) -> u16 {
+        match self.region {
+            Region::Ntsc => 261,
+            Region::Pal => 311,
+        }
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.mask & (MASK_BACKGROUND | MASK_SPRITES) != 0
+    }
+
+    /// draws the pixel under the current dot
+    fn output_pixel(&mut self) {
+        let x = self.dot as usize - 1;
+        let index = if self.rendering_enabled() {
+            let background = self.background_pixel();
+            match self.sprite_pixel() {
+                Some(sprite) => {
+                    // the hit needs both pixels opaque and is never reported on the last column
+                    if sprite.sprite_0 && background != 0 && x != WIDTH - 1 {
+                        self.status |= STATUS_SPRITE_0_HIT;
+                    }
+                    if background == 0 || !sprite.behind_background {
+                        sprite.index
+                    } else {
+                        background
+                    }
+                }
+                None => background,
+            }
+        } else if self.v as usize & 0x3fff >= vram::palette::START {
+            // with rendering off the backdrop is replaced by whatever color v points at
+            self.v as u8
+        } else {
+            0
+        };
+
+        let mut color = self.palette[palette_index(index as u16)];
+        if self.mask & MASK_GRAYSCALE != 0 {
+            color &= 0x30;
+        }
+        self.framebuffer[self.scanline as usize * WIDTH + x] = color;
+    }
+
+    /// $2007 moves across a row of the nametable, or down a column
+    fn increment_v(&mut self) {
+        let step = if self.ctrl & CTRL_INCREMENT_32 != 0 {
+            32
+        } else {
+            1
+        };
+        self.v = self.v.wrapping_add(step) & 0x7fff;
+    }
+
+    /// what OAMDATA reads, the unused attribute bits don't exist
+    fn oam_data(&self) -> u8 {
+        // secondary OAM is being cleared, which reads as $FF through here
+        if self.rendering_enabled()
+            && (self.scanline as usize) < HEIGHT
+            && (1..=64).contains(&self.dot)
+        {
+            return 0xff;
+        }
+        let val = self.oam[self.oam_addr as usize];
+        if self.oam_addr & 0b11 == 2 {
+            val & 0b1110_0011
+        } else {
+            val
+        }
+    }
+
+    /// reads the PPU's own address space
+    fn read_vram(&self, addr: u16, mapper: Option<&mut dyn Mapper>) -> u8 {
+        let addr = addr & 0x3fff;
+        match addr as usize {
+            vram::pattern_tables::START..=vram::pattern_tables::END => {
+                mapper.map_or(0, |mapper| mapper.ppu_read(addr))
+            }
+            vram::nametables::START..=vram::nametables::MIRRORS_END => {
+                self.vram[nametable_index(addr, mapper.as_deref())]
+            }
+            _ => self.palette[palette_index(addr)],
+        }
+    }
+
+    fn write_vram(&mut self, addr: u16, val: u8, mapper: Option<&mut dyn Mapper>) {
+        let addr = addr & 0x3fff;
+        match addr as usize {
+            vram::pattern_tables::START..=vram::pattern_tables::END => {
+                if let Some(mapper) = mapper {
+                    mapper.ppu_write(addr, val);
+                }
+            }
+            vram::nametables::START..=vram::nametables::MIRRORS_END => {
+                self.vram[nametable_index(addr, mapper.as_deref())] = val;
+            }
+            // palette entries are only 6 bits wide
+            _ => self.palette[palette_index(addr)] = val & 0x3f,
+        }
+    }
+}
+
+/// lends the mapper out again without giving it up
+fn reborrow<'a>(mapper: &'a mut Option<&mut dyn Mapper>) -> Option<&'a mut dyn Mapper> {
+    match mapper {
+        Some(mapper) => Some(&mut **mapper),
+        None => None,
+    }
+}
+
+/// boards without a cartridge attached are treated as horizontally mirrored
+fn nametable_index(addr: u16, mapper: Option<&dyn Mapper>) -> usize {
+    mapper
+        .map_or(Mirroring::Horizontal, |mapper| mapper.mirroring())
+        .vram_index(addr)
+}
+
+/// the backdrop entries of the sprite palettes are the background's
+fn palette_index(addr: u16) -> usize {
+    let index = addr as usize & 0x1f;
+    if index & 0x13 == 0x10 {
+        index & 0x0f
+    } else {
+        index
+    }
+}
+
+impl PpuDevice for Ppu {
+    fn read(&mut self, addr: u16, mapper: Option<&mut dyn Mapper>) -> u8 {
+        match addr & 0b0111 {
+            // PPUSTATUS
+            2 => {
+                let status = self.status;
+                self.status &= !STATUS_VBLANK;
+                self.w = false;
+                status
+            }
+            // OAMDATA
+            4 => self.oam_data(),
+            // PPUDATA
+            7 => {
+                let addr = self.v & 0x3fff;
+                let val = if addr as usize >= vram::palette::START {
+                    // the palette answers straight away, the buffer gets the nametable under it
+                    self.read_buffer = self.read_vram(addr - 0x1000, mapper);
+                    self.palette[palette_index(addr)]
+                } else {
+                    let val = self.read_vram(addr, mapper);
+                    std::mem::replace(&mut self.read_buffer, val)
+                };
+                self.increment_v();
+                val
+            }
+            // the rest are write only
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8, mapper: Option<&mut dyn Mapper>) {
+        match addr & 0b0111 {
+            // PPUCTRL
+            0 => {
+                self.ctrl = val;
+                self.t = self.t & !0x0c00 | ((val & CTRL_NAMETABLE) as u16) << 10;
+            }
+            // PPUMASK
+            1 => self.mask = val,
+            // OAMADDR
+            3 => self.oam_addr = val,
+            // OAMDATA
+            4 => {
+                self.oam[self.oam_addr as usize] = val;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            // PPUSCROLL, X then Y
+            5 => {
+                if self.w {
+                    self.t =
+                        self.t & !0x73e0 | ((val & 0x07) as u16) << 12 | ((val & 0xf8) as u16) << 2;
+                } else {
+                    self.t = self.t & !0x001f | (val >> 3) as u16;
+                    self.x = val & 0x07;
+                }
+                self.w = !self.w;
+            }
+            // PPUADDR, high byte then low byte
+            6 => {
+                if self.w {
+                    self.t = self.t & 0xff00 | val as u16;
+                    self.v = self.t;
+                } else {
+                    self.t = self.t & 0x00ff | ((val & 0x3f) as u16) << 8;
+                }
+                self.w = !self.w;
+            }
+            // PPUDATA
+            7 => {
+                self.write_vram(self.v, val, mapper);
+                self.increment_v();
+            }
+            // PPUSTATUS is read only
+            _ => (),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr & 0b0111 {
+            2 => self.status,
+            4 => self.oam_data(),
+            7 if self.v as usize & 0x3fff >= vram::palette::START => {
+                self.palette[palette_index(self.v)]
+            }
+            7 => self.read_buffer,
+            _ => 0,
+        }
+    }
+
+    fn open_bus_mask(&self, addr: u16) -> u8 {
+        match addr & 0b0111 {
+            2 => 0b0001_1111,
+            4 => 0,
+            // palette reads only drive 6 bits
+            7 if self.v as usize & 0x3fff >= vram::palette::START => 0b1100_0000,
+            7 => 0,
+            _ => 0xff,
+        }
+    }
+
+    fn tick(&mut self, mapper: Option<&mut dyn Mapper>) {
+        let pre_render = self.pre_render_scanline();
+        self.dot += 1;
+        // NTSC shortens every other pre-render line by a dot while rendering
+        if self.region == Region::Ntsc
+            && self.scanline == pre_render
+            && self.dot == DOTS - 1
+            && self.frames % 2 == 1
+            && self.rendering_enabled()
+        {
+            self.dot = DOTS;
+        }
+        if self.dot == DOTS {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline > pre_render {
+                self.scanline = 0;
+                self.frames += 1;
+            }
+        }
+
+        let visible = (self.scanline as usize) < HEIGHT;
+        if self.rendering_enabled() && (visible || self.scanline == pre_render) {
+            let mut mapper = mapper;
+            self.render_background(reborrow(&mut mapper));
+            self.render_sprites(mapper);
+        }
+        if visible && (1..=WIDTH as u16).contains(&self.dot) {
+            self.output_pixel();
+        }
+
+        if self.dot == 1 {
+            if self.scanline == VBLANK_SCANLINE {
+                self.status |= STATUS_VBLANK;
+            } else if self.scanline == pre_render {
+                self.status &= !(STATUS_VBLANK | STATUS_SPRITE_0_HIT | STATUS_OVERFLOW);
+            }
+        }
+    }
+
+    fn framebuffer(&self) -> Option<&[u8]> {
+        Some(&self.framebuffer[..])
+    }
+
+    fn nmi(&self) -> bool {
+        self.ctrl & CTRL_NMI != 0 && self.status & STATUS_VBLANK != 0
+    }
+
+    fn position(&self) -> Option<(u16, u16)> {
+        Some(Ppu::position(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::bus::Bus;
+    use crate::cartridge::Cartridge;
+    use crate::cpu::CPU;
+    use crate::scheduler::{Scheduler, Throttle};
+    use rune_ines::InesFile;
+
+    /// 8 KiB of pattern table RAM on a board that can switch its mirroring
+    pub(super) struct ChrRam {
+        pub(super) chr: [u8; 0x2000],
+        pub(super) mirroring: Mirroring,
+    }
+
+    impl Mapper for ChrRam {
+        fn cpu_read(&mut self, _addr: u16) -> Option<u8> {
+            None
+        }
+
+        fn cpu_write(&mut self, _addr: u16, _val: u8) {}
+
+        fn cpu_peek(&self, _addr: u16) -> Option<u8> {
+            None
+        }
+
+        fn ppu_read(&mut self, addr: u16) -> u8 {
+            self.chr[addr as usize]
+        }
+
+        fn ppu_write(&mut self, addr: u16, val: u8) {
+            self.chr[addr as usize] = val;
+        }
+
+        fn mirroring(&self) -> Mirroring {
+            self.mirroring
+        }
+    }
+
+    pub(super) fn ppu_bus() -> Bus {
+        let mut bus = Bus::default();
+        bus.attach_ppu(Box::new(Ppu::new(Region::Ntsc)));
+        bus.attach_cartridge(Cartridge::new(Box::new(ChrRam {
+            chr: [0; 0x2000],
+            mirroring: Mirroring::Vertical,
+        })));
+        bus
+    }
+
+    pub(super) fn set_addr(bus: &mut Bus, addr: u16) {
+        bus.write(0x2006, (addr >> 8) as u8);
+        bus.write(0x2006, addr as u8);
+    }
+
+    #[test]
+    fn ppudata_reads_are_buffered() {
+        let mut bus = ppu_bus();
+        set_addr(&mut bus, 0x2400);
+        bus.write(0x2007, 0x11);
+        bus.write(0x2007, 0x22);
+        set_addr(&mut bus, 0x0010);
+        bus.write(0x2007, 0x33);
+
+        set_addr(&mut bus, 0x2400);
+        bus.read(0x2007);
+        assert!(bus.read(0x2007) == 0x11);
+        assert!(bus.read(0x2007) == 0x22);
+
+        // vertical mirroring puts 0x2c00 over 0x2400
+        set_addr(&mut bus, 0x2c01);
+        bus.read(0x2007);
+        assert!(bus.read(0x2007) == 0x22);
+
+        set_addr(&mut bus, 0x0010);
+        bus.read(0x2007);
+        assert!(bus.read(0x2007) == 0x33);
+    }
+
+    #[test]
+    fn mapper_controls_mirroring() {
+        let mut ppu = Ppu::new(Region::Ntsc);
+        let mut chr = ChrRam {
+            chr: [0; 0x2000],
+            mirroring: Mirroring::FourScreen,
+        };
+        for (i, addr) in [0x2000, 0x2400, 0x2800, 0x2c00].into_iter().enumerate() {
+            ppu.write_vram(addr, i as u8 + 1, Some(&mut chr));
+        }
+        let tables = |ppu: &Ppu, chr: &mut ChrRam| {
+            [0x2000, 0x2400, 0x2800, 0x2c00].map(|addr| ppu.read_vram(addr, Some(&mut *chr)))
+        };
+        assert!(tables(&ppu, &mut chr) == [1, 2, 3, 4]);
+
+        // switching takes effect on the next access
+        chr.mirroring = Mirroring::Horizontal;
+        assert!(tables(&ppu, &mut chr) == [1, 1, 2, 2]);
+        chr.mirroring = Mirroring::SingleScreenB;
+        assert!(tables(&ppu, &mut chr) == [2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn increments_by_32() {
+        let mut bus = ppu_bus();
+        bus.write(0x2000, CTRL_INCREMENT_32);
+        set_addr(&mut bus, 0x2000);
+        bus.write(0x2007, 0x11);
+        bus.write(0x2007, 0x22);
+
+        bus.write(0x2000, 0);
+        set_addr(&mut bus, 0x2020);
+        bus.read(0x2007);
+        assert!(bus.read(0x2007) == 0x22);
+    }
+
+    #[test]
+    fn palette() {
+        let mut bus = ppu_bus();
+        set_addr(&mut bus, 0x2f00);
+        bus.write(0x2007, 0x44);
+        set_addr(&mut bus, 0x3f10);
+        bus.write(0x2007, 0xff);
+
+        // the palette isn't buffered, 0x3f10 is 0x3f00 and the top 2 bits come from the latch
+        set_addr(&mut bus, 0x3f00);
+        bus.write(0x2003, 0xc0);
+        assert!(bus.read(0x2007) == 0xff);
+        set_addr(&mut bus, 0x3f00);
+        assert!(bus.read(0x2007) == 0x3f);
+
+        // while the buffer picks up the nametable underneath
+        set_addr(&mut bus, 0x0000);
+        assert!(bus.read(0x2007) == 0x44);
+    }
+
+    #[test]
+    fn oam() {
+        let mut bus = ppu_bus();
+        bus.write(0x2003, 0x01);
+        for val in [0x11, 0xff, 0x33] {
+            bus.write(0x2004, val);
+        }
+        bus.write(0x2003, 0x02);
+        // attribute bits 2 - 4 aren't stored
+        assert!(bus.peek(0x2004) == 0xe3);
+        assert!(bus.read(0x2004) == 0xe3);
+        bus.write(0x2003, 0x03);
+        assert!(bus.read(0x2004) == 0x33);
+    }
+
+    #[test]
+    fn scroll_registers() {
+        // https://www.nesdev.org/wiki/PPU_scrolling#Summary
+        let mut ppu = Ppu::new(Region::Ntsc);
+        ppu.write(0x2000, 0b0000_0011, None);
+        assert!(ppu.t == 0x0c00);
+        ppu.read(0x2002, None);
+        ppu.write(0x2005, 0b0111_1101, None);
+        assert!(ppu.t == 0x0c0f && ppu.x == 0b101 && ppu.w);
+        ppu.write(0x2005, 0b0101_1110, None);
+        assert!(ppu.t == 0x6d6f && !ppu.w);
+        ppu.write(0x2006, 0b0011_1101, None);
+        assert!(ppu.t == 0x3d6f);
+        ppu.write(0x2006, 0b1111_0000, None);
+        assert!(ppu.t == 0x3df0 && ppu.v == 0x3df0);
+    }
+
+    #[test]
+    fn vblank() {
+        let mut ppu = Ppu::new(Region::Ntsc);
+        while ppu.position() != (VBLANK_SCANLINE, 1) {
+            ppu.tick(None);
+        }
+        assert!(ppu.peek(0x2002) & STATUS_VBLANK != 0);
+        assert!(!ppu.nmi());
+        ppu.write(0x2000, CTRL_NMI, None);
+        assert!(ppu.nmi());
+
+        // reading the flag clears it along with the write toggle
+        ppu.write(0x2006, 0x3f, None);
+        assert!(ppu.read(0x2002, None) & STATUS_VBLANK != 0);
+        assert!(ppu.read(0x2002, None) & STATUS_VBLANK == 0);
+        assert!(!ppu.w && !ppu.nmi());
+
+        while ppu.position() != (0, 0) {
+            ppu.tick(None);
+        }
+        assert!(ppu.frames() == 1);
+    }
+
+    #[test]
+    fn nmi_reaches_the_cpu() {
+        let rom = asm::assemble_ines(
+            "
+                    .org $8000
+            reset:  lda #$80
+                    sta $2000
+            spin:   jmp spin
+            nmi:    inc $10
+                    rti
+                    .org $fffa
+                    .word nmi, reset, reset
+            ",
+        )
+        .unwrap();
+        let mut cpu = CPU::default();
+        cpu.load_rom(InesFile::from_bytes(&rom)).unwrap();
+        cpu.bus.attach_ppu(Box::new(Ppu::new(Region::Ntsc)));
+        cpu.power_on();
+
+        let mut scheduler = Scheduler::new(Region::Ntsc, Throttle::Unthrottled);
+        for _ in 0..3 {
+            scheduler.run_frame(&mut cpu);
+        }
+        assert!(cpu.bus.peek(0x0010) == 3);
+    }
+
+    #[test]
+    fn trace_follows_skipped_dots() {
+        let rom = asm::assemble_ines(
+            "
+                    .org $8000
+            reset:  lda #$08
+                    sta $2001
+            spin:   jmp spin
+                    .org $fffa
+                    .word reset, reset, reset
+            ",
+        )
+        .unwrap();
+        let mut cpu = CPU::default();
+        cpu.load_rom(InesFile::from_bytes(&rom)).unwrap();
+        cpu.bus.attach_ppu(Box::new(Ppu::new(Region::Ntsc)));
+        cpu.power_on();
+
+        let mut scheduler = Scheduler::new(Region::Ntsc, Throttle::Unthrottled);
+        for _ in 0..3 {
+            scheduler.run_frame(&mut cpu);
+        }
+
+        // a PPU rendering from power on skips the same dots
+        let mut ppu = Ppu::new(Region::Ntsc);
+        ppu.mask = MASK_BACKGROUND;
+        for _ in 0..cpu.bus.cycles() * 3 {
+            PpuDevice::tick(&mut ppu, None);
+        }
+        let (scanline, dot) = ppu.position();
+        let dots = cpu.bus.cycles() * 3;
+        assert!((scanline, dot) != ((dots / 341 % 262) as u16, (dots % 341) as u16));
+
+        assert!(cpu.bus.ppu_position() == (scanline, dot));
+        assert!(cpu
+            .trace_line()
+            .contains(&format!("PPU:{scanline:>3},{dot:>3}")));
+    }
+}
@@ -0,0 +1,300 @@
+use super::{Ppu, CTRL_BACKGROUND_TABLE, MASK_BACKGROUND, MASK_BACKGROUND_LEFT};
+use crate::mapper::Mapper;
+
+/// The background half of the rendering pipeline, a tile is fetched every 8 dots
+/// while the shift registers feed the one before it out a pixel at a time
+///
+/// https://www.nesdev.org/wiki/PPU_rendering
+#[derive(Default)]
+pub(super) struct Background {
+    /// the tile number, palette and pattern bytes of the next tile
+    tile: u8,
+    palette: u8,
+    pattern_lo: u8,
+    pattern_hi: u8,
+    /// the top 8 bits are the tile being drawn, the bottom ones the next tile
+    pattern_lo_shifter: u16,
+    pattern_hi_shifter: u16,
+    /// the palette bits spread over the 8 pixels of each tile
+    palette_lo_shifter: u16,
+    palette_hi_shifter: u16,
+}
+
+impl Background {
+    fn shift(&mut self) {
+        self.pattern_lo_shifter <<= 1;
+        self.pattern_hi_shifter <<= 1;
+        self.palette_lo_shifter <<= 1;
+        self.palette_hi_shifter <<= 1;
+    }
+
+    /// moves the fetched tile into the low half of the shifters
+    fn reload(&mut self) {
+        let spread = |bit: u8| if self.palette & bit != 0 { 0xff } else { 0x00 };
+        self.pattern_lo_shifter = self.pattern_lo_shifter & 0xff00 | self.pattern_lo as u16;
+        self.pattern_hi_shifter = self.pattern_hi_shifter & 0xff00 | self.pattern_hi as u16;
+        self.palette_lo_shifter = self.palette_lo_shifter & 0xff00 | spread(0b01);
+        self.palette_hi_shifter = self.palette_hi_shifter & 0xff00 | spread(0b10);
+    }
+}
+
+impl Ppu {
+    /// the background's work for the current dot of a visible or pre-render scanline
+    pub(super) fn render_background(&mut self, mapper: Option<&mut dyn Mapper>) {
+        let dot = self.dot;
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            self.background.shift();
+            if dot % 8 == 1 {
+                self.background.reload();
+            }
+        }
+
+        if (1..=256).contains(&dot) || (321..=336).contains(&dot) {
+            match dot % 8 {
+                1 => self.background.tile = self.read_vram(0x2000 | self.v & 0x0fff, mapper),
+                3 => {
+                    let v = self.v;
+                    let addr = 0x23c0 | v & 0x0c00 | (v >> 4) & 0x38 | (v >> 2) & 0x07;
+                    // each attribute byte covers 4x4 tiles, 2 bits per 2x2 quadrant
+                    let shift = (v >> 4) & 0b100 | v & 0b10;
+                    self.background.palette = (self.read_vram(addr, mapper) >> shift) & 0b11;
+                }
+                5 => self.background.pattern_lo = self.read_vram(self.pattern_addr(), mapper),
+                7 => self.background.pattern_hi = self.read_vram(self.pattern_addr() + 8, mapper),
+                0 => {
+                    self.increment_coarse_x();
+                    if dot == 256 {
+                        self.increment_y();
+                    }
+                }
+                _ => (),
+            }
+        } else if dot == 257 {
+            self.copy_horizontal();
+        } else if dot == 337 || dot == 339 {
+            // two unused nametable fetches end the line, some mappers count them
+            self.read_vram(0x2000 | self.v & 0x0fff, mapper);
+        }
+
+        if self.scanline == self.pre_render_scanline() && (280..=304).contains(&dot) {
+            self.copy_vertical();
+        }
+    }
+
+    /// the background pixel under the current dot as an index into the palette RAM,
+    /// 0 when it's transparent
+    pub(super) fn background_pixel(&self) -> u8 {
+        let x = self.dot - 1;
+        if self.mask & MASK_BACKGROUND == 0 || (x < 8 && self.mask & MASK_BACKGROUND_LEFT == 0) {
+            return 0;
+        }
+
+        let bit = 0x8000 >> self.x;
+        let background = &self.background;
+        let pixel = ((background.pattern_hi_shifter & bit != 0) as u8) << 1
+            | (background.pattern_lo_shifter & bit != 0) as u8;
+        let palette = ((background.palette_hi_shifter & bit != 0) as u8) << 1
+            | (background.palette_lo_shifter & bit != 0) as u8;
+        if pixel == 0 {
+            0
+        } else {
+            palette << 2 | pixel
+        }
+    }
+
+    /// the row of the current tile's pattern in the background pattern table
+    fn pattern_addr(&self) -> u16 {
+        let table = if self.ctrl & CTRL_BACKGROUND_TABLE != 0 {
+            0x1000
+        } else {
+            0
+        };
+        let fine_y = (self.v >> 12) & 0b111;
+        table | (self.background.tile as u16) << 4 | fine_y
+    }
+
+    /// moves `v` one tile right, wrapping into the horizontally adjacent nametable
+    pub(super) fn increment_coarse_x(&mut self) {
+        if self.v & 0x001f == 31 {
+            self.v &= !0x001f;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    /// moves `v` one pixel down, wrapping into the vertically adjacent nametable
+    /// after row 29 since rows 30 and 31 are the attribute table
+    pub(super) fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+
+        self.v &= !0x7000;
+        let coarse_y = match (self.v & 0x03e0) >> 5 {
+            29 => {
+                self.v ^= 0x0800;
+                0
+            }
+            // scrolled into the attribute table, wraps without switching nametables
+            31 => 0,
+            coarse_y => coarse_y + 1,
+        };
+        self.v = self.v & !0x03e0 | coarse_y << 5;
+    }
+
+    /// coarse X and the horizontal nametable bit come back from `t` at the end of each line
+    fn copy_horizontal(&mut self) {
+        self.v = self.v & !0x041f | self.t & 0x041f;
+    }
+
+    /// fine Y, coarse Y and the vertical nametable bit come back from `t` before each frame
+    fn copy_vertical(&mut self) {
+        self.v = self.v & !0x7be0 | self.t & 0x7be0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{ppu_bus, set_addr};
+    use super::super::{HEIGHT, WIDTH};
+    use super::*;
+    use crate::bus::{Bus, PpuDevice};
+    use crate::scheduler::Region;
+
+    #[test]
+    fn coarse_x_wraps_into_the_next_nametable() {
+        let mut ppu = Ppu::new(Region::Ntsc);
+        ppu.v = 0x001e;
+        ppu.increment_coarse_x();
+        assert!(ppu.v == 0x001f);
+        ppu.increment_coarse_x();
+        assert!(ppu.v == 0x0400);
+        ppu.v = 0x041f;
+        ppu.increment_coarse_x();
+        assert!(ppu.v == 0x0000);
+    }
+
+    #[test]
+    fn y_wraps_into_the_next_nametable() {
+        let mut ppu = Ppu::new(Region::Ntsc);
+        ppu.v = 0x6000;
+        ppu.increment_y();
+        assert!(ppu.v == 0x7000);
+        ppu.increment_y();
+        assert!(ppu.v == 0x0020);
+
+        // row 29 is the last one
+        ppu.v = 0x7000 | 29 << 5;
+        ppu.increment_y();
+        assert!(ppu.v == 0x0800);
+
+        // rows 30 and 31 show the attribute table and wrap in place
+        ppu.v = 0x7000 | 31 << 5 | 0x0800;
+        ppu.increment_y();
+        assert!(ppu.v == 0x0800);
+    }
+
+    /// tile 1 is a solid 8x8 block of color 1, placed at the top left of the first nametable
+    /// and at (2, 1) of the second one
+    fn tiled_bus() -> Bus {
+        let mut bus = ppu_bus();
+        set_addr(&mut bus, 0x0010);
+        for _ in 0..8 {
+            bus.write(0x2007, 0xff);
+        }
+        set_addr(&mut bus, 0x2000);
+        bus.write(0x2007, 0x01);
+        set_addr(&mut bus, 0x2422);
+        bus.write(0x2007, 0x01);
+        // the top right quadrant of the first attribute byte, so (2, 1) uses the second palette
+        set_addr(&mut bus, 0x27c0);
+        bus.write(0x2007, 0b0100);
+
+        set_addr(&mut bus, 0x3f00);
+        for color in [0x0f, 0x16, 0x00, 0x00, 0x00, 0x2a] {
+            bus.write(0x2007, color);
+        }
+        bus
+    }
+
+    /// runs the PPU for a frame after the one being drawn, so scrolling has taken effect
+    fn render(bus: &mut Bus, ctrl: u8, mask: u8, scroll: (u8, u8)) -> Vec<u8> {
+        bus.write(0x2000, ctrl);
+        bus.write(0x2001, mask);
+        bus.read(0x2002);
+        bus.write(0x2005, scroll.0);
+        bus.write(0x2005, scroll.1);
+        for _ in 0..2 * 89342 / 3 {
+            bus.tick();
+        }
+        bus.framebuffer().unwrap().to_vec()
+    }
+
+    fn row(frame: &[u8], y: usize, xs: std::ops::Range<usize>) -> Vec<u8> {
+        xs.map(|x| frame[y * WIDTH + x]).collect()
+    }
+
+    #[test]
+    fn draws_the_background() {
+        let mut bus = tiled_bus();
+        let frame = render(&mut bus, 0, MASK_BACKGROUND | MASK_BACKGROUND_LEFT, (0, 0));
+        assert!(frame.len() == WIDTH * HEIGHT);
+        for y in 0..8 {
+            assert!(row(&frame, y, 0..9) == [0x16, 0x16, 0x16, 0x16, 0x16, 0x16, 0x16, 0x16, 0x0f]);
+        }
+        assert!(row(&frame, 8, 0..8) == [0x0f; 8]);
+        assert!(frame[WIDTH * HEIGHT - 1] == 0x0f);
+
+        // hiding the leftmost 8 pixels shows the backdrop there
+        let frame = render(&mut bus, 0, MASK_BACKGROUND, (0, 0));
+        assert!(row(&frame, 0, 0..8) == [0x0f; 8]);
+    }
+
+    #[test]
+    fn scrolls() {
+        let mut bus = tiled_bus();
+        let frame = render(&mut bus, 0, MASK_BACKGROUND | MASK_BACKGROUND_LEFT, (3, 2));
+        assert!(row(&frame, 0, 0..6) == [0x16, 0x16, 0x16, 0x16, 0x16, 0x0f]);
+        assert!(row(&frame, 5, 0..6) == [0x16, 0x16, 0x16, 0x16, 0x16, 0x0f]);
+        assert!(row(&frame, 6, 0..6) == [0x0f; 6]);
+
+        // starting in the second nametable, tile (2, 1) with its own palette
+        let frame = render(
+            &mut bus,
+            0b01,
+            MASK_BACKGROUND | MASK_BACKGROUND_LEFT,
+            (4, 0),
+        );
+        assert!(
+            row(&frame, 8, 11..21) == [0x0f, 0x2a, 0x2a, 0x2a, 0x2a, 0x2a, 0x2a, 0x2a, 0x2a, 0x0f]
+        );
+    }
+
+    #[test]
+    fn odd_frames_skip_a_dot_while_rendering() {
+        let mut ppu = Ppu::new(Region::Ntsc);
+        let frame_lengths = |ppu: &mut Ppu| {
+            let mut lengths = vec![];
+            for _ in 0..4 {
+                let frames = ppu.frames();
+                let mut dots = 0;
+                while ppu.frames() == frames {
+                    ppu.tick(None);
+                    dots += 1;
+                }
+                lengths.push(dots);
+            }
+            lengths
+        };
+
+        // the first frame is cut short by starting at (0, 0)
+        frame_lengths(&mut ppu);
+        assert!(frame_lengths(&mut ppu) == [89342; 4]);
+        ppu.mask = MASK_BACKGROUND;
+        let lengths = frame_lengths(&mut ppu);
+        assert!(lengths.contains(&89341) && lengths.contains(&89342));
+        assert!(lengths.iter().sum::<u64>() == 2 * (89341 + 89342));
+    }
+}
@@ -0,0 +1,374 @@
+use super::{
+    Ppu, CTRL_SPRITE_16, CTRL_SPRITE_TABLE, MASK_SPRITES, MASK_SPRITES_LEFT, STATUS_OVERFLOW,
+};
+use crate::mapper::Mapper;
+
+/// sprites a scanline can hold
+const LIMIT: usize = 8;
+
+/// A sprite loaded for the scanline being drawn
+#[derive(Debug, Clone, Copy, Default)]
+struct Slot {
+    pattern_lo: u8,
+    pattern_hi: u8,
+    attributes: u8,
+    x: u8,
+}
+
+/// The sprite half of the rendering pipeline: while a scanline is drawn OAM is searched
+/// for the sprites on the next one, which get their patterns fetched during hblank
+///
+/// https://www.nesdev.org/wiki/PPU_sprite_evaluation
+#[derive(Default)]
+pub(super) struct Sprites {
+    /// the sprites evaluation found, 4 OAM bytes each
+    secondary_oam: [u8; 4 * LIMIT],
+    found: usize,
+    /// whether sprite 0 is the first one found
+    found_sprite_0: bool,
+    /// the sprites being drawn on this scanline
+    slots: [Slot; LIMIT],
+    count: usize,
+    sprite_0: bool,
+}
+
+/// A sprite's pixel, only for opaque ones
+pub(super) struct SpritePixel {
+    /// index into the palette RAM
+    pub index: u8,
+    pub behind_background: bool,
+    pub sprite_0: bool,
+}
+
+impl Ppu {
+    /// the sprites' work for the current dot of a visible or pre-render scanline
+    pub(super) fn render_sprites(&mut self, mapper: Option<&mut dyn Mapper>) {
+        let dot = self.dot;
+        if dot == 256 {
+            if self.scanline == self.pre_render_scanline() {
+                // nothing is evaluated, so nothing shows up on the first line
+                self.sprites.secondary_oam = [0xff; 4 * LIMIT];
+                self.sprites.found = 0;
+                self.sprites.found_sprite_0 = false;
+            } else {
+                self.evaluate_sprites();
+            }
+        }
+
+        if (257..=320).contains(&dot) {
+            self.oam_addr = 0;
+            if dot == 257 {
+                self.sprites.count = self.sprites.found;
+                self.sprites.sprite_0 = self.sprites.found_sprite_0;
+            }
+            let slot = (dot - 257) as usize / 8;
+            match dot % 8 {
+                5 => {
+                    let val = self.read_vram(self.sprite_pattern_addr(slot), mapper);
+                    self.load_slot(slot, val, false);
+                }
+                7 => {
+                    let val = self.read_vram(self.sprite_pattern_addr(slot) + 8, mapper);
+                    self.load_slot(slot, val, true);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn sprite_height(&self) -> u16 {
+        if self.ctrl & CTRL_SPRITE_16 != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// how far into a sprite starting at `y` the current scanline is, if it's on it at all
+    fn sprite_row(&self, y: u8) -> Option<u16> {
+        let row = self.scanline.checked_sub(y as u16)?;
+        (row < self.sprite_height()).then_some(row)
+    }
+
+    /// copies the first 8 sprites on the current scanline into secondary OAM
+    ///
+    /// once it's full the PPU keeps looking to set the overflow flag, but it wrongly steps
+    /// through the bytes within each sprite as it moves to the next one. it ends up taking
+    /// tile numbers, attributes and X positions for Y coordinates, so the flag misfires.
+    fn evaluate_sprites(&mut self) {
+        self.sprites.secondary_oam = [0xff; 4 * LIMIT];
+        self.sprites.found = 0;
+        self.sprites.found_sprite_0 = false;
+
+        let mut n = 0;
+        while n < 64 && self.sprites.found < LIMIT {
+            let sprite = &self.oam[n * 4..n * 4 + 4];
+            if self.sprite_row(sprite[0]).is_some() {
+                let found = self.sprites.found;
+                self.sprites.secondary_oam[found * 4..found * 4 + 4].copy_from_slice(sprite);
+                self.sprites.found += 1;
+                self.sprites.found_sprite_0 |= n == 0;
+            }
+            n += 1;
+        }
+
+        let mut m = 0;
+        while n < 64 {
+            if self.sprite_row(self.oam[n * 4 + m]).is_some() {
+                self.status |= STATUS_OVERFLOW;
+                break;
+            }
+            n += 1;
+            m = (m + 1) % 4;
+        }
+    }
+
+    /// the pattern row slot `slot` shows on the next scanline,
+    /// empty slots fetch tile $FF's to keep the mapper seeing the same accesses
+    fn sprite_pattern_addr(&self, slot: usize) -> u16 {
+        let sprite = &self.sprites.secondary_oam[slot * 4..slot * 4 + 4];
+        let (y, tile, attributes) = (sprite[0], sprite[1] as u16, sprite[2]);
+        let height = self.sprite_height();
+        let mut row = self.sprite_row(y).unwrap_or(0);
+        if attributes & 0b1000_0000 != 0 {
+            row = height - 1 - row;
+        }
+
+        if height == 16 {
+            // the tile's lowest bit picks the table, the bottom half is the next tile
+            (tile & 1) << 12 | (tile & 0xfe | row >> 3) << 4 | row & 0b111
+        } else {
+            let table = if self.ctrl & CTRL_SPRITE_TABLE != 0 {
+                0x1000
+            } else {
+                0
+            };
+            table | tile << 4 | row
+        }
+    }
+
+    /// latches a fetched pattern byte, flipping it for sprites mirrored horizontally
+    fn load_slot(&mut self, slot: usize, mut pattern: u8, high: bool) {
+        let sprite = &self.sprites.secondary_oam[slot * 4..slot * 4 + 4];
+        let (attributes, x) = (sprite[2], sprite[3]);
+        if slot >= self.sprites.count {
+            pattern = 0;
+        } else if attributes & 0b0100_0000 != 0 {
+            pattern = pattern.reverse_bits();
+        }
+
+        let slot = &mut self.sprites.slots[slot];
+        slot.attributes = attributes;
+        slot.x = x;
+        if high {
+            slot.pattern_hi = pattern;
+        } else {
+            slot.pattern_lo = pattern;
+        }
+    }
+
+    /// the frontmost opaque sprite pixel under the current dot
+    pub(super) fn sprite_pixel(&self) -> Option<SpritePixel> {
+        let x = self.dot - 1;
+        if self.mask & MASK_SPRITES == 0 || (x < 8 && self.mask & MASK_SPRITES_LEFT == 0) {
+            return None;
+        }
+
+        let sprites = &self.sprites;
+        sprites.slots[..sprites.count]
+            .iter()
+            .enumerate()
+            .find_map(|(i, slot)| {
+                let column = x.checked_sub(slot.x as u16).filter(|&column| column < 8)?;
+                let bit = 7 - column;
+                let pixel = (slot.pattern_hi >> bit & 1) << 1 | slot.pattern_lo >> bit & 1;
+                (pixel != 0).then_some(SpritePixel {
+                    index: 0x10 | (slot.attributes & 0b11) << 2 | pixel,
+                    behind_background: slot.attributes & 0b0010_0000 != 0,
+                    sprite_0: i == 0 && sprites.sprite_0,
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::ChrRam;
+    use super::super::{MASK_BACKGROUND, MASK_BACKGROUND_LEFT, STATUS_SPRITE_0_HIT, WIDTH};
+    use super::*;
+    use crate::bus::PpuDevice;
+    use crate::mapper::Mirroring;
+    use crate::scheduler::Region;
+
+    const ALL: u8 = MASK_BACKGROUND | MASK_BACKGROUND_LEFT | MASK_SPRITES | MASK_SPRITES_LEFT;
+
+    /// tile 1 is solid color 1, tile 2 a column of color 3 down its left edge
+    /// and tile 3 a row of color 1 along its top, the rest of OAM is off screen
+    fn setup(sprites: &[[u8; 4]], background: bool) -> (Ppu, ChrRam) {
+        let mut chr = ChrRam {
+            chr: [0; 0x2000],
+            mirroring: Mirroring::Vertical,
+        };
+        chr.chr[0x10..0x18].fill(0xff);
+        chr.chr[0x20..0x30].fill(0x80);
+        chr.chr[0x30] = 0xff;
+
+        let mut ppu = Ppu::new(Region::Ntsc);
+        ppu.oam.fill(0xf0);
+        for (i, sprite) in sprites.iter().enumerate() {
+            ppu.oam[i * 4..i * 4 + 4].copy_from_slice(sprite);
+        }
+        if background {
+            // the top left tile is opaque, the rest of the nametable is tile 0
+            ppu.vram[0] = 1;
+        }
+        ppu.palette[0x00] = 0x0f;
+        ppu.palette[0x01] = 0x16;
+        ppu.palette[0x11] = 0x21;
+        ppu.palette[0x13] = 0x23;
+        ppu.palette[0x15] = 0x25;
+        (ppu, chr)
+    }
+
+    /// draws two frames and returns the second
+    fn render(ppu: &mut Ppu, chr: &mut ChrRam, ctrl: u8, mask: u8) -> Vec<u8> {
+        ppu.ctrl = ctrl;
+        ppu.mask = mask;
+        let frames = ppu.frames();
+        while ppu.frames() < frames + 2 {
+            ppu.tick(Some(chr));
+        }
+        ppu.framebuffer.to_vec()
+    }
+
+    fn row(frame: &[u8], y: usize, xs: std::ops::Range<usize>) -> Vec<u8> {
+        xs.map(|x| frame[y * WIDTH + x]).collect()
+    }
+
+    #[test]
+    fn draws_sprites_a_line_below_their_y() {
+        let (mut ppu, mut chr) = setup(&[[20, 1, 0b00, 10], [40, 1, 0b01, 4]], false);
+        let frame = render(&mut ppu, &mut chr, 0, ALL);
+        assert!(row(&frame, 20, 10..18) == [0x0f; 8]);
+        for y in 21..29 {
+            assert!(
+                row(&frame, y, 9..19)
+                    == [0x0f, 0x21, 0x21, 0x21, 0x21, 0x21, 0x21, 0x21, 0x21, 0x0f]
+            );
+        }
+        assert!(row(&frame, 29, 10..18) == [0x0f; 8]);
+        assert!(row(&frame, 41, 4..12) == [0x25; 8]);
+
+        // hiding the leftmost 8 pixels cuts into the second sprite
+        let frame = render(&mut ppu, &mut chr, 0, MASK_SPRITES);
+        assert!(row(&frame, 41, 4..12) == [0x0f, 0x0f, 0x0f, 0x0f, 0x25, 0x25, 0x25, 0x25]);
+    }
+
+    #[test]
+    fn flips() {
+        let sprites = [[20, 2, 0b0100_0000, 10], [40, 3, 0b1000_0000, 10]];
+        let (mut ppu, mut chr) = setup(&sprites, false);
+        let frame = render(&mut ppu, &mut chr, 0, ALL);
+        assert!(row(&frame, 21, 10..18) == [0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x23]);
+        assert!(row(&frame, 41, 10..18) == [0x0f; 8]);
+        assert!(row(&frame, 48, 10..18) == [0x21; 8]);
+    }
+
+    #[test]
+    fn tall_sprites() {
+        // tile 2 picks the first pattern table, its bottom half is tile 3
+        let (mut ppu, mut chr) = setup(&[[20, 2, 0, 10], [40, 2, 0b1000_0000, 10]], false);
+        let frame = render(&mut ppu, &mut chr, CTRL_SPRITE_16, ALL);
+        assert!(row(&frame, 21, 10..12) == [0x23, 0x0f]);
+        assert!(row(&frame, 29, 10..12) == [0x21, 0x21]);
+        assert!(row(&frame, 30, 10..12) == [0x0f, 0x0f]);
+
+        // flipping swaps the halves too
+        assert!(row(&frame, 41, 10..12) == [0x0f, 0x0f]);
+        assert!(row(&frame, 48, 10..12) == [0x21, 0x21]);
+        assert!(row(&frame, 56, 10..12) == [0x23, 0x0f]);
+    }
+
+    #[test]
+    fn priority() {
+        // the first sprite is behind the background, which still hides the second one
+        let sprites = [[0, 1, 0b0010_0000, 4], [0, 1, 0b01, 0]];
+        let (mut ppu, mut chr) = setup(&sprites, true);
+        let frame = render(&mut ppu, &mut chr, 0, ALL);
+        assert!(
+            row(&frame, 1, 0..14)
+                == [
+                    0x25, 0x25, 0x25, 0x25, 0x16, 0x16, 0x16, 0x16, 0x21, 0x21, 0x21, 0x21, 0x0f,
+                    0x0f
+                ]
+        );
+    }
+
+    /// every PPUSTATUS flag that was up at some point of a frame
+    fn flags(sprites: &[[u8; 4]], background: bool, mask: u8) -> u8 {
+        let (mut ppu, mut chr) = setup(sprites, background);
+        ppu.mask = mask;
+        let mut flags = 0;
+        while ppu.frames() < 1 {
+            ppu.tick(Some(&mut chr));
+            flags |= ppu.status;
+        }
+        flags
+    }
+
+    #[test]
+    fn eight_sprites_per_line() {
+        let sprites: Vec<[u8; 4]> = (0..9).map(|i| [20, 1, 0, i * 10]).collect();
+        let (mut ppu, mut chr) = setup(&sprites, false);
+        let frame = render(&mut ppu, &mut chr, 0, ALL);
+        assert!(frame[21 * WIDTH + 70] == 0x21);
+        assert!(frame[21 * WIDTH + 80] == 0x0f);
+        assert!(flags(&sprites, false, ALL) & STATUS_OVERFLOW != 0);
+        assert!(flags(&sprites[..8], false, ALL) & STATUS_OVERFLOW == 0);
+    }
+
+    #[test]
+    fn overflow_bug() {
+        // 8 sprites on line 21, then a sprite whose tile number reads as a Y coordinate
+        let mut sprites: Vec<[u8; 4]> = (0..8).map(|i| [20, 1, 0, i * 10]).collect();
+        sprites.push([0xf0, 0xf0, 0xf0, 0xf0]);
+        sprites.push([0xf0, 20, 0xf0, 0xf0]);
+        assert!(flags(&sprites, false, ALL) & STATUS_OVERFLOW != 0);
+
+        // and a ninth sprite on the line is missed since its X position is checked instead
+        sprites[9] = [0xf0, 0xf0, 0xf0, 0xf0];
+        sprites.push([20, 0xf0, 0xf0, 0xf0]);
+        assert!(flags(&sprites, false, ALL) & STATUS_OVERFLOW == 0);
+    }
+
+    /// where sprite 0 hit first shows up
+    fn sprite_0_hit(sprite: [u8; 4], mask: u8) -> Option<(u16, u16)> {
+        let (mut ppu, mut chr) = setup(&[sprite], false);
+        // the whole background is opaque
+        ppu.vram[..0x3c0].fill(1);
+        ppu.mask = mask;
+        while ppu.frames() < 1 {
+            ppu.tick(Some(&mut chr));
+            if ppu.status & STATUS_SPRITE_0_HIT != 0 {
+                return Some(ppu.position());
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn sprite_0_hit_timing() {
+        assert!(sprite_0_hit([30, 1, 0, 50], ALL) == Some((31, 51)));
+        // nothing is drawn on line 0, so a sprite above it can't hit there
+        assert!(sprite_0_hit([0, 1, 0, 50], ALL) == Some((1, 51)));
+        assert!(sprite_0_hit([30, 1, 0, 254], ALL) == Some((31, 255)));
+        // never on the last column
+        assert!(sprite_0_hit([30, 1, 0, 255], ALL).is_none());
+        // nor where either layer is clipped
+        assert!(sprite_0_hit([30, 1, 0, 0], ALL) == Some((31, 1)));
+        assert!(sprite_0_hit([30, 1, 0, 4], MASK_BACKGROUND | MASK_SPRITES) == Some((31, 9)));
+        assert!(sprite_0_hit([30, 1, 0, 4], ALL & !MASK_SPRITES_LEFT) == Some((31, 9)));
+        // or where the sprite is transparent
+        assert!(sprite_0_hit([30, 2, 0b0100_0000, 0], ALL) == Some((31, 8)));
+    }
+}
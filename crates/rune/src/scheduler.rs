@@ -0,0 +1,269 @@
+use crate::cpu::CPU;
+use rune_ines::{InesFile, TVSystem};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The console's video standard, it decides every clock rate in the system
+///
+/// https://www.nesdev.org/wiki/Cycle_reference_chart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+}
+
+impl Region {
+    /// dual region games run on NTSC
+    pub fn from_header(rom: &InesFile) -> Region {
+        match rom.header.get_tv_system() {
+            TVSystem::PAL => Region::Pal,
+            TVSystem::NTSC | TVSystem::DUAL => Region::Ntsc,
+        }
+    }
+
+    /// frequency of the crystal every other clock is divided from
+    pub const fn master_clock_hz(self) -> f64 {
+        match self {
+            Region::Ntsc => 236_250_000.0 / 11.0,
+            Region::Pal => 26_601_712.0,
+        }
+    }
+
+    /// master clocks per CPU (and APU) cycle
+    pub const fn cpu_divider(self) -> u64 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+        }
+    }
+
+    /// master clocks per PPU dot
+    pub const fn ppu_divider(self) -> u64 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal => 5,
+        }
+    }
+
+    /// master clocks per frame
+    ///
+    /// NTSC drops a dot every other frame so its frames average 89341.5 dots
+    pub const fn master_clocks_per_frame(self) -> u64 {
+        match self {
+            Region::Ntsc => 357_366,
+            Region::Pal => 531_960,
+        }
+    }
+
+    pub fn frame_duration(self) -> Duration {
+        Duration::from_secs_f64(self.master_clocks_per_frame() as f64 / self.master_clock_hz())
+    }
+}
+
+/// Blocks until the audio device needs more samples, so that sound drives the frame rate
+pub trait AudioSync {
+    fn wait_for_frame(&mut self);
+}
+
+/// What the emulator waits on after each frame
+pub enum Throttle {
+    /// runs as fast as possible, for tests and fast-forward
+    Unthrottled,
+    /// sleeps until the frame would be over on real hardware
+    WallClock,
+    Audio(Box<dyn AudioSync>),
+}
+
+/// Runs the system one frame at a time from the master clock
+///
+/// a frame is emulated as fast as possible and throttling only happens once it's done,
+/// which is enough for the result to look and sound real time
+pub struct Scheduler {
+    region: Region,
+    throttle: Throttle,
+    /// master clock the current frame ends on
+    frame_end: u64,
+    /// when the current frame should be over in wall-clock throttling
+    deadline: Option<Instant>,
+    frames: u64,
+}
+
+impl Scheduler {
+    pub fn new(region: Region, throttle: Throttle) -> Self {
+        Scheduler {
+            region,
+            throttle,
+            frame_end: 0,
+            deadline: None,
+            frames: 0,
+        }
+    }
+
+    pub fn set_throttle(&mut self, throttle: Throttle) {
+        self.throttle = throttle;
+        self.deadline = None;
+    }
+
+    /// number of frames emulated so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// emulates one frame then waits on the throttle
+    pub fn run_frame(&mut self, cpu: &mut CPU) {
+        cpu.bus.set_region(self.region);
+        self.frame_end += self.region.master_clocks_per_frame();
+        if self.frame_end <= cpu.bus.master_clock() {
+            // the bus ran without us (e.g. a fresh CPU), restart frames from where it is
+            self.frame_end = cpu.bus.master_clock() + self.region.master_clocks_per_frame();
+        }
+
+        while cpu.bus.master_clock() < self.frame_end {
+            step(cpu);
+        }
+
+        self.frames += 1;
+        self.throttle();
+    }
+
+    fn throttle(&mut self) {
+        match self.throttle {
+            Throttle::Unthrottled => (),
+            Throttle::Audio(ref mut audio) => audio.wait_for_frame(),
+            Throttle::WallClock => {
+                let now = Instant::now();
+                let deadline = self.deadline.unwrap_or(now) + self.region.frame_duration();
+
+                if deadline > now {
+                    thread::sleep(deadline - now);
+                    self.deadline = Some(deadline);
+                } else {
+                    // too far behind to catch up, start counting from now instead of rushing
+                    self.deadline = Some(now);
+                }
+            }
+        }
+    }
+}
+
+/// runs one instruction and clocks the rest of the system along with it
+pub fn step(cpu: &mut CPU) -> u64 {
+    let start = cpu.bus.cycles();
+    let cycles = cpu.step();
+
+    // a cycle stepped CPU already clocked the bus, this catches up everything else
+    let ticked = cpu.bus.cycles() - start;
+    for _ in ticked..cycles {
+        cpu.bus.tick();
+    }
+    cycles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::BusDevice;
+    use crate::cpu::Timing;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    /// counts its ticks
+    struct Counter {
+        ticks: Arc<AtomicU64>,
+    }
+
+    impl BusDevice for Counter {
+        fn read(&mut self, _addr: u16) -> u8 {
+            0
+        }
+
+        fn write(&mut self, _addr: u16, _val: u8) {}
+
+        fn tick(&mut self) {
+            self.ticks.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// a CPU running an endless loop of NOPs out of RAM, with counters on the PPU and APU
+    fn counted_cpu(timing: Timing) -> (CPU, Arc<AtomicU64>, Arc<AtomicU64>) {
+        let dots = Arc::new(AtomicU64::new(0));
+        let apu_cycles = Arc::new(AtomicU64::new(0));
+        let mut cpu = CPU::default();
+        cpu.set_timing(timing);
+        cpu.bus.attach_ppu(Box::new(Counter {
+            ticks: dots.clone(),
+        }));
+        cpu.bus.attach_apu_io(Box::new(Counter {
+            ticks: apu_cycles.clone(),
+        }));
+        // RAM is all BRKs with the vector at $0000, so fill it with NOPs instead
+        for addr in 0..0x0800 {
+            cpu.bus.write(addr, 0xea);
+        }
+        (cpu, dots, apu_cycles)
+    }
+
+    #[test]
+    fn ntsc_ratios() {
+        for timing in [Timing::Instruction, Timing::Cycle] {
+            let (mut cpu, dots, apu_cycles) = counted_cpu(timing);
+            let mut scheduler = Scheduler::new(Region::Ntsc, Throttle::Unthrottled);
+            scheduler.run_frame(&mut cpu);
+
+            // instructions don't line up with the end of the frame, so it may overshoot a little
+            let master = cpu.bus.master_clock();
+            assert!((357_366..357_366 + 7 * 12).contains(&master));
+            assert!(cpu.bus.cycles() == master / 12);
+            assert!(apu_cycles.load(Ordering::Relaxed) == master / 12);
+            assert!(dots.load(Ordering::Relaxed) == master / 4);
+            assert!(scheduler.frames() == 1);
+        }
+    }
+
+    #[test]
+    fn pal_ratios() {
+        let (mut cpu, dots, apu_cycles) = counted_cpu(Timing::Instruction);
+        let mut scheduler = Scheduler::new(Region::Pal, Throttle::Unthrottled);
+        scheduler.run_frame(&mut cpu);
+        scheduler.run_frame(&mut cpu);
+
+        let master = cpu.bus.master_clock();
+        assert!((2 * 531_960..2 * 531_960 + 7 * 16).contains(&master));
+        assert!(apu_cycles.load(Ordering::Relaxed) == master / 16);
+        // 3.2 dots per CPU cycle
+        assert!(dots.load(Ordering::Relaxed) == master / 5);
+        assert!(scheduler.frames() == 2);
+    }
+
+    #[test]
+    fn overshoot_is_taken_from_the_next_frame() {
+        let (mut cpu, _, _) = counted_cpu(Timing::Instruction);
+        let mut scheduler = Scheduler::new(Region::Ntsc, Throttle::Unthrottled);
+        for _ in 0..10 {
+            scheduler.run_frame(&mut cpu);
+        }
+        let master = cpu.bus.master_clock();
+        assert!((10 * 357_366..10 * 357_366 + 7 * 12).contains(&master));
+    }
+
+    #[test]
+    fn wall_clock_throttle() {
+        let (mut cpu, _, _) = counted_cpu(Timing::Instruction);
+        let mut scheduler = Scheduler::new(Region::Pal, Throttle::WallClock);
+        let start = Instant::now();
+        for _ in 0..3 {
+            scheduler.run_frame(&mut cpu);
+        }
+        // the first frame already waits its full length
+        assert!(start.elapsed() >= Region::Pal.frame_duration() * 3);
+    }
+
+    #[test]
+    fn frame_rates() {
+        let ntsc = 1.0 / Region::Ntsc.frame_duration().as_secs_f64();
+        let pal = 1.0 / Region::Pal.frame_duration().as_secs_f64();
+        assert!((ntsc - 60.0988).abs() < 0.001);
+        assert!((pal - 50.0070).abs() < 0.001);
+    }
+}
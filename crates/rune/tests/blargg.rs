@@ -0,0 +1,140 @@
+//! Runs test ROMs that report through blargg's $6000 protocol
+//!
+//! the ROMs themselves aren't redistributable, drop them anywhere under tests/roms/blargg
+//! and run `cargo test -- --ignored` for `blargg_roms` to pick them up
+
+use rune::asm;
+use rune::blargg::{self, Error, SIGNATURE};
+use rune::cartridge::Cartridge;
+use rune::cpu::CPU;
+use rune::ppu::Ppu;
+use rune_ines::InesFile;
+use std::path::{Path, PathBuf};
+
+const MAX_FRAMES: u64 = 3600;
+
+fn run(rom: &[u8], max_frames: u64) -> Result<blargg::Outcome, Error> {
+    let cartridge = Cartridge::from_ines(InesFile::from_bytes(rom)).unwrap();
+    let mut cpu = CPU::default();
+    cpu.bus.attach_ppu(Box::new(Ppu::new(cartridge.region())));
+    cpu.bus.attach_cartridge(cartridge);
+    cpu.power_on();
+    blargg::run(&mut cpu, max_frames)
+}
+
+/// a test ROM that reports `code` and `text`, asking to be reset first if `reset` is set
+fn reporter(code: u8, text: &str, reset: bool) -> Vec<u8> {
+    let reset_request = if reset {
+        "
+            lda $6000
+            cmp #$81
+            beq copy_text
+            lda #$81
+            jsr report
+hang:       jmp hang"
+    } else {
+        ""
+    };
+    let [sig0, sig1, sig2] = SIGNATURE;
+
+    asm::assemble_ines(&format!(
+        "
+            .org $c000
+start:      {reset_request}
+copy_text:  ldx #0
+copy:       lda text,x
+            sta $6004,x
+            inx
+            cmp #0
+            bne copy
+            lda #{code}
+            jsr report
+done:       jmp done
+
+; status $80 goes in before the signature so a stale status is never valid
+report:     pha
+            lda #$80
+            sta $6000
+            lda #{sig0}
+            sta $6001
+            lda #{sig1}
+            sta $6002
+            lda #{sig2}
+            sta $6003
+            pla
+            sta $6000
+            rts
+
+text:       .byte \"{}\", 0
+
+            .org $fffa
+            .word start, start, start
+",
+        text.replace('\n', "\\n")
+    ))
+    .unwrap()
+}
+
+#[test]
+fn passing_rom() {
+    let outcome = run(&reporter(0, "Passed\n", false), MAX_FRAMES).unwrap();
+    assert!(outcome.passed());
+    assert!(outcome.text == "Passed\n");
+}
+
+#[test]
+fn failing_rom() {
+    let outcome = run(&reporter(3, "Failed #3\n", false), MAX_FRAMES).unwrap();
+    assert!(!outcome.passed());
+    assert!(outcome.code == 3);
+    assert!(outcome.text == "Failed #3\n");
+}
+
+#[test]
+fn reset_request() {
+    let outcome = run(&reporter(0, "Passed after reset\n", true), MAX_FRAMES).unwrap();
+    assert!(outcome.passed());
+    assert!(outcome.text == "Passed after reset\n");
+}
+
+#[test]
+fn timeout() {
+    let spin = asm::assemble_ines(".org $8000\nspin: jmp spin\n.org $fffc\n.word spin").unwrap();
+    assert!(run(&spin, 10) == Err(Error::Timeout(10)));
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path.extension().is_some_and(|ext| ext == "nes") {
+            roms.push(path);
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs the blargg ROMs under tests/roms/blargg"]
+fn blargg_roms() {
+    let mut roms = vec![];
+    find_roms(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms/blargg"),
+        &mut roms,
+    );
+    roms.sort();
+    assert!(!roms.is_empty(), "no ROMs found under tests/roms/blargg");
+
+    let mut failures = vec![];
+    for path in roms {
+        let rom = std::fs::read(&path).unwrap();
+        match run(&rom, MAX_FRAMES) {
+            Ok(outcome) if outcome.passed() => (),
+            Ok(outcome) => failures.push(format!("{}: {}", path.display(), outcome.text.trim())),
+            Err(err) => failures.push(format!("{}: {err}", path.display())),
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
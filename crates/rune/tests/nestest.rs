@@ -0,0 +1,276 @@
+//! Runs nestest style ROMs in automation mode and checks them against golden traces
+//!
+//! a ROM starts at $C000, reports failures through $02 (official opcodes) and $03
+//! (unofficial opcodes) and returns with an RTS once it's done.
+//! The golden traces are nestest's published log, never output of this emulator.
+
+use rune::asm;
+use rune::cartridge::Cartridge;
+use rune::cpu::{Timing, CPU};
+use rune_ines::InesFile;
+use std::path::Path;
+
+/// the longest a test ROM may run before it's considered stuck
+const MAX_INSTRUCTIONS: usize = 100_000;
+
+/// runs the ROM from $C000 and returns its trace along with the status bytes
+///
+/// stops once PC leaves the cartridge, the CPU jams or the trace is `limit` lines long
+fn run(rom: &[u8], limit: usize) -> (Vec<String>, u8, u8) {
+    let mut cpu = CPU::default();
+    cpu.bus
+        .attach_cartridge(Cartridge::from_ines(InesFile::from_bytes(rom)).unwrap());
+    // the PPU column is only right when the bus is clocked as the CPU goes
+    cpu.set_timing(Timing::Cycle);
+    cpu.power_on();
+    cpu.set_pc(0xc000);
+
+    let mut trace = Vec::new();
+    while cpu.pc() >= 0x8000 && !cpu.halted() && trace.len() < limit {
+        trace.push(cpu.trace_line());
+        cpu.step();
+    }
+    (trace, cpu.bus.peek(0x02), cpu.bus.peek(0x03))
+}
+
+/// panics on the first line that differs from the golden log
+fn compare(trace: &[String], golden: &str) {
+    let golden: Vec<&str> = golden.lines().collect();
+    for (i, (line, expected)) in trace.iter().zip(&golden).enumerate() {
+        assert!(
+            line == expected,
+            "trace diverges on line {}\nexpected: {expected}\n     got: {line}",
+            i + 1
+        );
+    }
+    assert!(
+        trace.len() == golden.len(),
+        "trace is {} lines long, the golden log has {}",
+        trace.len(),
+        golden.len()
+    );
+}
+
+/// a nestest style ROM that checks its own results, the status bytes are what's compared
+const CPU_BASICS: &str = "
+            .org $c000
+start:      ldx #0
+            stx $02
+            stx $03
+
+            ; loads and stores
+            lda #$55
+            sta $10
+            ldx $10
+            cpx #$55
+            bne fail_01
+
+            ; the stack
+            pha
+            lda #0
+            jsr inc_x
+            pla
+            cmp #$55
+            bne fail_02
+            cpx #$56
+            bne fail_02
+
+            ; indirect addressing through $20 = $0300
+            lda #$00
+            sta $20
+            lda #$03
+            sta $21
+            ldy #3
+            lda #$aa
+            sta ($20),y
+            ldx #$10
+            lda ($10,x)
+            bne fail_03
+            lda $0300,y
+            cmp #$aa
+            bne fail_03
+
+            ; shifts and a plain addition
+            sec
+            lda #$80
+            rol a
+            cmp #$01
+            bne fail_04
+            lsr a
+            bne fail_04
+            bcc fail_04
+            clc
+            lda #$10
+            adc #$20
+            cmp #$30
+            bne fail_04
+            jmp mirrors
+
+; in the middle so every test can branch to them
+fail_01:    ldx #1
+            jmp fail
+fail_02:    ldx #2
+            jmp fail
+fail_03:    ldx #3
+            jmp fail
+fail_04:    ldx #4
+            jmp fail
+fail_05:    ldx #5
+            jmp fail
+fail_06:    ldx #6
+            jmp fail
+fail:       stx $02
+            rts
+
+            ; RAM is mirrored every 2 KiB and the board has PRG RAM at $6000
+mirrors:    lda #$5a
+            sta $0012
+            lda $0812
+            cmp #$5a
+            bne fail_05
+            lda $1812
+            cmp #$5a
+            bne fail_05
+            lda #$a5
+            sta $6000
+            lda $6000
+            cmp #$a5
+            bne fail_05
+
+            ; ADC and SBC carry and overflow
+            clc
+            lda #$7f
+            adc #$01
+            bvc fail_06
+            bcs fail_06
+            cmp #$80
+            bne fail_06
+            clc
+            lda #$ff
+            adc #$01
+            bcc fail_06
+            bne fail_06
+            sec
+            lda #$00
+            sbc #$01
+            bcs fail_06
+            bvs fail_06
+            cmp #$ff
+            bne fail_06
+            sec
+            lda #$80
+            sbc #$01
+            bvc fail_06
+            bcc fail_06
+            cmp #$7f
+            bne fail_06
+            ; the NES has no decimal mode, D is kept but ignored
+            sed
+            clc
+            lda #$09
+            adc #$01
+            cld
+            cmp #$0a
+            bne fail_06
+
+            ; JMP ($04FF) takes its high byte from $0400
+            lda #<jmp_ok
+            sta $04ff
+            lda #>jmp_ok
+            sta $0400
+            jmp ($04ff)
+
+jmp_ok:     lda #$33
+            sta $30
+            lax $30
+            cpx #$33
+            bne ufail_01
+            lda #$f0
+            ldx #$3c
+            sax $31
+            lda $31
+            cmp #$30
+            bne ufail_01
+            lda #$40
+            sta $32
+            lda #$3f
+            dcp $32
+            bne ufail_02
+            nop $32
+            nop #$ff
+            rts
+
+inc_x:      inx
+            rts
+
+ufail_01:   ldx #1
+            jmp ufail
+ufail_02:   ldx #2
+            jmp ufail
+ufail:      stx $03
+            rts
+
+            .org $fffa
+            .word start, start, start
+";
+
+#[test]
+fn cpu_basics() {
+    let (trace, official, unofficial) =
+        run(&asm::assemble_ines(CPU_BASICS).unwrap(), MAX_INSTRUCTIONS);
+    assert!(official == 0, "official opcode test {official:#04x} failed");
+    assert!(
+        unofficial == 0,
+        "unofficial opcode test {unofficial:#04x} failed"
+    );
+    // it ran to its final RTS rather than being cut off
+    assert!(trace.len() < MAX_INSTRUCTIONS);
+}
+
+/// the bytes nestest.nes runs through in the first lines of its published log
+const NESTEST_OPENING: &str = "
+            .org $c000
+            jmp $c5f5
+            .org $c5f5
+            ldx #$00
+            stx $00
+            stx $10
+            stx $11
+            jsr $c72d
+            .org $c72d
+            nop
+            sec
+            bcs $c735
+            .org $c735
+            nop
+            .org $fffa
+            .word $c000, $c000, $c000
+";
+
+#[test]
+fn nestest_opening() {
+    let golden = include_str!("nestest/nestest_opening.log");
+    let (trace, _, _) = run(
+        &asm::assemble_ines(NESTEST_OPENING).unwrap(),
+        golden.lines().count(),
+    );
+    compare(&trace, golden);
+}
+
+/// nestest.nes and nestest.log can't be redistributed, drop them in tests/roms to run this
+#[test]
+#[ignore = "needs tests/roms/nestest.nes and tests/roms/nestest.log"]
+fn nestest() {
+    let roms = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms");
+    let rom = std::fs::read(roms.join("nestest.nes")).expect("tests/roms/nestest.nes is missing");
+    let golden = std::fs::read_to_string(roms.join("nestest.log"))
+        .expect("tests/roms/nestest.log is missing");
+
+    let (trace, official, unofficial) = run(&rom, golden.lines().count());
+    compare(&trace, &golden);
+    assert!(official == 0, "official opcode test {official:#04x} failed");
+    assert!(
+        unofficial == 0,
+        "unofficial opcode test {unofficial:#04x} failed"
+    );
+}
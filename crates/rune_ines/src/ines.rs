@@ -152,7 +152,11 @@ pub struct InesFile {
 impl InesFile {
     /// returns an InesFile loaded with contents of file
     pub fn open(filename: &str) -> InesFile {
-        let file = std::fs::read(filename).unwrap();
+        InesFile::from_bytes(&std::fs::read(filename).unwrap())
+    }
+
+    /// parses an iNES image that is already in memory
+    pub fn from_bytes(file: &[u8]) -> InesFile {
         let header = InesHeader::parse(&file[0..16]).unwrap();
 
         let mut curr = 16;
@@ -275,27 +279,15 @@ mod tests {
     fn flags10() {
         let mut header: InesHeader = unsafe { std::mem::zeroed() };
         header.flags10 = 0;
-        assert!(match header.get_tv_system() {
-            TVSystem::NTSC => true,
-            _ => false,
-        });
+        assert!(matches!(header.get_tv_system(), TVSystem::NTSC));
 
         header.flags10 = 1;
-        assert!(match header.get_tv_system() {
-            TVSystem::DUAL => true,
-            _ => false,
-        });
+        assert!(matches!(header.get_tv_system(), TVSystem::DUAL));
         header.flags10 = 3;
-        assert!(match header.get_tv_system() {
-            TVSystem::DUAL => true,
-            _ => false,
-        });
+        assert!(matches!(header.get_tv_system(), TVSystem::DUAL));
 
         header.flags10 = 2;
-        assert!(match header.get_tv_system() {
-            TVSystem::PAL => true,
-            _ => false,
-        });
+        assert!(matches!(header.get_tv_system(), TVSystem::PAL));
 
         header.flags10 = 0b0001_0000;
         assert!(header.has_prg_ram());